@@ -1,70 +1,424 @@
-//! AST (abstract syntax tree) data structure.
-
-use crate::lexer::Token;
-
-/// Represents an expression node in the AST.
-#[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
-    /// Number literal (represented using floating point `f64`).
-    NumberLit(f64),
-    /// Boolean literal.
-    BoolLit(bool),
-    /// String literal.
-    StringLit(String),
-    /// An identifier (e.g. `foo`).
-    Identifier(String),
-    /// A function call (e.g. `foo(1, bar, baz())`).
-    FnCall {
-        callee: Box<Expr>,
-        args: Vec<Expr>,
-    },
-    /// A binary expression (e.g. `1+1`).
-    Binary {
-        lhs: Box<Expr>,
-        op: Token,
-        rhs: Box<Expr>,
-    },
-    /// An unary expression (e.g. `-1`).
-    Unary {
-        op: Token,
-        arg: Box<Expr>,
-    },
-    /// Error token. Used for error recovery.
-    Error,
-}
-
-/// Represents a statement node in the AST.
-#[derive(Debug, Clone, PartialEq)]
-pub enum Stmt {
-    /// Variable declaration.
-    LetDeclaration {
-        ident: String,
-        initializer: Expr,
-    },
-    /// Function declaration.
-    FnDeclaration {
-        ident: String,
-        params: Vec<String>,
-        body: Vec<Stmt>,
-    },
-    /// Block statement.
-    Block(Vec<Stmt>),
-    /// If/else statement.
-    IfElseStmt {
-        condition: Expr,
-        if_block: Vec<Stmt>,
-        /// If `else` clause is not present, this field should be `None`.
-        else_block: Option<Vec<Stmt>>,
-    },
-    /// While statement.
-    WhileStmt {
-        condition: Expr,
-        body: Vec<Stmt>,
-    },
-    /// Expression statement (expression with side effect).
-    ExprStmt(Expr),
-    /// Return statement.
-    ReturnStmt(Expr),
-    /// Error token. Used for error recovery/
-    Error,
-}
+//! AST (abstract syntax tree) data structure.
+
+use crate::lexer::Token;
+use std::fmt;
+use std::ops::Range;
+
+/// Represents an expression node in the AST.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Number literal (represented using floating point `f64`).
+    NumberLit(f64),
+    /// Boolean literal.
+    BoolLit(bool),
+    /// String literal.
+    StringLit(String),
+    /// Array literal (e.g. `[1, 2, 3]`).
+    /// Tuple literals (e.g. `(1, 2)`) are also represented as arrays.
+    ArrayLit(Vec<Expr>),
+    /// An identifier (e.g. `foo`).
+    Identifier(String),
+    /// A function call (e.g. `foo(1, bar, baz())`).
+    FnCall {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+        /// The span of the call, from the start of the callee to the closing `)`.
+        /// For the `println()` call added by [`crate::parser::Parser::parse_repl_input`], the span of the printed
+        /// statement.
+        span: Range<usize>,
+    },
+    /// A binary expression (e.g. `1+1`).
+    Binary {
+        lhs: Box<Expr>,
+        op: Token,
+        rhs: Box<Expr>,
+    },
+    /// An unary expression (e.g. `-1`).
+    Unary {
+        op: Token,
+        arg: Box<Expr>,
+    },
+    /// A block expression (e.g. `{ let a = 1; a + 1 }`).
+    Block {
+        body: Vec<Stmt>,
+        /// The trailing expression (without a semicolon) that is the value of the block.
+        /// If not present, the block evaluates to `nil`.
+        value: Option<Box<Expr>>,
+    },
+    /// A function expression (e.g. `fn(x) { return x + 1; }`).
+    /// The function is represented as a [`Stmt::FnDeclaration`] with the identifier `<anonymous>`.
+    FnLit(Box<Stmt>),
+    /// Error token. Used for error recovery.
+    Error,
+}
+
+impl Expr {
+    /// Returns `true` if both expressions have the same structure.
+    /// Unlike `PartialEq`, this ignores source information that does not affect the meaning of the program (such as
+    /// spans) and compares number literals by their bit pattern (so that `NaN` literals are equal).
+    /// Use this in tests that only care about the shape of the AST.
+    pub fn structurally_eq(&self, other: &Expr) -> bool {
+        match (self, other) {
+            (Expr::NumberLit(a), Expr::NumberLit(b)) => a.to_bits() == b.to_bits(),
+            (Expr::BoolLit(a), Expr::BoolLit(b)) => a == b,
+            (Expr::StringLit(a), Expr::StringLit(b)) => a == b,
+            (Expr::ArrayLit(a), Expr::ArrayLit(b)) => exprs_structurally_eq(a, b),
+            (Expr::Identifier(a), Expr::Identifier(b)) => a == b,
+            (
+                Expr::FnCall { callee, args, .. },
+                Expr::FnCall {
+                    callee: other_callee,
+                    args: other_args,
+                    ..
+                },
+            ) => callee.structurally_eq(other_callee) && exprs_structurally_eq(args, other_args),
+            (
+                Expr::Binary { lhs, op, rhs },
+                Expr::Binary {
+                    lhs: other_lhs,
+                    op: other_op,
+                    rhs: other_rhs,
+                },
+            ) => op == other_op && lhs.structurally_eq(other_lhs) && rhs.structurally_eq(other_rhs),
+            (
+                Expr::Unary { op, arg },
+                Expr::Unary {
+                    op: other_op,
+                    arg: other_arg,
+                },
+            ) => op == other_op && arg.structurally_eq(other_arg),
+            (
+                Expr::Block { body, value },
+                Expr::Block {
+                    body: other_body,
+                    value: other_value,
+                },
+            ) => {
+                stmts_structurally_eq(body, other_body)
+                    && match (value, other_value) {
+                        (Some(a), Some(b)) => a.structurally_eq(b),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            (Expr::FnLit(a), Expr::FnLit(b)) => a.structurally_eq(b),
+            (Expr::Error, Expr::Error) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Formats the expression as source code (e.g. `x > 0`). Used for error messages such as failed assertions.
+/// Nested binary expressions are always parenthesized and the statements of block expressions are omitted.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Formats an operand of an operator, adding parentheses around binary expressions.
+        struct Operand<'a>(&'a Expr);
+        impl fmt::Display for Operand<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self.0 {
+                    Expr::Binary { .. } => write!(f, "({})", self.0),
+                    expr => write!(f, "{}", expr),
+                }
+            }
+        }
+
+        /// Formats a comma separated list of expressions.
+        fn write_list(f: &mut fmt::Formatter<'_>, exprs: &[Expr]) -> fmt::Result {
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", expr)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Expr::NumberLit(val) => write!(f, "{}", val),
+            Expr::BoolLit(val) => write!(f, "{}", val),
+            Expr::StringLit(val) => write!(f, "{:?}", val),
+            Expr::ArrayLit(elements) => {
+                write!(f, "[")?;
+                write_list(f, elements)?;
+                write!(f, "]")
+            }
+            Expr::Identifier(ident) => write!(f, "{}", ident),
+            Expr::FnCall { callee, args, .. } => {
+                write!(f, "{}(", Operand(callee))?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Expr::Binary { lhs, op, rhs } => {
+                write!(f, "{} {} {}", Operand(lhs), operator_str(op), Operand(rhs))
+            }
+            Expr::Unary { op, arg } => write!(f, "{}{}", operator_str(op), Operand(arg)),
+            Expr::Block {
+                value: Some(value), ..
+            } => write!(f, "{{ ... {} }}", value),
+            Expr::Block { value: None, .. } => write!(f, "{{ ... }}"),
+            Expr::FnLit(func) => match func.as_ref() {
+                Stmt::FnDeclaration { params, .. } => {
+                    write!(f, "fn({}) {{ ... }}", params.join(", "))
+                }
+                _ => write!(f, "fn"),
+            },
+            Expr::Error => write!(f, "<error>"),
+        }
+    }
+}
+
+/// Returns the source text of the operator `op`.
+fn operator_str(op: &Token) -> &'static str {
+    match op {
+        Token::LogicalNot => "!",
+        Token::LogicalAnd => "&&",
+        Token::LogicalOr => "||",
+        Token::Plus => "+",
+        Token::Minus => "-",
+        Token::Asterisk => "*",
+        Token::Slash => "/",
+        Token::TildeSlash => "~/",
+        Token::Equals => "=",
+        Token::PlusEquals => "+=",
+        Token::MinusEquals => "-=",
+        Token::AsteriskEquals => "*=",
+        Token::SlashEquals => "/=",
+        Token::EqualsEquals => "==",
+        Token::NotEquals => "!=",
+        Token::GreaterThan => ">",
+        Token::GreaterThanEquals => ">=",
+        Token::LessThan => "<",
+        Token::LessThanEquals => "<=",
+        _ => "?",
+    }
+}
+
+/// Represents a statement node in the AST.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// Variable declaration.
+    LetDeclaration {
+        ident: String,
+        initializer: Expr,
+        /// The comment immediately preceding the declaration. See [`crate::parser::Parser::set_doc_comments`].
+        doc: Option<String>,
+        /// The span of the declaration, from `let` to `;`.
+        span: Range<usize>,
+        /// The 1-based source line of the `let` keyword.
+        line: usize,
+    },
+    /// Destructuring variable declaration (e.g. `let (x, y) = f();`).
+    /// The initializer must evaluate to a tuple (array) with exactly one element per identifier.
+    TupleDeclaration {
+        idents: Vec<String>,
+        initializer: Expr,
+        /// The span of the declaration, from `let` to `;`.
+        span: Range<usize>,
+        /// The 1-based source line of the `let` keyword.
+        line: usize,
+    },
+    /// Function declaration.
+    FnDeclaration {
+        ident: String,
+        params: Vec<String>,
+        body: Vec<Stmt>,
+        /// The 1-based source line of the `fn` keyword. `0` for the anonymous top level function.
+        line: usize,
+        /// The comment immediately preceding the declaration. See [`crate::parser::Parser::set_doc_comments`].
+        doc: Option<String>,
+        /// The span of the declaration, from `fn` to `}`. The whole source for the anonymous top level function.
+        span: Range<usize>,
+    },
+    /// Block statement.
+    Block(Vec<Stmt>),
+    /// If/else statement.
+    IfElseStmt {
+        condition: Expr,
+        if_block: Vec<Stmt>,
+        /// If `else` clause is not present, this field should be `None`.
+        else_block: Option<Vec<Stmt>>,
+        /// The 1-based source line of the `if` keyword.
+        line: usize,
+    },
+    /// While statement.
+    WhileStmt {
+        condition: Expr,
+        body: Vec<Stmt>,
+        /// The 1-based source line of the `while` keyword.
+        line: usize,
+    },
+    /// Do-while statement. The body is executed once before the condition is checked for the first time.
+    DoWhileStmt {
+        body: Vec<Stmt>,
+        condition: Expr,
+        /// The 1-based source line of the `do` keyword.
+        line: usize,
+    },
+    /// Expression statement (expression with side effect).
+    ExprStmt {
+        expr: Expr,
+        /// The 1-based source line where the expression starts.
+        line: usize,
+    },
+    /// Empty statement (a lone `;`). Does nothing.
+    EmptyStmt,
+    /// Return statement.
+    /// The trailing expression of a function body (without a semicolon) is also represented as a return statement.
+    ReturnStmt {
+        expr: Expr,
+        /// The 1-based source line of the `return` keyword (or of the trailing expression).
+        line: usize,
+    },
+    /// Break statement. Exits the innermost loop.
+    BreakStmt {
+        /// The span of the statement, from `break` to `;`.
+        span: Range<usize>,
+        /// The 1-based source line of the `break` keyword.
+        line: usize,
+    },
+    /// Continue statement. Skips the rest of the body of the innermost loop and checks the loop condition again.
+    ContinueStmt {
+        /// The span of the statement, from `continue` to `;`.
+        span: Range<usize>,
+        /// The 1-based source line of the `continue` keyword.
+        line: usize,
+    },
+    /// Error token. Used for error recovery/
+    Error,
+}
+
+impl Stmt {
+    /// Returns the 1-based source line where the statement starts or `None` for statements without a line (blocks,
+    /// empty statements and errors). The line of the anonymous top level function is `0`.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::LetDeclaration { line, .. }
+            | Stmt::TupleDeclaration { line, .. }
+            | Stmt::FnDeclaration { line, .. }
+            | Stmt::IfElseStmt { line, .. }
+            | Stmt::WhileStmt { line, .. }
+            | Stmt::DoWhileStmt { line, .. }
+            | Stmt::ExprStmt { line, .. }
+            | Stmt::ReturnStmt { line, .. }
+            | Stmt::BreakStmt { line, .. }
+            | Stmt::ContinueStmt { line, .. } => Some(*line),
+            Stmt::Block(_) | Stmt::EmptyStmt | Stmt::Error => None,
+        }
+    }
+
+    /// Returns `true` if both statements have the same structure. See [`Expr::structurally_eq`].
+    /// Doc comments are ignored.
+    pub fn structurally_eq(&self, other: &Stmt) -> bool {
+        match (self, other) {
+            (
+                Stmt::LetDeclaration {
+                    ident, initializer, ..
+                },
+                Stmt::LetDeclaration {
+                    ident: other_ident,
+                    initializer: other_initializer,
+                    ..
+                },
+            ) => ident == other_ident && initializer.structurally_eq(other_initializer),
+            (
+                Stmt::TupleDeclaration {
+                    idents,
+                    initializer,
+                    ..
+                },
+                Stmt::TupleDeclaration {
+                    idents: other_idents,
+                    initializer: other_initializer,
+                    ..
+                },
+            ) => idents == other_idents && initializer.structurally_eq(other_initializer),
+            (
+                Stmt::FnDeclaration {
+                    ident,
+                    params,
+                    body,
+                    ..
+                },
+                Stmt::FnDeclaration {
+                    ident: other_ident,
+                    params: other_params,
+                    body: other_body,
+                    ..
+                },
+            ) => {
+                ident == other_ident
+                    && params == other_params
+                    && stmts_structurally_eq(body, other_body)
+            }
+            (Stmt::Block(a), Stmt::Block(b)) => stmts_structurally_eq(a, b),
+            (
+                Stmt::IfElseStmt {
+                    condition,
+                    if_block,
+                    else_block,
+                    ..
+                },
+                Stmt::IfElseStmt {
+                    condition: other_condition,
+                    if_block: other_if_block,
+                    else_block: other_else_block,
+                    ..
+                },
+            ) => {
+                condition.structurally_eq(other_condition)
+                    && stmts_structurally_eq(if_block, other_if_block)
+                    && match (else_block, other_else_block) {
+                        (Some(a), Some(b)) => stmts_structurally_eq(a, b),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            (
+                Stmt::WhileStmt {
+                    condition, body, ..
+                },
+                Stmt::WhileStmt {
+                    condition: other_condition,
+                    body: other_body,
+                    ..
+                },
+            ) => {
+                condition.structurally_eq(other_condition)
+                    && stmts_structurally_eq(body, other_body)
+            }
+            (
+                Stmt::DoWhileStmt {
+                    body, condition, ..
+                },
+                Stmt::DoWhileStmt {
+                    body: other_body,
+                    condition: other_condition,
+                    ..
+                },
+            ) => {
+                stmts_structurally_eq(body, other_body)
+                    && condition.structurally_eq(other_condition)
+            }
+            (Stmt::ExprStmt { expr, .. }, Stmt::ExprStmt { expr: other, .. }) => {
+                expr.structurally_eq(other)
+            }
+            (Stmt::EmptyStmt, Stmt::EmptyStmt) => true,
+            (Stmt::ReturnStmt { expr, .. }, Stmt::ReturnStmt { expr: other, .. }) => {
+                expr.structurally_eq(other)
+            }
+            (Stmt::BreakStmt { .. }, Stmt::BreakStmt { .. }) => true,
+            (Stmt::ContinueStmt { .. }, Stmt::ContinueStmt { .. }) => true,
+            (Stmt::Error, Stmt::Error) => true,
+            _ => false,
+        }
+    }
+}
+
+fn exprs_structurally_eq(a: &[Expr], b: &[Expr]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.structurally_eq(b))
+}
+
+fn stmts_structurally_eq(a: &[Stmt], b: &[Stmt]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.structurally_eq(b))
+}
@@ -1,132 +1,411 @@
-//! Source code lexing (aka scanning, tokenizing).
-
-use logos::Logos;
-
-/// Represents a source code `Token`.
-#[derive(Debug, Logos, Clone, PartialEq)]
-pub enum Token {
-    // literals
-    #[regex(r"[0-9.]+", |lex| lex.slice().parse())]
-    NumberLit(f64),
-    #[regex(r"true|false", |lex| if lex.slice() == "true" { true } else { false } )]
-    BoolLit(bool),
-    #[regex(r#""[^"]*""#, |lex| lex.slice()[1..lex.slice().len() - 1].to_string())]
-    StringLit(String),
-
-    // identifiers
-    #[regex("[a-zA-Z_]+", |lex| lex.slice().to_string())]
-    Identifier(String),
-
-    // unary operators
-    #[token("!")]
-    LogicalNot,
-
-    // binary operators
-    // - arithmetics
-    #[token("+")]
-    Plus,
-    #[token("-")]
-    Minus, // NOTE: can also be unary
-    #[token("*")]
-    Asterisk,
-    #[token("/")]
-    Slash,
-    // - assignment
-    #[token("=")]
-    Equals,
-    #[token("+=")]
-    PlusEquals,
-    #[token("-=")]
-    MinusEquals,
-    #[token("*=")]
-    AsteriskEquals,
-    #[token("/=")]
-    SlashEquals,
-    // - equality
-    #[token("==")]
-    EqualsEquals,
-    #[token("!=")]
-    NotEquals,
-    // - ordering
-    #[token(">")]
-    GreaterThan,
-    #[token(">=")]
-    GreaterThanEquals,
-    #[token("<")]
-    LessThan,
-    #[token("<=")]
-    LessThanEquals,
-
-    // punctuation
-    #[token("(")]
-    OpenParen,
-    #[token(")")]
-    CloseParen,
-    #[token("{")]
-    OpenBrace,
-    #[token("}")]
-    CloseBrace,
-    #[token(",")]
-    Comma,
-    #[token(";")]
-    Semi,
-
-    // keywords
-    #[token("fn")]
-    Fn,
-    #[token("let")]
-    Let,
-    #[token("return")]
-    Return,
-    #[token("if")]
-    If,
-    #[token("else")]
-    Else,
-    #[token("while")]
-    While,
-
-    // misc
-    #[regex(r"[ \t\n\r\f]+", logos::skip)]
-    #[regex(r"//[^\n]*", logos::skip)] // single line comments
-    #[error]
-    Error,
-
-    /// Only generated in parse phase when `lexer.next()` returns `None`.
-    Eof,
-}
-
-impl Token {
-    /// Returns the binary binding power or `None` if invalid binop token.
-    /// Binding power `0` and `1` is reserved for accepting any expression.
-    /// Assignment ([`Token::Equals`]) has the lowest precedence with `(3, 2)`.
-    pub fn binop_bp(&self) -> Option<(u8, u8)> {
-        match self {
-            /* Additive */
-            Token::Plus | Token::Minus => Some((8, 9)),
-            /* Multiplicative */
-            Token::Asterisk | Token::Slash => Some((10, 11)),
-            /* Assignment */
-            Token::Equals
-            | Token::PlusEquals
-            | Token::MinusEquals
-            | Token::AsteriskEquals
-            | Token::SlashEquals => Some((3, 2)),
-            /* Equality */
-            Token::EqualsEquals | Token::NotEquals => Some((4, 5)),
-            Token::GreaterThan
-            | Token::GreaterThanEquals
-            | Token::LessThan
-            | Token::LessThanEquals => Some((6, 7)),
-            _ => None,
-        }
-    }
-
-    /// Returns the postfix binding power or `None` if invalid binop token.
-    /// Binding power `0` and `1` is reserved for accepting any expression.
-    pub fn postfix_bp(&self) -> Option<(u8, ())> {
-        match self {
-            Token::OpenParen => Some((12, ())),
-            _ => None,
-        }
-    }
-}
+//! Source code lexing (aka scanning, tokenizing).
+
+use logos::{Lexer, Logos};
+use std::ops::Range;
+
+/// Represents a source code `Token`.
+#[derive(Debug, Logos, Clone, PartialEq)]
+pub enum Token {
+    // literals
+    #[regex(r"[0-9.]+([eE][+-]?[0-9]+)?", |lex| parse_number_lit(lex.slice()))]
+    #[regex(r"0[xX][0-9a-fA-F]+", |lex| parse_number_lit(lex.slice()))]
+    NumberLit(f64),
+    #[regex(r"true|false", |lex| lex.slice() == "true")]
+    BoolLit(bool),
+    /// A string literal with its escape sequences processed. See [`parse_string_lit`].
+    /// A string literal with an invalid escape sequence produces a [`Token::Error`].
+    #[regex(r#""([^"\\]|\\.)*""#, |lex| parse_string_lit(&lex.slice()[1..lex.slice().len() - 1]))]
+    StringLit(String),
+    /// A string literal without a closing quote. Spans from the opening quote to the end of the input.
+    #[regex(r#""([^"\\]|\\.)*\\?"#)]
+    UnterminatedStringLit,
+
+    // identifiers
+    #[regex("[a-zA-Z_]+", |lex| lex.slice().to_string())]
+    Identifier(String),
+
+    // unary operators
+    #[token("!")]
+    LogicalNot,
+
+    // logical operators
+    #[token("&&")]
+    LogicalAnd,
+    #[token("||")]
+    LogicalOr,
+
+    // binary operators
+    // - arithmetics
+    #[token("+")]
+    Plus,
+    #[token("-")]
+    Minus, // NOTE: can also be unary
+    #[token("*")]
+    Asterisk,
+    #[token("/")]
+    Slash,
+    /// Floor division. `//` is not used because it starts a [`Token::Comment`].
+    #[token("~/")]
+    TildeSlash,
+    // - assignment
+    #[token("=")]
+    Equals,
+    #[token("+=")]
+    PlusEquals,
+    #[token("-=")]
+    MinusEquals,
+    #[token("*=")]
+    AsteriskEquals,
+    #[token("/=")]
+    SlashEquals,
+    // - equality
+    #[token("==")]
+    EqualsEquals,
+    #[token("!=")]
+    NotEquals,
+    // - ordering
+    #[token(">")]
+    GreaterThan,
+    #[token(">=")]
+    GreaterThanEquals,
+    #[token("<")]
+    LessThan,
+    #[token("<=")]
+    LessThanEquals,
+
+    // punctuation
+    #[token("(")]
+    OpenParen,
+    #[token(")")]
+    CloseParen,
+    #[token("[")]
+    OpenBracket,
+    #[token("]")]
+    CloseBracket,
+    #[token("{")]
+    OpenBrace,
+    #[token("}")]
+    CloseBrace,
+    #[token(",")]
+    Comma,
+    #[token(";")]
+    Semi,
+
+    // keywords
+    #[token("fn")]
+    Fn,
+    #[token("let")]
+    Let,
+    #[token("return")]
+    Return,
+    #[token("if")]
+    If,
+    #[token("else")]
+    Else,
+    #[token("while")]
+    While,
+    #[token("do")]
+    Do,
+    #[token("break")]
+    Break,
+    #[token("continue")]
+    Continue,
+
+    // misc
+    /// A single line comment. Contains the text after the `//`.
+    /// Comments are skipped by the parser (see [`crate::parser::Parser::set_doc_comments`]).
+    #[regex(r"//[^\n]*", |lex| lex.slice()[2..].to_string())]
+    Comment(String),
+    #[regex(r"[ \t\n\r\f]+", logos::skip)]
+    #[error]
+    Error,
+
+    /// Only generated in parse phase when `lexer.next()` returns `None`.
+    Eof,
+}
+
+/// Lexes the `source` into a [`Vec`] of [`Token`]s with their spans (byte ranges in `source`) without parsing.
+/// Invalid input produces [`Token::Error`] tokens. The returned tokens do not include [`Token::Eof`].
+/// This is useful for tooling such as syntax highlighting.
+///
+/// # Example
+/// ```
+/// use ella_parser::lexer::{lex, Token};
+///
+/// assert_eq!(
+///     lex("let x = 1;"),
+///     vec![
+///         (Token::Let, 0..3),
+///         (Token::Identifier("x".to_string()), 4..5),
+///         (Token::Equals, 6..7),
+///         (Token::NumberLit(1.0), 8..9),
+///         (Token::Semi, 9..10),
+///     ]
+/// );
+/// ```
+pub fn lex(source: &str) -> Vec<(Token, Range<usize>)> {
+    Token::lexer(source).spanned().collect()
+}
+
+/// A lexical error: a slice of the source code that is not a valid token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    /// The invalid source code.
+    pub text: String,
+    /// The byte range of `text` in the source code.
+    pub span: Range<usize>,
+}
+
+/// Iterator over the tokens of a source code. See [`tokens`].
+pub struct Tokens<'a> {
+    lexer: Lexer<'a, Token>,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<(Token, Range<usize>), LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.lexer.next()?;
+        let span = self.lexer.span();
+        Some(match token {
+            Token::Error => Err(LexError {
+                text: self.lexer.slice().to_string(),
+                span,
+            }),
+            token => Ok((token, span)),
+        })
+    }
+}
+
+/// Lexes the `source` lazily. Unlike [`lex`], invalid input is yielded as a [`LexError`] with the offending text, in
+/// order with the valid tokens. This is useful for tooling that marks errors (e.g. syntax highlighting).
+///
+/// # Example
+/// ```
+/// use ella_parser::lexer::{tokens, LexError, Token};
+///
+/// assert_eq!(
+///     tokens("let x = @;").collect::<Vec<_>>(),
+///     vec![
+///         Ok((Token::Let, 0..3)),
+///         Ok((Token::Identifier("x".to_string()), 4..5)),
+///         Ok((Token::Equals, 6..7)),
+///         Err(LexError {
+///             text: "@".to_string(),
+///             span: 8..9
+///         }),
+///         Ok((Token::Semi, 9..10)),
+///     ]
+/// );
+/// ```
+pub fn tokens(source: &str) -> Tokens<'_> {
+    Tokens {
+        lexer: Token::lexer(source),
+    }
+}
+
+/// Parses a number literal. Accepts decimal (`1.5`), scientific (`1e3`) and hexadecimal (`0xff`) notations.
+/// Returns `None` if `src` is not a valid number literal.
+/// This function is also used for parsing numbers at runtime to stay consistent with the lexer.
+///
+/// # Example
+/// ```
+/// use ella_parser::lexer::parse_number_lit;
+///
+/// assert_eq!(parse_number_lit("1.5"), Some(1.5));
+/// assert_eq!(parse_number_lit("1e3"), Some(1000.0));
+/// assert_eq!(parse_number_lit("0xff"), Some(255.0));
+/// assert_eq!(parse_number_lit("1.2.3"), None);
+/// assert_eq!(parse_number_lit("inf"), None);
+/// ```
+pub fn parse_number_lit(src: &str) -> Option<f64> {
+    if let Some(hex) = src.strip_prefix("0x").or_else(|| src.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+            .ok()
+            .map(|number| number as f64)
+    } else if src.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        src.parse().ok()
+    } else {
+        None // reject special values accepted by `f64::from_str` (e.g. `inf` or `NaN`)
+    }
+}
+
+/// Returns `true` if `src` is an integer number literal (decimal or hexadecimal) that cannot be represented exactly
+/// by its parsed `value` (e.g. integers above 2^53). Literals that are not integers always return `false`.
+///
+/// # Example
+/// ```
+/// use ella_parser::lexer::is_imprecise_integer_lit;
+///
+/// assert!(is_imprecise_integer_lit("9007199254740993", 9007199254740993.0));
+/// assert!(!is_imprecise_integer_lit("9007199254740992", 9007199254740992.0));
+/// assert!(!is_imprecise_integer_lit("0.1", 0.1));
+/// ```
+pub fn is_imprecise_integer_lit(src: &str, value: f64) -> bool {
+    let integer = if let Some(hex) = src.strip_prefix("0x").or_else(|| src.strip_prefix("0X")) {
+        u128::from_str_radix(hex, 16)
+    } else if src.bytes().all(|byte| byte.is_ascii_digit()) {
+        src.parse()
+    } else {
+        return false;
+    };
+    match integer {
+        Ok(integer) => value as u128 != integer,
+        Err(_) => true, // too large to be represented exactly
+    }
+}
+
+/// Processes the escape sequences in the content of a string literal (without the quotes).
+/// Supported escape sequences are `\n`, `\r`, `\t`, `\0`, `\\`, `\"`, `\xNN` (a code point with 2 hex digits) and
+/// `\u{N}` (a Unicode scalar value with 1 to 6 hex digits).
+/// Returns `None` if `src` contains an invalid escape sequence (e.g. a surrogate or a code point above `10FFFF`).
+///
+/// # Example
+/// ```
+/// use ella_parser::lexer::parse_string_lit;
+///
+/// assert_eq!(parse_string_lit(r"a\tb"), Some("a\tb".to_string()));
+/// assert_eq!(parse_string_lit(r"\x41\u{1F600}"), Some("A\u{1F600}".to_string()));
+/// assert_eq!(parse_string_lit(r"\u{D800}"), None);
+/// assert_eq!(parse_string_lit(r"\u{41"), None);
+/// assert_eq!(parse_string_lit(r"\q"), None);
+/// ```
+pub fn parse_string_lit(src: &str) -> Option<String> {
+    let mut result = String::with_capacity(src.len());
+    let mut chars = src.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let escaped = match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            'x' => {
+                let digits: String = chars.by_ref().take(2).collect();
+                if digits.len() != 2 {
+                    return None;
+                }
+                parse_code_point(&digits)?
+            }
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let mut digits = String::new();
+                loop {
+                    match chars.next()? {
+                        '}' => break,
+                        c => digits.push(c),
+                    }
+                }
+                if digits.is_empty() || digits.len() > 6 {
+                    return None;
+                }
+                parse_code_point(&digits)?
+            }
+            _ => return None,
+        };
+        result.push(escaped);
+    }
+    Some(result)
+}
+
+/// Parses hex `digits` into a Unicode scalar value. Returns `None` if invalid.
+fn parse_code_point(digits: &str) -> Option<char> {
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    char::from_u32(u32::from_str_radix(digits, 16).ok()?)
+}
+
+impl Token {
+    /// Returns the binary binding power or `None` if invalid binop token.
+    /// Binding power `0` and `1` is reserved for accepting any expression.
+    /// Assignment ([`Token::Equals`]) has the lowest precedence with `(3, 2)`.
+    pub fn binop_bp(&self) -> Option<(u8, u8)> {
+        match self {
+            /* Additive */
+            Token::Plus | Token::Minus => Some((12, 13)),
+            /* Multiplicative */
+            Token::Asterisk | Token::Slash | Token::TildeSlash => Some((14, 15)),
+            /* Assignment */
+            Token::Equals
+            | Token::PlusEquals
+            | Token::MinusEquals
+            | Token::AsteriskEquals
+            | Token::SlashEquals => Some((3, 2)),
+            /* Logical */
+            Token::LogicalOr => Some((4, 5)),
+            Token::LogicalAnd => Some((6, 7)),
+            /* Equality */
+            Token::EqualsEquals | Token::NotEquals => Some((8, 9)),
+            Token::GreaterThan
+            | Token::GreaterThanEquals
+            | Token::LessThan
+            | Token::LessThanEquals => Some((10, 11)),
+            _ => None,
+        }
+    }
+
+    /// Returns the prefix binding power or `None` if invalid prefix operator token.
+    /// Prefix operators bind tighter than binary operators but looser than postfix operators.
+    pub fn prefix_bp(&self) -> Option<((), u8)> {
+        match self {
+            Token::LogicalNot | Token::Minus => Some(((), 15)),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the token is a relational comparison operator (`<`, `<=`, `>` or `>=`).
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Token::GreaterThan | Token::GreaterThanEquals | Token::LessThan | Token::LessThanEquals
+        )
+    }
+
+    /// Returns the source text of the token if it is a reserved keyword (including the `true` and `false` literals).
+    /// Keywords cannot be used as identifiers.
+    pub fn keyword(&self) -> Option<&'static str> {
+        match self {
+            Token::Fn => Some("fn"),
+            Token::Let => Some("let"),
+            Token::Return => Some("return"),
+            Token::If => Some("if"),
+            Token::Else => Some("else"),
+            Token::While => Some("while"),
+            Token::Do => Some("do"),
+            Token::Break => Some("break"),
+            Token::Continue => Some("continue"),
+            Token::BoolLit(true) => Some("true"),
+            Token::BoolLit(false) => Some("false"),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the token is an assignment operator (`=`, `+=`, `-=`, `*=` or `/=`).
+    pub fn is_assignment(&self) -> bool {
+        matches!(
+            self,
+            Token::Equals
+                | Token::PlusEquals
+                | Token::MinusEquals
+                | Token::AsteriskEquals
+                | Token::SlashEquals
+        )
+    }
+
+    /// Returns the postfix binding power or `None` if invalid binop token.
+    /// Binding power `0` and `1` is reserved for accepting any expression.
+    pub fn postfix_bp(&self) -> Option<(u8, ())> {
+        match self {
+            Token::OpenParen => Some((16, ())),
+            _ => None,
+        }
+    }
+}
@@ -1,6 +1,6 @@
-//! Source code parsing.
-
-pub mod ast;
-pub mod lexer;
-pub mod parser;
-pub mod visitor;
+//! Source code parsing.
+
+pub mod ast;
+pub mod lexer;
+pub mod parser;
+pub mod visitor;
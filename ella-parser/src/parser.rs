@@ -1,111 +1,365 @@
-//! Parse a [`Source`] into an AST (abstract syntax tree).
-
-use crate::ast::{Expr, Stmt};
-use crate::lexer::Token;
-use ella_source::{Source, SyntaxError};
-use logos::{Lexer, Logos};
-use std::mem;
-
-mod expr;
-mod stmt;
-pub use expr::*;
-pub use stmt::*;
-
-/// A parser instance.
-pub struct Parser<'a> {
-    /// Cached token for peeking.
-    current_token: Token,
-    lexer: Lexer<'a, Token>,
-    /// Source code.
-    source: &'a Source<'a>,
-}
-
-impl<'a> Parser<'a> {
-    /// Create a new [`Parser`] from the `source`.
-    pub fn new(source: &'a Source<'a>) -> Self {
-        let mut lexer = Token::lexer(source.content);
-        Self {
-            current_token: lexer.next().unwrap(),
-            lexer,
-            source,
-        }
-    }
-}
-
-impl<'a> Parser<'a> {
-    /// Returns an anonymous top level function.
-    pub fn parse_program(&mut self) -> Stmt {
-        let mut body = Vec::new();
-        loop {
-            body.push(self.parse_declaration());
-            if self.current_token == Token::Eof {
-                break;
-            }
-        }
-
-        Stmt::FnDeclaration {
-            body,
-            ident: "<global>".to_string(),
-            params: Vec::new(),
-        }
-    }
-
-    /// Returns an anonymous top level function.
-    /// If the last statement is an [`Stmt::ExprStmt`], it will create a function call to `println()`.
-    pub fn parse_repl_input(&mut self) -> Stmt {
-        let mut body = Vec::new();
-        loop {
-            body.push(self.parse_declaration());
-            if matches!(self.current_token, Token::Eof | Token::Error) {
-                break;
-            }
-        }
-
-        if let Some(Stmt::ExprStmt(expr)) = body.last_mut() {
-            *expr = Expr::FnCall {
-                args: vec![expr.clone()],
-                callee: Box::new(Expr::Identifier("println".to_string())),
-            }
-        }
-
-        Stmt::FnDeclaration {
-            body,
-            ident: "<global>".to_string(),
-            params: Vec::new(),
-        }
-    }
-}
-
-/// Parser utilities.
-impl<'a> Parser<'a> {
-    fn next(&mut self) -> Token {
-        let token = self.lexer.next().unwrap_or(Token::Eof);
-        self.current_token = token.clone();
-        token
-    }
-
-    /// Predicate that tests whether the next token has the same discriminant and eats the next token if yes as a side effect.
-    #[must_use = "to unconditionally eat a token, use Self::next"]
-    fn eat(&mut self, tok: Token) -> bool {
-        if mem::discriminant(&self.current_token) == mem::discriminant(&tok) {
-            self.next(); // eat token
-            true
-        } else {
-            false
-        }
-    }
-
-    fn expect(&mut self, tok: Token) {
-        if !self.eat(tok) {
-            self.next();
-            self.unexpected()
-        }
-    }
-
-    /// Raises an unexpected token error.
-    fn unexpected(&mut self) {
-        self.source
-            .errors
-            .add_error(SyntaxError::new("Unexpected token", self.lexer.span()))
-    }
-}
+//! Parse a [`Source`] into an AST (abstract syntax tree).
+
+use crate::ast::{Expr, Stmt};
+use crate::lexer::Token;
+use ella_source::{Source, SyntaxError};
+use logos::{Lexer, Logos};
+use std::mem;
+use std::ops::Range;
+
+mod expr;
+mod stmt;
+
+/// The maximum number of elements in an array or tuple literal (the operand of the `make_array` instruction is a `u8`).
+pub const MAX_ARRAY_LIT_LEN: usize = u8::MAX as usize;
+
+/// A parser instance.
+pub struct Parser<'a> {
+    /// Cached token for peeking.
+    current_token: Token,
+    lexer: Lexer<'a, Token>,
+    /// Source code.
+    source: &'a Source<'a>,
+    /// The comment lines immediately preceding `current_token`.
+    preceding_comments: Vec<String>,
+    /// If `true`, comments preceding declarations are attached to the AST. See [`Self::set_doc_comments`].
+    doc_comments: bool,
+    /// `true` if there is a newline between the previous token and `current_token`.
+    newline_before: bool,
+    /// If `true`, a newline can be used instead of a `;` to terminate an expression statement.
+    /// This is only enabled by [`Self::parse_repl_input`].
+    newline_terminates_exprs: bool,
+    /// The end of the token preceding `current_token`.
+    prev_token_end: usize,
+    /// The span of the last parsed parenthesized expression, from `(` to `)`.
+    last_paren_expr: Range<usize>,
+    /// The 1-based source line of `current_token`.
+    current_line: usize,
+    /// The offset up to which the newlines are counted in `current_line`.
+    current_line_offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Create a new [`Parser`] from the `source`.
+    pub fn new(source: &'a Source<'a>) -> Self {
+        let mut parser = Self {
+            current_token: Token::Eof,
+            lexer: Token::lexer(&source.content),
+            source,
+            preceding_comments: Vec::new(),
+            doc_comments: false,
+            newline_before: false,
+            newline_terminates_exprs: false,
+            prev_token_end: 0,
+            last_paren_expr: 0..0,
+            current_line: 1,
+            current_line_offset: 0,
+        };
+        parser.next(); // load the first token
+        parser
+    }
+
+    /// Enables or disables doc comments (disabled by default).
+    /// When enabled, the `//` comment lines immediately preceding a `let` or `fn` declaration are attached to the
+    /// `doc` field of the [`Stmt`] (one line per comment, without the `//` and the following space).
+    /// This is useful for documentation tooling.
+    pub fn set_doc_comments(&mut self, doc_comments: bool) {
+        self.doc_comments = doc_comments;
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Returns an anonymous top level function.
+    pub fn parse_program(&mut self) -> Stmt {
+        let mut body = Vec::new();
+        while self.current_token != Token::Eof {
+            body.push(self.parse_declaration());
+        }
+
+        Stmt::FnDeclaration {
+            body,
+            ident: "<global>".to_string(),
+            params: Vec::new(),
+            line: 0,
+            doc: None,
+            span: 0..self.source.content.len(),
+        }
+    }
+
+    /// Returns an anonymous top level function.
+    /// If `auto_print` is `true` and the last statement is an [`Stmt::ExprStmt`], it will create a function call to
+    /// `println()`. Statement-like expressions (assignments and calls to `print()` or `println()`) are never wrapped.
+    ///
+    /// Unlike [`Self::parse_program`], the `;` terminating an expression statement can be omitted if the statement is
+    /// followed by a newline or by the end of the input. This makes pasting multiple lines into the REPL more forgiving.
+    pub fn parse_repl_input(&mut self, auto_print: bool) -> Stmt {
+        self.newline_terminates_exprs = true;
+        let mut body = Vec::new();
+        let mut last_span = 0..0;
+        while self.current_token != Token::Eof {
+            let start = self.lexer.span().start;
+            body.push(self.parse_declaration());
+            last_span = start..self.prev_token_end;
+        }
+
+        if auto_print {
+            if let Some(Stmt::ExprStmt { expr, .. }) = body.last_mut() {
+                if !is_statement_like(expr) {
+                    *expr = Expr::FnCall {
+                        args: vec![expr.clone()],
+                        callee: Box::new(Expr::Identifier("println".to_string())),
+                        span: last_span,
+                    }
+                }
+            }
+        }
+
+        Stmt::FnDeclaration {
+            body,
+            ident: "<global>".to_string(),
+            params: Vec::new(),
+            line: 0,
+            doc: None,
+            span: 0..self.source.content.len(),
+        }
+    }
+}
+
+/// Returns `true` if the value of `expr` is not worth printing in the REPL.
+fn is_statement_like(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binary { op, .. } => op.is_assignment(),
+        Expr::FnCall { callee, .. } => {
+            matches!(callee.as_ref(), Expr::Identifier(ident) if ident == "print" || ident == "println")
+        }
+        _ => false,
+    }
+}
+
+/// Parser utilities.
+impl<'a> Parser<'a> {
+    /// Advances to the next token, skipping comments. The skipped comments that are on their own line are kept as
+    /// `preceding_comments`. Trailing comments (after a token on the same line) are discarded.
+    fn next(&mut self) -> Token {
+        self.preceding_comments.clear();
+        let token_end = self.lexer.span().end;
+        self.prev_token_end = token_end;
+        let mut prev_end = token_end;
+        loop {
+            match self.lexer.next().unwrap_or(Token::Eof) {
+                Token::Comment(comment) => {
+                    let span = self.lexer.span();
+                    let own_line =
+                        prev_end == 0 || self.source.content[prev_end..span.start].contains('\n');
+                    if own_line {
+                        let comment = comment.strip_prefix(' ').unwrap_or(&comment);
+                        self.preceding_comments.push(comment.to_string());
+                    }
+                    prev_end = span.end;
+                }
+                token => {
+                    let start = self.lexer.span().start.max(token_end);
+                    self.newline_before = self.source.content[token_end..start].contains('\n');
+                    self.current_line += self.source.content[self.current_line_offset..start]
+                        .matches('\n')
+                        .count();
+                    self.current_line_offset = start;
+                    self.current_token = token.clone();
+                    return token;
+                }
+            }
+        }
+    }
+
+    /// Returns the doc comment for the declaration starting at the current token or `None` if there is no comment
+    /// immediately preceding it or if doc comments are disabled.
+    fn doc_comment(&self) -> Option<String> {
+        if self.doc_comments && !self.preceding_comments.is_empty() {
+            Some(self.preceding_comments.join("\n"))
+        } else {
+            None
+        }
+    }
+
+    /// Predicate that tests whether the next token has the same discriminant and eats the next token if yes as a side effect.
+    #[must_use = "to unconditionally eat a token, use Self::next"]
+    fn eat(&mut self, tok: Token) -> bool {
+        if mem::discriminant(&self.current_token) == mem::discriminant(&tok) {
+            self.next(); // eat token
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: Token) {
+        if !self.eat(tok) {
+            self.next();
+            self.unexpected()
+        }
+    }
+
+    /// Raises an unmatched delimiter error pointing at the opening delimiter `span`.
+    fn unmatched(&mut self, delimiter: &str, span: Range<usize>) {
+        self.source.errors.add_error(SyntaxError::new(
+            format!("Unmatched '{}' opened here", delimiter),
+            span,
+        ))
+    }
+
+    /// Raises an error for a reserved `keyword` used where an identifier is expected.
+    fn reserved_keyword(&mut self, keyword: &str) {
+        self.source.errors.add_error(SyntaxError::new(
+            format!("`{}` is a reserved keyword", keyword),
+            self.lexer.span(),
+        ))
+    }
+
+    /// Raises an error if an array or tuple literal spanning `span` has more than [`MAX_ARRAY_LIT_LEN`] elements.
+    fn check_array_lit_len(&mut self, len: usize, span: Range<usize>) {
+        if len > MAX_ARRAY_LIT_LEN {
+            self.source.errors.add_error(SyntaxError::new(
+                format!(
+                    "Too many elements in array literal. An array literal can have at most {} elements",
+                    MAX_ARRAY_LIT_LEN
+                ),
+                span,
+            ))
+        }
+    }
+
+    /// Raises an unexpected token error.
+    fn unexpected(&mut self) {
+        self.source
+            .errors
+            .add_error(SyntaxError::new("Unexpected token", self.lexer.span()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_structurally_eq() {
+        let parse = |source: &str| {
+            let source = source.into();
+            let ast = Parser::new(&source).parse_program();
+            assert!(source.has_no_errors());
+            ast
+        };
+
+        let compact =
+            parse("fn f(x) { if x > 0 { return x; } else { return -x; } } let y = f(-1);");
+        let indented = parse(
+            r#"
+            fn f(x) {
+                if x > 0 {
+                    return x;
+                } else {
+                    return -x;
+                }
+            }
+            // comments are ignored
+            let y = f(-1);"#,
+        );
+        assert!(compact.structurally_eq(&indented));
+        assert!(!compact.structurally_eq(&parse("fn f(x) { return x; } let y = f(-1);")));
+        assert!(Expr::NumberLit(f64::NAN).structurally_eq(&Expr::NumberLit(f64::NAN)));
+    }
+
+    /// Parses the `source` as REPL input and returns the body of the top level function.
+    fn repl_input(source: &str, auto_print: bool) -> Vec<Stmt> {
+        let source = source.into();
+        let ast = Parser::new(&source).parse_repl_input(auto_print);
+        assert!(source.has_no_errors());
+        match ast {
+            Stmt::FnDeclaration { body, .. } => body,
+            _ => unreachable!(),
+        }
+    }
+
+    fn expr_stmt(expr: Expr) -> Stmt {
+        Stmt::ExprStmt { expr, line: 1 }
+    }
+
+    fn println(arg: Expr) -> Stmt {
+        expr_stmt(Expr::FnCall {
+            callee: Box::new(Expr::Identifier("println".to_string())),
+            args: vec![arg],
+            span: 0..0,
+        })
+    }
+
+    /// Asserts that both function bodies have the same structure (ignoring spans).
+    fn assert_body_eq(body: Vec<Stmt>, expected: Vec<Stmt>) {
+        assert!(
+            Stmt::Block(body.clone()).structurally_eq(&Stmt::Block(expected.clone())),
+            "{:?} != {:?}",
+            body,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse_repl_input() {
+        let x = || Expr::Identifier("x".to_string());
+
+        assert_body_eq(repl_input("x;", true), vec![println(x())]);
+        assert_eq!(repl_input("x;", false), vec![expr_stmt(x())]);
+        assert_body_eq(
+            repl_input("1; x;", true),
+            vec![expr_stmt(Expr::NumberLit(1.0)), println(x())],
+        );
+
+        // statement-like expressions are not wrapped
+        assert_body_eq(repl_input("println(x);", true), vec![println(x())]);
+        for source in &["x = 1;", "x += 1;", "print(x);"] {
+            assert_eq!(repl_input(source, true), repl_input(source, false));
+        }
+        // newlines terminate expression statements
+        assert_body_eq(
+            repl_input("1\nx", true),
+            vec![expr_stmt(Expr::NumberLit(1.0)), println(x())],
+        );
+        assert_body_eq(
+            repl_input("x = 1 // comment\n  x;\n", false),
+            repl_input("x = 1; x;", false),
+        );
+        // newlines also terminate expression statements in function bodies
+        assert_body_eq(
+            repl_input("fn f(x) {\n  x = x + 1\n  x\n}\n", false),
+            repl_input("fn f(x) { x = x + 1; x }", false),
+        );
+
+        assert_eq!(
+            repl_input("let y = 1;", true),
+            vec![Stmt::LetDeclaration {
+                ident: "y".to_string(),
+                initializer: Expr::NumberLit(1.0),
+                doc: None,
+                span: 0..10,
+                line: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_repl_input_reports_invalid_tokens() {
+        let source = "@ let x = 1;".into();
+        let ast = Parser::new(&source).parse_repl_input(false);
+        assert!(!source.has_no_errors());
+        match ast {
+            // the declaration following the invalid token is still parsed
+            Stmt::FnDeclaration { body, .. } => assert_eq!(body.len(), 2),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_newline_requires_semi_in_program() {
+        let source = "1\nx;".into();
+        Parser::new(&source).parse_program();
+        assert!(!source.has_no_errors());
+    }
+}
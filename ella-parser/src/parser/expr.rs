@@ -1,4 +1,5 @@
 use super::*;
+use crate::lexer::is_imprecise_integer_lit;
 
 impl<'a> Parser<'a> {
     /* Expressions */
@@ -15,19 +16,45 @@ impl<'a> Parser<'a> {
             Token::NumberLit(_) | Token::BoolLit(_) | Token::StringLit(_) => {
                 self.parse_literal_expr()
             }
-            Token::Identifier(_) => self.parse_identifier_or_call_expr(),
-            Token::LogicalNot => {
+            Token::Identifier(_) => self.parse_identifier_expr(),
+            Token::OpenBracket => self.parse_array_lit_expr(),
+            Token::OpenParen => self.parse_paren_expr(),
+            Token::Fn => self.parse_fn_lit_expr(),
+            Token::UnterminatedStringLit => {
+                self.source.errors.add_error(SyntaxError::new(
+                    "Unterminated string literal",
+                    self.lexer.span(),
+                ));
                 self.next();
-                Expr::Unary {
-                    op: Token::LogicalNot,
-                    arg: Box::new(self.parse_expr()),
+                Expr::Error
+            }
+            Token::Error if self.lexer.slice().starts_with('"') => {
+                self.source.errors.add_error(SyntaxError::new(
+                    "Invalid escape sequence in string literal",
+                    self.lexer.span(),
+                ));
+                self.next();
+                Expr::Error
+            }
+            Token::OpenBrace => {
+                let (body, value) = self.parse_braced_body_with_value(true);
+                Expr::Block {
+                    body,
+                    value: value.map(|(value, _)| Box::new(value)),
                 }
             }
-            Token::Minus => {
+            Token::LogicalNot | Token::Minus => {
+                let op = self.current_token.clone();
+                let ((), r_bp) = op.prefix_bp().unwrap();
                 self.next();
-                Expr::Unary {
-                    op: Token::Minus,
-                    arg: Box::new(self.parse_expr()),
+
+                match (op, self.parse_expr_bp(r_bp)) {
+                    // fold negative number literals to avoid emitting a `neg` instruction
+                    (Token::Minus, Expr::NumberLit(val)) => Expr::NumberLit(-val),
+                    (op, arg) => Expr::Unary {
+                        op,
+                        arg: Box::new(arg),
+                    },
                 }
             }
             _ => {
@@ -41,6 +68,7 @@ impl<'a> Parser<'a> {
     /// Parses an expression with the specified `min_bp`.
     /// To parse any expression use, [`Self::parse_expr`].
     fn parse_expr_bp(&mut self, min_bp: u8) -> Expr {
+        let start = self.lexer.span().start;
         let mut lhs = self.parse_primary_expr(); // TODO: move handle prefix into parse_expr_bp
 
         loop {
@@ -50,6 +78,7 @@ impl<'a> Parser<'a> {
                     break;
                 }
                 let postfix_op = self.current_token.clone();
+                let postfix_span = self.lexer.span();
                 self.next();
 
                 match postfix_op {
@@ -63,6 +92,9 @@ impl<'a> Parser<'a> {
 
                                 if self.eat(Token::CloseParen) {
                                     break;
+                                } else if self.current_token == Token::Eof {
+                                    self.unmatched("(", postfix_span);
+                                    break;
                                 } else if !self.eat(Token::Comma) {
                                     self.next();
                                     self.unexpected();
@@ -74,6 +106,7 @@ impl<'a> Parser<'a> {
                         lhs = Expr::FnCall {
                             callee: Box::new(lhs),
                             args,
+                            span: start..self.prev_token_end,
                         }
                     }
                     _ => unreachable!(),
@@ -93,6 +126,17 @@ impl<'a> Parser<'a> {
 
             // self.current_token is a valid binop
             let binop = self.current_token.clone();
+            // detect chained comparisons (e.g. `1 < x < 3`), parsed as `(1 < x) < 3`
+            // explicitly parenthesized comparisons (e.g. `(a < b) < c`) are allowed
+            let lhs_parenthesized = self.last_paren_expr == (start..self.prev_token_end);
+            if let Expr::Binary { op, .. } = &lhs {
+                if binop.is_comparison() && op.is_comparison() && !lhs_parenthesized {
+                    self.source.errors.add_error(SyntaxError::new(
+                        "Comparison operators cannot be chained. Use `&&` to combine comparisons instead (e.g. `a < b && b < c`)",
+                        self.lexer.span(),
+                    ));
+                }
+            }
             self.next();
 
             let rhs = self.parse_expr_bp(r_bp);
@@ -108,11 +152,99 @@ impl<'a> Parser<'a> {
     }
 
     /* Expressions.Literals */
+    /// Parses an array literal expression (e.g. `[1, 2, 3]`).
+    fn parse_array_lit_expr(&mut self) -> Expr {
+        let open_span = self.lexer.span();
+        self.expect(Token::OpenBracket);
+
+        let mut elements = Vec::new();
+        if !self.eat(Token::CloseBracket) {
+            loop {
+                elements.push(self.parse_expr());
+
+                if self.eat(Token::CloseBracket) {
+                    break;
+                } else if self.current_token == Token::Eof {
+                    self.unmatched("[", open_span.clone());
+                    break;
+                } else if !self.eat(Token::Comma) {
+                    self.next();
+                    self.unexpected();
+                    break;
+                }
+            }
+        }
+        self.check_array_lit_len(elements.len(), open_span.start..self.prev_token_end);
+
+        Expr::ArrayLit(elements)
+    }
+
+    /// Parses a parenthesized expression (e.g. `(1 + 2)`) or a tuple literal (e.g. `(1, 2)`).
+    /// A parenthesized list is a tuple if it contains a comma. A single element tuple is written with a trailing comma
+    /// (e.g. `(1,)`). Tuples are represented as [`Expr::ArrayLit`].
+    fn parse_paren_expr(&mut self) -> Expr {
+        let open_span = self.lexer.span();
+        self.expect(Token::OpenParen);
+
+        let mut elements = vec![self.parse_expr()];
+        let mut is_tuple = false;
+        loop {
+            if self.eat(Token::CloseParen) {
+                break;
+            } else if self.current_token == Token::Eof {
+                self.unmatched("(", open_span.clone());
+                break;
+            } else if !self.eat(Token::Comma) {
+                self.next();
+                self.unexpected();
+                break;
+            }
+
+            is_tuple = true;
+            if self.eat(Token::CloseParen) {
+                break; // trailing comma
+            }
+            elements.push(self.parse_expr());
+        }
+        self.last_paren_expr = open_span.start..self.prev_token_end;
+
+        if is_tuple {
+            self.check_array_lit_len(elements.len(), self.last_paren_expr.clone());
+            Expr::ArrayLit(elements)
+        } else {
+            elements.pop().unwrap()
+        }
+    }
+
+    /// Parses a function expression (e.g. `fn(x) { return x + 1; }`).
+    /// The function can be immediately called (e.g. `fn() { return 42; }()`) since calls are parsed as a postfix
+    /// operator.
+    fn parse_fn_lit_expr(&mut self) -> Expr {
+        let start = self.lexer.span().start;
+        self.expect(Token::Fn);
+        match self.parse_fn_params_and_body("<anonymous>".to_string(), start, None) {
+            Stmt::Error => Expr::Error,
+            func => Expr::FnLit(Box::new(func)),
+        }
+    }
+
     /// Parses a literal expression.
     /// A literal can be either a number literal or a bool literal.
     fn parse_literal_expr(&mut self) -> Expr {
         let val = match self.current_token {
-            Token::NumberLit(val) => Expr::NumberLit(val),
+            Token::NumberLit(val) => {
+                if is_imprecise_integer_lit(self.lexer.slice(), val) {
+                    self.source.errors.add_error(SyntaxError::warning(
+                        format!(
+                            "Number literal {} cannot be represented exactly and is rounded to {}",
+                            self.lexer.slice(),
+                            val
+                        ),
+                        self.lexer.span(),
+                    ));
+                }
+                Expr::NumberLit(val)
+            }
             Token::BoolLit(val) => Expr::BoolLit(val),
             Token::StringLit(ref val) => Expr::StringLit(val.clone()),
             _ => {
@@ -127,8 +259,8 @@ impl<'a> Parser<'a> {
     }
 
     /* Expressions.Identifier */
-    /// Parses an identifier or a call expression.
-    fn parse_identifier_or_call_expr(&mut self) -> Expr {
+    /// Parses an identifier. Call expressions are parsed as a postfix operator in [`Self::parse_expr_bp`].
+    fn parse_identifier_expr(&mut self) -> Expr {
         let ident = match self.current_token.clone() {
             Token::Identifier(ident) => {
                 self.next();
@@ -163,6 +295,8 @@ mod tests {
         assert_debug_snapshot!("int", expr("1"));
         assert_debug_snapshot!("double-2.0", expr("2.0"));
         assert_debug_snapshot!("double-2.5", expr("2.5"));
+        assert_debug_snapshot!("scientific", expr("1.5e3"));
+        assert_debug_snapshot!("hex", expr("0xff"));
     }
 
     #[test]
@@ -171,6 +305,111 @@ mod tests {
         assert_debug_snapshot!("binary-equality", expr("1 == 2 - 1"));
         assert_debug_snapshot!("binary-associativity", expr("2 * 2 * 2")); // should be (2 * 2) * 2
         assert_debug_snapshot!("binary-associativity-2", expr("a = b = c")); // should be a = (b = c)
+        assert_debug_snapshot!("binary-floor-div", expr("1 + 7 ~/ 2 * x")); // should be 1 + ((7 ~/ 2) * x)
+    }
+
+    #[test]
+    fn test_unary_expr() {
+        assert_debug_snapshot!("unary-neg-lit", expr("-2"));
+        assert_debug_snapshot!("unary-neg-mul", expr("-2 * 3")); // should be (-2) * 3
+        assert_debug_snapshot!("unary-neg-add", expr("-2 + 3")); // should be (-2) + 3
+        assert_debug_snapshot!("unary-neg-identifier-mul", expr("-x * 3")); // should be (-x) * 3
+        assert_debug_snapshot!("unary-neg-fn-call", expr("-foo(1)")); // should be -(foo(1))
+        assert_debug_snapshot!("unary-not-equality", expr("!a == b")); // should be (!a) == b
+    }
+
+    #[test]
+    fn test_chained_comparison() {
+        let source = "1 < x < 3".into();
+        Parser::new(&source).parse_expr();
+        let errors = source.errors.errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .message()
+            .starts_with("Comparison operators cannot be chained"));
+        assert_eq!(errors[0].span(), 6..7);
+
+        expr("1 < x == y < 3"); // comparisons separated by equality are allowed
+        expr("(a < b) < c"); // parenthesized comparisons are allowed
+        expr("((1 < x)) == true");
+
+        let source = "(a) < b < c".into();
+        Parser::new(&source).parse_expr();
+        assert_eq!(source.errors.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_imprecise_number_lit() {
+        let source = "9007199254740993".into();
+        Parser::new(&source).parse_expr();
+        let warnings = source.errors.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message(),
+            "Number literal 9007199254740993 cannot be represented exactly and is rounded to 9007199254740992"
+        );
+        assert_eq!(warnings[0].span(), 0..16);
+
+        for precise in &["100", "9007199254740992", "0xff", "1.5", "1e300"] {
+            let source = (*precise).into();
+            Parser::new(&source).parse_expr();
+            assert!(source.errors.warnings().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert_debug_snapshot!("logical-or", expr("a || b"));
+        assert_debug_snapshot!("logical-and", expr("a && b"));
+        // should be a || ((b == c) && d)
+        assert_debug_snapshot!("logical-precedence", expr("a || b == c && d"));
+    }
+
+    #[test]
+    fn test_block_expr() {
+        assert_debug_snapshot!("block-expr", expr("{ let a = 1; a + 1 }"));
+        assert_debug_snapshot!("block-expr-no-value", expr("{ let a = 1; a + 1; }"));
+        assert_debug_snapshot!("block-expr-empty", expr("{}"));
+        assert_debug_snapshot!("block-expr-nested", expr("{ let b = { 1 }; b }"));
+    }
+
+    #[test]
+    fn test_array_lit() {
+        assert_debug_snapshot!("array-lit", expr("[1, foo, [2]]"));
+        assert_debug_snapshot!("array-lit-empty", expr("[]"));
+    }
+
+    #[test]
+    fn test_paren_expr() {
+        assert_debug_snapshot!("paren-expr", expr("(1 + 2) * 3"));
+        assert_eq!(expr("((a))"), Expr::Identifier("a".to_string()));
+        assert_debug_snapshot!("tuple-lit", expr("(a, 1 + 2)"));
+        assert_eq!(
+            expr("(a,)"),
+            Expr::ArrayLit(vec![Expr::Identifier("a".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_array_lit_too_many_elements() {
+        let elements = |count: usize| vec!["1"; count].join(", ");
+        for source in &[
+            format!("[{}]", elements(256)),
+            format!("({})", elements(256)),
+        ] {
+            let source = source.as_str().into();
+            Parser::new(&source).parse_expr();
+            let errors = source.errors.errors();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(
+                errors[0].message(),
+                "Too many elements in array literal. An array literal can have at most 255 elements"
+            );
+            assert_eq!(errors[0].span(), 0..source.content.len());
+        }
+
+        expr(&format!("[{}]", elements(255)));
+        expr(&format!("({})", elements(255)));
     }
 
     #[test]
@@ -184,6 +423,14 @@ mod tests {
         assert_debug_snapshot!("fn-call-with-args", expr("foo(1, bar)"));
         assert_debug_snapshot!("fn-call-with-nested-args", expr("foo(1, bar, baz())"));
         assert_debug_snapshot!("fn-call-chained", expr("foo(1, 2)(3)(4)"));
+        assert_debug_snapshot!("fn-call-call-result", expr("f()()"));
+        assert_debug_snapshot!("fn-call-call-result-with-args", expr("getAdder(2)(3)"));
+        assert_debug_snapshot!("fn-call-block", expr("{ f }(1)"));
         assert_debug_snapshot!("fn-call-fib", expr("fib(x - 1) + fib(x - 2)"));
     }
+
+    #[test]
+    fn test_fn_lit() {
+        assert_debug_snapshot!("fn-lit-iife", expr("fn() { return 42; }()"));
+    }
 }
@@ -1,265 +1,677 @@
-use super::*;
-
-impl<'a> Parser<'a> {
-    /// Parses a declaration (or statement).
-    pub fn parse_declaration(&mut self) -> Stmt {
-        match self.current_token {
-            Token::Let => self.parse_let_declaration(),
-            Token::Fn => self.parse_fn_declaration(),
-            _ => self.parse_stmt(),
-        }
-    }
-
-    /// Parses a statement.
-    pub fn parse_stmt(&mut self) -> Stmt {
-        match self.current_token {
-            Token::Return => self.parse_return_stmt(),
-            Token::OpenBrace => self.parse_block_stmt(),
-            Token::If => self.parse_if_else_stmt(),
-            Token::While => self.parse_while_stmt(),
-            _ => {
-                // expression statement
-                let expr = self.parse_expr();
-                let stmt = Stmt::ExprStmt(expr);
-                self.expect(Token::Semi);
-                stmt
-            }
-        }
-    }
-
-    pub fn parse_block_stmt(&mut self) -> Stmt {
-        self.expect(Token::OpenBrace);
-
-        let mut body = Vec::new();
-        if !self.eat(Token::CloseBrace) {
-            loop {
-                body.push(self.parse_declaration());
-
-                if self.eat(Token::CloseBrace) {
-                    break;
-                } else if self.current_token == Token::Eof {
-                    self.unexpected();
-                    break;
-                }
-            }
-        }
-
-        Stmt::Block(body)
-    }
-
-    pub fn parse_if_else_stmt(&mut self) -> Stmt {
-        self.expect(Token::If);
-
-        let condition = self.parse_expr();
-        let mut if_block = Vec::new();
-        let mut else_block = None;
-
-        self.expect(Token::OpenBrace);
-        if !self.eat(Token::CloseBrace) {
-            loop {
-                if_block.push(self.parse_declaration());
-
-                if self.eat(Token::CloseBrace) {
-                    break;
-                } else if self.current_token == Token::Eof {
-                    self.unexpected();
-                    break;
-                }
-            }
-        }
-
-        if self.eat(Token::Else) {
-            else_block = Some(Vec::new());
-            self.expect(Token::OpenBrace);
-            if !self.eat(Token::CloseBrace) {
-                loop {
-                    else_block.as_mut().unwrap().push(self.parse_declaration());
-
-                    if self.eat(Token::CloseBrace) {
-                        break;
-                    } else if self.current_token == Token::Eof {
-                        self.unexpected();
-                        break;
-                    }
-                }
-            }
-        }
-
-        Stmt::IfElseStmt {
-            condition,
-            if_block,
-            else_block,
-        }
-    }
-
-    pub fn parse_while_stmt(&mut self) -> Stmt {
-        self.expect(Token::While);
-        let condition = self.parse_expr();
-        let mut body = Vec::new();
-
-        self.expect(Token::OpenBrace);
-        if !self.eat(Token::CloseBrace) {
-            loop {
-                body.push(self.parse_declaration());
-
-                if self.eat(Token::CloseBrace) {
-                    break;
-                } else if self.current_token == Token::Eof {
-                    self.unexpected();
-                    break;
-                }
-            }
-        }
-
-        Stmt::WhileStmt { condition, body }
-    }
-
-    fn parse_let_declaration(&mut self) -> Stmt {
-        self.expect(Token::Let);
-        let ident = if let Token::Identifier(ref ident) = self.current_token {
-            let ident = ident.clone();
-            self.next();
-            ident
-        } else {
-            self.unexpected();
-            return Stmt::Error;
-        };
-        self.expect(Token::Equals);
-        let initializer = self.parse_expr();
-        self.expect(Token::Semi);
-        Stmt::LetDeclaration { ident, initializer }
-    }
-
-    fn parse_fn_declaration(&mut self) -> Stmt {
-        self.expect(Token::Fn);
-        let ident = if let Token::Identifier(ref ident) = self.current_token {
-            let ident = ident.clone();
-            self.next();
-            ident
-        } else {
-            self.next();
-            self.unexpected();
-            return Stmt::Error;
-        };
-        self.expect(Token::OpenParen);
-        let mut params = Vec::new();
-        if !self.eat(Token::CloseParen) {
-            loop {
-                params.push(if let Token::Identifier(ref ident) = self.current_token {
-                    let ident = ident.clone();
-                    self.next();
-                    ident
-                } else {
-                    self.unexpected();
-                    return Stmt::Error;
-                });
-
-                if self.eat(Token::CloseParen) {
-                    break;
-                } else if !self.eat(Token::Comma) {
-                    self.unexpected();
-                    break;
-                }
-            }
-        }
-
-        self.expect(Token::OpenBrace);
-
-        let mut body = Vec::new();
-        if !self.eat(Token::CloseBrace) {
-            loop {
-                body.push(self.parse_declaration());
-
-                if self.eat(Token::CloseBrace) {
-                    break;
-                }
-            }
-        }
-
-        Stmt::FnDeclaration {
-            body,
-            ident,
-            params,
-        }
-    }
-
-    fn parse_return_stmt(&mut self) -> Stmt {
-        self.expect(Token::Return);
-        let expr = self.parse_expr();
-        self.expect(Token::Semi);
-        Stmt::ReturnStmt(expr)
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use insta::assert_debug_snapshot;
-
-    fn stmt(source: &str) -> Stmt {
-        let source = source.into();
-        let ast = Parser::new(&source).parse_declaration();
-        assert!(source.has_no_errors());
-        ast
-    }
-
-    #[test]
-    fn test_block_stmt() {
-        assert_debug_snapshot!("block-stmt", stmt("{ 1; }"));
-        assert_debug_snapshot!("block-stmt-multiple", stmt("{ 1; 2; }"));
-        assert_debug_snapshot!("block-stmt-nested", stmt("{ 1; 2; { 3; } }"));
-    }
-
-    #[test]
-    fn test_if_else_stmt() {
-        assert_debug_snapshot!(
-            "if-stmt",
-            stmt(
-                r#"
-                if condition {
-                    if_block();
-                }"#
-            )
-        );
-        assert_debug_snapshot!(
-            "if-else-stmt",
-            stmt(
-                r#"
-                if condition {
-                    if_block();
-                } else {
-                    else_block();
-                }"#
-            )
-        );
-        assert_debug_snapshot!("if-else-stmt-empty", stmt(r#"if condition {} else {}"#));
-    }
-
-    #[test]
-    fn test_while_stmt() {
-        assert_debug_snapshot!("while-stmt", stmt(r#"while true { while_block(); }"#));
-        assert_debug_snapshot!("while-stmt-empty", stmt(r#"while true {}"#));
-    }
-
-    #[test]
-    fn test_let_declaration() {
-        assert_debug_snapshot!("let-declaration", stmt("let x = 2;"));
-        assert_debug_snapshot!("let-declaration-with-expr", stmt("let x = 1 + 2;"));
-    }
-
-    #[test]
-    fn test_fn_declaration() {
-        assert_debug_snapshot!("fn-declaration", stmt("fn foo() {}"));
-        assert_debug_snapshot!("fn-declaration-with-params", stmt("fn foo(a, b, c) {}"));
-        assert_debug_snapshot!(
-            "fn-declaration-with-params-and-body",
-            stmt("fn foo(a, b, c) { a + b + c; }")
-        );
-    }
-
-    #[test]
-    fn test_return_stmt() {
-        assert_debug_snapshot!("return-stmt", stmt("return 1;"));
-        assert_debug_snapshot!("return-stmt-with-expr", stmt("return 1 + 2;"));
-    }
-}
+use super::*;
+
+impl<'a> Parser<'a> {
+    /// Parses a declaration (or statement).
+    pub fn parse_declaration(&mut self) -> Stmt {
+        match self.current_token {
+            Token::Let => self.parse_let_declaration(),
+            Token::Fn => self.parse_fn_declaration(),
+            _ => self.parse_stmt(),
+        }
+    }
+
+    /// Parses a statement.
+    pub fn parse_stmt(&mut self) -> Stmt {
+        match self.current_token {
+            Token::Return => self.parse_return_stmt(),
+            Token::Break | Token::Continue => self.parse_break_or_continue_stmt(),
+            Token::OpenBrace => self.parse_block_stmt(),
+            Token::If => self.parse_if_else_stmt(),
+            Token::While => self.parse_while_stmt(),
+            Token::Do => self.parse_do_while_stmt(),
+            Token::Semi => {
+                self.next();
+                Stmt::EmptyStmt
+            }
+            _ => {
+                // expression statement
+                let line = self.current_line;
+                let expr = self.parse_expr();
+                self.expect_expr_stmt_end();
+                Stmt::ExprStmt { expr, line }
+            }
+        }
+    }
+
+    /// Expects the `;` terminating an expression statement.
+    /// In REPL input, a newline or the end of the input can be used instead (see [`Self::parse_repl_input`]).
+    fn expect_expr_stmt_end(&mut self) {
+        let newline_terminated = self.newline_terminates_exprs
+            && self.current_token != Token::Semi
+            && (self.newline_before || self.current_token == Token::Eof);
+        if !newline_terminated {
+            self.expect(Token::Semi);
+        }
+    }
+
+    /// Parses a list of declarations enclosed in braces (`{ ... }`).
+    /// If the closing brace is missing, the error points at the opening brace.
+    fn parse_braced_body(&mut self) -> Vec<Stmt> {
+        self.parse_braced_body_with_value(false).0
+    }
+
+    /// Parses a list of declarations enclosed in braces (`{ ... }`).
+    /// If `allow_value` is `true`, the last expression can omit the semicolon and is returned separately (with its
+    /// 1-based source line) as the value of the block.
+    pub(crate) fn parse_braced_body_with_value(
+        &mut self,
+        allow_value: bool,
+    ) -> (Vec<Stmt>, Option<(Expr, usize)>) {
+        let open_span = self.lexer.span();
+        self.expect(Token::OpenBrace);
+
+        let mut body = Vec::new();
+        let mut value = None;
+        while !self.eat(Token::CloseBrace) {
+            if self.current_token == Token::Eof {
+                self.unmatched("{", open_span);
+                break;
+            }
+
+            match self.current_token {
+                Token::Let
+                | Token::Fn
+                | Token::Return
+                | Token::Break
+                | Token::Continue
+                | Token::OpenBrace
+                | Token::If
+                | Token::While
+                | Token::Do
+                | Token::Semi => body.push(self.parse_declaration()),
+                _ if allow_value => {
+                    let line = self.current_line;
+                    let expr = self.parse_expr();
+                    if self.current_token == Token::CloseBrace {
+                        // trailing expression without a semicolon
+                        value = Some((expr, line));
+                    } else {
+                        self.expect_expr_stmt_end();
+                        body.push(Stmt::ExprStmt { expr, line });
+                    }
+                }
+                _ => body.push(self.parse_declaration()),
+            }
+        }
+        (body, value)
+    }
+
+    /// Parses the condition of an `if` or `while` statement.
+    /// Also returns `true` if the whole condition is enclosed in parentheses (e.g. `(x < 0)` but not `(x) < (0)`).
+    fn parse_condition(&mut self) -> (Expr, bool) {
+        let start = self.lexer.span().start;
+        let condition = self.parse_expr();
+        let parenthesized = self.last_paren_expr == (start..self.prev_token_end);
+        (condition, parenthesized)
+    }
+
+    /// Parses the body of an `if`, `else` or `while` statement: either a list of declarations enclosed in braces or a
+    /// single statement without braces (e.g. `if (condition) return;`).
+    /// The braces can only be omitted if `allow_without_braces` is `true`. Otherwise, the start of the statement could
+    /// have been parsed as a part of the condition (e.g. `while i < n (i += 1);` or `if x -y;`).
+    fn parse_body(&mut self, allow_without_braces: bool) -> Vec<Stmt> {
+        if self.current_token == Token::OpenBrace {
+            self.parse_braced_body()
+        } else {
+            if !allow_without_braces {
+                self.source.errors.add_error(SyntaxError::new(
+                    "Expected '{', braces can only be omitted if the condition is enclosed in parentheses",
+                    self.lexer.span(),
+                ));
+            }
+            vec![self.parse_stmt()]
+        }
+    }
+
+    pub fn parse_block_stmt(&mut self) -> Stmt {
+        Stmt::Block(self.parse_braced_body())
+    }
+
+    /// Parses an if/else statement.
+    /// An `else if` clause is parsed as an `else` block containing a single nested [`Stmt::IfElseStmt`].
+    /// An `else` always belongs to the nearest `if` (e.g. `if (a) if (b) x(); else y();` is `if (a) { if (b) x(); else y(); }`).
+    pub fn parse_if_else_stmt(&mut self) -> Stmt {
+        let line = self.current_line;
+        self.expect(Token::If);
+
+        let (condition, parenthesized) = self.parse_condition();
+        let if_block = self.parse_body(parenthesized);
+        let else_block = if self.eat(Token::Else) {
+            Some(self.parse_body(true))
+        } else {
+            None
+        };
+
+        Stmt::IfElseStmt {
+            condition,
+            if_block,
+            else_block,
+            line,
+        }
+    }
+
+    pub fn parse_while_stmt(&mut self) -> Stmt {
+        let line = self.current_line;
+        self.expect(Token::While);
+        let (condition, parenthesized) = self.parse_condition();
+        let body = self.parse_body(parenthesized);
+
+        Stmt::WhileStmt {
+            condition,
+            body,
+            line,
+        }
+    }
+
+    /// Parses a do-while statement (`do { ... } while condition;`).
+    pub fn parse_do_while_stmt(&mut self) -> Stmt {
+        let line = self.current_line;
+        self.expect(Token::Do);
+        let body = self.parse_braced_body();
+        self.expect(Token::While);
+        let condition = self.parse_expr();
+        self.expect(Token::Semi);
+
+        Stmt::DoWhileStmt {
+            body,
+            condition,
+            line,
+        }
+    }
+
+    fn parse_let_declaration(&mut self) -> Stmt {
+        let doc = self.doc_comment();
+        let start = self.lexer.span().start;
+        let line = self.current_line;
+        self.expect(Token::Let);
+        if self.current_token == Token::OpenParen {
+            return self.parse_tuple_declaration(start, line);
+        }
+        let ident = if let Token::Identifier(ref ident) = self.current_token {
+            let ident = ident.clone();
+            self.next();
+            ident
+        } else if let Some(keyword) = self.current_token.keyword() {
+            self.reserved_keyword(keyword);
+            self.next();
+            return Stmt::Error;
+        } else {
+            self.unexpected();
+            return Stmt::Error;
+        };
+        self.expect(Token::Equals);
+        let initializer = self.parse_expr();
+        self.expect(Token::Semi);
+        Stmt::LetDeclaration {
+            ident,
+            initializer,
+            doc,
+            span: start..self.prev_token_end,
+            line,
+        }
+    }
+
+    /// Parses the rest of a `let (x, y) = ...;` declaration, starting at the `(`.
+    /// `start` and `line` are the offset and the line of the `let` keyword.
+    fn parse_tuple_declaration(&mut self, start: usize, line: usize) -> Stmt {
+        let open_paren_span = self.lexer.span();
+        self.expect(Token::OpenParen);
+        let mut idents = Vec::new();
+        loop {
+            if let Token::Identifier(ref ident) = self.current_token {
+                let ident = ident.clone();
+                if idents.contains(&ident) {
+                    self.source.errors.add_error(SyntaxError::new(
+                        format!("Duplicate variable {}", ident),
+                        self.lexer.span(),
+                    ));
+                }
+                idents.push(ident);
+                self.next();
+            } else if let Some(keyword) = self.current_token.keyword() {
+                self.reserved_keyword(keyword);
+                self.next();
+                return Stmt::Error;
+            } else {
+                self.next();
+                self.unexpected();
+                return Stmt::Error;
+            }
+
+            if self.eat(Token::CloseParen) {
+                break;
+            } else if self.current_token == Token::Eof {
+                self.unmatched("(", open_paren_span);
+                return Stmt::Error;
+            } else if !self.eat(Token::Comma) {
+                self.next();
+                self.unexpected();
+                return Stmt::Error;
+            }
+        }
+        self.expect(Token::Equals);
+        let initializer = self.parse_expr();
+        self.expect(Token::Semi);
+        Stmt::TupleDeclaration {
+            idents,
+            initializer,
+            span: start..self.prev_token_end,
+            line,
+        }
+    }
+
+    fn parse_fn_declaration(&mut self) -> Stmt {
+        let doc = self.doc_comment();
+        let start = self.lexer.span().start;
+        self.expect(Token::Fn);
+        let ident = if let Token::Identifier(ref ident) = self.current_token {
+            let ident = ident.clone();
+            self.next();
+            ident
+        } else if let Some(keyword) = self.current_token.keyword() {
+            self.reserved_keyword(keyword);
+            self.next();
+            return Stmt::Error;
+        } else {
+            self.next();
+            self.unexpected();
+            return Stmt::Error;
+        };
+        self.parse_fn_params_and_body(ident, start, doc)
+    }
+
+    /// Parses the parameters and the body of a function, starting at the `(`.
+    /// Used for both function declarations and function expressions. `start` is the offset of the `fn` keyword.
+    pub(super) fn parse_fn_params_and_body(
+        &mut self,
+        ident: String,
+        start: usize,
+        doc: Option<String>,
+    ) -> Stmt {
+        let open_paren_span = self.lexer.span();
+        self.expect(Token::OpenParen);
+        let mut params = Vec::new();
+        if !self.eat(Token::CloseParen) {
+            loop {
+                params.push(if let Token::Identifier(ref ident) = self.current_token {
+                    let ident = ident.clone();
+                    if params.contains(&ident) {
+                        self.source.errors.add_error(SyntaxError::new(
+                            format!("Duplicate parameter {}", ident),
+                            self.lexer.span(),
+                        ));
+                    }
+                    self.next();
+                    ident
+                } else if let Some(keyword) = self.current_token.keyword() {
+                    self.reserved_keyword(keyword);
+                    return Stmt::Error;
+                } else {
+                    self.unexpected();
+                    return Stmt::Error;
+                });
+
+                if self.eat(Token::CloseParen) {
+                    break;
+                } else if self.current_token == Token::Eof {
+                    self.unmatched("(", open_paren_span);
+                    break;
+                } else if !self.eat(Token::Comma) {
+                    self.unexpected();
+                    break;
+                }
+            }
+        }
+
+        // a trailing expression without a semicolon is returned from the function
+        let (mut body, value) = self.parse_braced_body_with_value(true);
+        if let Some((expr, line)) = value {
+            body.push(Stmt::ReturnStmt { expr, line });
+        }
+
+        let (line, _) = self.source.line_col(start);
+        Stmt::FnDeclaration {
+            body,
+            ident,
+            params,
+            line,
+            doc,
+            span: start..self.prev_token_end,
+        }
+    }
+
+    fn parse_return_stmt(&mut self) -> Stmt {
+        let line = self.current_line;
+        self.expect(Token::Return);
+        let expr = self.parse_expr();
+        self.expect(Token::Semi);
+        Stmt::ReturnStmt { expr, line }
+    }
+
+    /// Parses a `break;` or `continue;` statement.
+    /// Using them outside of a loop is reported by the resolver.
+    fn parse_break_or_continue_stmt(&mut self) -> Stmt {
+        let start = self.lexer.span().start;
+        let line = self.current_line;
+        let is_break = self.current_token == Token::Break;
+        self.next();
+        self.expect(Token::Semi);
+
+        let span = start..self.prev_token_end;
+        if is_break {
+            Stmt::BreakStmt { span, line }
+        } else {
+            Stmt::ContinueStmt { span, line }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_debug_snapshot;
+
+    fn stmt(source: &str) -> Stmt {
+        let source = source.into();
+        let ast = Parser::new(&source).parse_declaration();
+        assert!(source.has_no_errors());
+        ast
+    }
+
+    #[test]
+    fn test_block_stmt() {
+        assert_debug_snapshot!("block-stmt", stmt("{ 1; }"));
+        assert_debug_snapshot!("block-stmt-multiple", stmt("{ 1; 2; }"));
+        assert_debug_snapshot!("block-stmt-nested", stmt("{ 1; 2; { 3; } }"));
+    }
+
+    #[test]
+    fn test_empty_stmt() {
+        assert_debug_snapshot!("empty-stmt", stmt(";"));
+        assert_debug_snapshot!("empty-stmt-in-block", stmt("{ ;; }"));
+    }
+
+    /// Parses the `source` and returns the messages and spans of all reported errors.
+    fn errors(source: &str) -> Vec<(String, std::ops::Range<usize>)> {
+        let source = source.into();
+        Parser::new(&source).parse_program();
+        source
+            .errors
+            .errors()
+            .iter()
+            .map(|error| (error.message().to_string(), error.span()))
+            .collect()
+    }
+
+    #[test]
+    fn test_unmatched_delimiters() {
+        let unmatched_brace = ("Unmatched '{' opened here".to_string(), 0..1);
+        assert_eq!(errors("{ 1;"), vec![unmatched_brace.clone()]);
+        assert_eq!(errors("{ { 1; }"), vec![unmatched_brace]);
+        assert_eq!(
+            errors("fn foo() { if x { 1; }"),
+            vec![("Unmatched '{' opened here".to_string(), 9..10)]
+        );
+        assert_eq!(
+            errors("while x {"),
+            vec![("Unmatched '{' opened here".to_string(), 8..9)]
+        );
+        assert_eq!(
+            errors("foo(1, 2"),
+            vec![
+                ("Unmatched '(' opened here".to_string(), 3..4),
+                ("Unexpected token".to_string(), 8..8)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string_lit() {
+        assert_eq!(
+            errors(r#"let s = "abc;"#)[0],
+            ("Unterminated string literal".to_string(), 8..13)
+        );
+        assert_eq!(
+            errors("let s = \"a\nb;\nlet t = 1;")[0],
+            ("Unterminated string literal".to_string(), 8..24)
+        );
+        assert!(errors(r#"let s = "abc";"#).is_empty());
+    }
+
+    #[test]
+    fn test_keyword_as_identifier() {
+        assert_eq!(
+            errors("let while = 1;")[0],
+            ("`while` is a reserved keyword".to_string(), 4..9)
+        );
+        assert_eq!(
+            errors("fn if() {}")[0],
+            ("`if` is a reserved keyword".to_string(), 3..5)
+        );
+        assert_eq!(
+            errors("fn f(a, true) {}")[0],
+            ("`true` is a reserved keyword".to_string(), 8..12)
+        );
+        assert_eq!(
+            errors("let (x, return) = (1, 2);")[0],
+            ("`return` is a reserved keyword".to_string(), 8..14)
+        );
+        assert_eq!(
+            errors("let break = 1;")[0],
+            ("`break` is a reserved keyword".to_string(), 4..9)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_params() {
+        assert_eq!(
+            errors("fn f(a, a) {}"),
+            vec![("Duplicate parameter a".to_string(), 8..9)]
+        );
+        assert_eq!(
+            errors("fn f(a, b, a, b) {}")[1],
+            ("Duplicate parameter b".to_string(), 14..15)
+        );
+        assert!(errors("fn f(a, b) {}").is_empty());
+    }
+
+    #[test]
+    fn test_string_lit_escapes() {
+        assert_eq!(
+            stmt(r#"let s = "\u{41}\x42\n\"";"#),
+            Stmt::LetDeclaration {
+                ident: "s".to_string(),
+                initializer: Expr::StringLit("AB\n\"".to_string()),
+                doc: None,
+                span: 0..25,
+                line: 1,
+            }
+        );
+        assert_eq!(
+            errors(r#"let s = "\u{110000}";"#)[0],
+            (
+                "Invalid escape sequence in string literal".to_string(),
+                8..20
+            )
+        );
+        assert_eq!(
+            errors(r#"let s = "\u{D800}";"#)[0],
+            (
+                "Invalid escape sequence in string literal".to_string(),
+                8..18
+            )
+        );
+    }
+
+    #[test]
+    fn test_if_else_stmt() {
+        assert_debug_snapshot!(
+            "if-stmt",
+            stmt(
+                r#"
+                if condition {
+                    if_block();
+                }"#
+            )
+        );
+        assert_debug_snapshot!(
+            "if-else-stmt",
+            stmt(
+                r#"
+                if condition {
+                    if_block();
+                } else {
+                    else_block();
+                }"#
+            )
+        );
+        assert_debug_snapshot!("if-else-stmt-empty", stmt(r#"if condition {} else {}"#));
+        assert_debug_snapshot!(
+            "if-else-if-stmt",
+            stmt(r#"if a { 1; } else if b { 2; } else { 3; }"#)
+        );
+        assert_debug_snapshot!("if-stmt-without-braces", stmt(r#"if (cond) x();"#));
+        assert_debug_snapshot!(
+            "if-else-stmt-without-braces",
+            stmt(r#"if (a) b(); else c();"#)
+        );
+        // else binds to the nearest if
+        assert_debug_snapshot!(
+            "if-else-stmt-dangling-else",
+            stmt(r#"if (a) if (b) c(); else d();"#)
+        );
+    }
+
+    #[test]
+    fn test_while_stmt() {
+        assert_debug_snapshot!("while-stmt", stmt(r#"while true { while_block(); }"#));
+        assert_debug_snapshot!("while-stmt-empty", stmt(r#"while true {}"#));
+        assert_debug_snapshot!("while-stmt-without-braces", stmt(r#"while (cond) x();"#));
+    }
+
+    #[test]
+    fn test_body_without_braces_requires_parenthesized_condition() {
+        let error = |span| {
+            (
+                "Expected '{', braces can only be omitted if the condition is enclosed in parentheses"
+                    .to_string(),
+                span,
+            )
+        };
+        // the start of the body would be parsed as a call or as a binary expression
+        assert_eq!(errors("while i < n (i += 1);"), vec![error(20..21)]);
+        assert_eq!(errors("while (i < n) (i += 1);"), vec![error(22..23)]);
+        assert_eq!(errors("if x -y;"), vec![error(7..8)]);
+        assert_eq!(errors("if (x) -y;"), vec![error(9..10)]);
+        assert_eq!(errors("if (a) + (b) c();"), vec![error(13..14)]);
+        assert_eq!(errors("if x y();"), vec![error(5..6)]);
+
+        assert!(errors("while ((i) < n) i += 1;").is_empty());
+        assert!(errors("if (a, b) c(); else -d;").is_empty());
+    }
+
+    #[test]
+    fn test_do_while_stmt() {
+        assert_debug_snapshot!("do-while-stmt", stmt(r#"do { do_block(); } while x;"#));
+    }
+
+    #[test]
+    fn test_let_declaration() {
+        assert_debug_snapshot!("let-declaration", stmt("let x = 2;"));
+        assert_debug_snapshot!("let-declaration-with-expr", stmt("let x = 1 + 2;"));
+    }
+
+    #[test]
+    fn test_tuple_declaration() {
+        assert_eq!(
+            stmt("let (x, y) = f();"),
+            Stmt::TupleDeclaration {
+                idents: vec!["x".to_string(), "y".to_string()],
+                initializer: Expr::FnCall {
+                    callee: Box::new(Expr::Identifier("f".to_string())),
+                    args: Vec::new(),
+                    span: 13..16,
+                },
+                span: 0..17,
+                line: 1,
+            }
+        );
+        assert_eq!(
+            errors("let (x, x) = (1, 2);"),
+            vec![("Duplicate variable x".to_string(), 8..9)]
+        );
+        assert_eq!(
+            errors("let (x, y = (1, 2);")[0],
+            ("Unexpected token".to_string(), 12..13)
+        );
+    }
+
+    #[test]
+    fn test_fn_declaration() {
+        assert_debug_snapshot!("fn-declaration", stmt("fn foo() {}"));
+        assert_debug_snapshot!("fn-declaration-with-params", stmt("fn foo(a, b, c) {}"));
+        assert_debug_snapshot!(
+            "fn-declaration-with-params-and-body",
+            stmt("fn foo(a, b, c) { a + b + c; }")
+        );
+        assert_debug_snapshot!(
+            "fn-declaration-trailing-expr",
+            stmt("fn double(x) { x * 2 }")
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let source = r#"
+            // Adds two numbers.
+            //Returns the sum.
+            fn add(a, b) { return a + b; }
+            // Not attached.
+            add(1, 2);
+            let x = 1; // Not attached either.
+            // Attached.
+            let y = 2;"#
+            .into();
+        let mut parser = Parser::new(&source);
+        parser.set_doc_comments(true);
+        let body = match parser.parse_program() {
+            Stmt::FnDeclaration { body, .. } => body,
+            _ => unreachable!(),
+        };
+        assert!(source.has_no_errors());
+        match &body[0] {
+            Stmt::FnDeclaration { doc, .. } => assert_eq!(
+                doc.as_deref(),
+                Some("Adds two numbers.\nReturns the sum.")
+            ),
+            _ => unreachable!(),
+        }
+        assert!(matches!(&body[2], Stmt::LetDeclaration { doc: None, .. }));
+        // trailing comments are not attached to the next declaration
+        match &body[3] {
+            Stmt::LetDeclaration { doc, .. } => assert_eq!(doc.as_deref(), Some("Attached.")),
+            _ => unreachable!(),
+        }
+
+        // disabled by default
+        assert!(stmt("// Comment.\nlet x = 1;").structurally_eq(&stmt("let x = 1;")));
+        assert!(matches!(
+            stmt("// Comment.\nlet x = 1;"),
+            Stmt::LetDeclaration { doc: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_return_stmt() {
+        assert_debug_snapshot!("return-stmt", stmt("return 1;"));
+        assert_debug_snapshot!("return-stmt-with-expr", stmt("return 1 + 2;"));
+    }
+
+    #[test]
+    fn test_break_and_continue_stmts() {
+        assert_debug_snapshot!(
+            "break-continue-stmt",
+            stmt("while x {\n    if y { break; }\n    continue;\n}")
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Call graph pass.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use ella_parser::ast::{Expr, Stmt};
+use ella_parser::visitor::{walk_expr, walk_stmt, Visitor};
+
+use crate::resolve::{ResolveResult, Symbol};
+
+/// Maps each function declaration to the function declarations it directly calls.
+///
+/// Identifiers are matched to function declarations using the symbols of the [`Resolver`](crate::resolve::Resolver):
+/// functions with the same name in different scopes are different functions and identifiers that refer to other
+/// variables (e.g. parameters) are not calls. A function that is referenced without being called (e.g. passed as an
+/// argument to `map`) is also considered called because it may be called indirectly.
+pub struct CallGraph<'a> {
+    /// The top level function (usually `<global>`).
+    root: &'a Stmt,
+    /// All function declarations, in the order they are visited (`root` first).
+    functions: Vec<&'a Stmt>,
+    calls: HashMap<*const Stmt, HashSet<*const Stmt>>,
+    resolve_result: ResolveResult<'a>,
+    /// The stack of the functions that are currently visited. The last element is the innermost function.
+    fn_stack: Vec<*const Stmt>,
+    /// The symbols referenced by every function. They are matched to declarations once all functions are visited
+    /// because a function can reference a function declared after it.
+    references: Vec<(*const Stmt, Rc<RefCell<Symbol>>)>,
+}
+
+impl<'a> CallGraph<'a> {
+    /// Builds the call graph of `program`. `program` should be the top level [`Stmt::FnDeclaration`] returned by
+    /// [`ella_parser::parser::Parser::parse_program`] and `resolve_result` the result of resolving it.
+    pub fn new(program: &'a Stmt, resolve_result: ResolveResult<'a>) -> Self {
+        let mut call_graph = Self {
+            root: program,
+            functions: Vec::new(),
+            calls: HashMap::new(),
+            resolve_result,
+            fn_stack: Vec::new(),
+            references: Vec::new(),
+        };
+        call_graph.visit_stmt(program);
+
+        // Only keep the symbols that refer to declared functions.
+        let declarations: HashMap<*const RefCell<Symbol>, *const Stmt> = call_graph
+            .functions
+            .iter()
+            .filter_map(|&func| {
+                let symbol = resolve_result.lookup_declaration(func)?;
+                Some((Rc::as_ptr(symbol), func as *const Stmt))
+            })
+            .collect();
+        for (caller, symbol) in std::mem::take(&mut call_graph.references) {
+            if let Some(&callee) = declarations.get(&Rc::as_ptr(&symbol)) {
+                call_graph.calls.get_mut(&caller).unwrap().insert(callee);
+            }
+        }
+        call_graph
+    }
+
+    /// Returns all function declarations (including the top level function), in declaration order.
+    pub fn functions(&self) -> &[&'a Stmt] {
+        &self.functions
+    }
+
+    /// Returns the function declarations directly called by the function declaration `func`, in declaration order.
+    pub fn callees(&self, func: &Stmt) -> Vec<&'a Stmt> {
+        match self.calls.get(&(func as *const Stmt)) {
+            Some(callees) => self
+                .functions
+                .iter()
+                .copied()
+                .filter(|&callee| callees.contains(&(callee as *const Stmt)))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the function declarations that can never be called from the top level function, in declaration order.
+    pub fn unreachable_functions(&self) -> Vec<&'a Stmt> {
+        let mut reachable = HashSet::new();
+        let mut worklist = vec![self.root as *const Stmt];
+        while let Some(func) = worklist.pop() {
+            if reachable.insert(func) {
+                if let Some(callees) = self.calls.get(&func) {
+                    worklist.extend(callees.iter().copied());
+                }
+            }
+        }
+
+        self.functions
+            .iter()
+            .copied()
+            .filter(|&func| !reachable.contains(&(func as *const Stmt)))
+            .collect()
+    }
+}
+
+impl<'a> Visitor<'a> for CallGraph<'a> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Identifier(_) => {
+                if let (Some(&current_fn), Some(symbol)) = (
+                    self.fn_stack.last(),
+                    self.resolve_result.lookup_referenced_symbol(expr),
+                ) {
+                    self.references.push((current_fn, Rc::clone(symbol)));
+                }
+            }
+            // Function expressions have no name. Their calls are attributed to the enclosing function.
+            Expr::FnLit(func) => {
+                walk_stmt(self, func);
+                return;
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        if let Stmt::FnDeclaration { .. } = stmt {
+            self.functions.push(stmt);
+            self.calls.entry(stmt as *const Stmt).or_default();
+            self.fn_stack.push(stmt as *const Stmt);
+            walk_stmt(self, stmt);
+            self.fn_stack.pop();
+        } else {
+            walk_stmt(self, stmt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::Resolver;
+    use ella_parser::parser::Parser;
+
+    /// Returns the identifier of the function declaration `func`.
+    fn ident(func: &Stmt) -> &str {
+        match func {
+            Stmt::FnDeclaration { ident, .. } => ident,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the identifiers of every function of `source` with the identifiers of the functions it calls (in
+    /// declaration order) and the identifiers of the unreachable functions.
+    fn call_graph(source: &str) -> (Vec<(String, Vec<String>)>, Vec<String>) {
+        let source = source.into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+
+        let graph = CallGraph::new(&ast, resolver.resolve_result());
+        let calls = graph
+            .functions()
+            .iter()
+            .map(|func| {
+                let callees = graph.callees(func).into_iter().map(ident);
+                (ident(func).to_string(), callees.map(String::from).collect())
+            })
+            .collect();
+        let unreachable = graph.unreachable_functions().into_iter().map(ident);
+        (calls, unreachable.map(String::from).collect())
+    }
+
+    fn calls(entries: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+        entries
+            .iter()
+            .map(|(func, callees)| {
+                let callees = callees.iter().map(|callee| callee.to_string()).collect();
+                (func.to_string(), callees)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_calls() {
+        let (graph, _) = call_graph(
+            r#"
+            fn double(x) { return x * 2; }
+            fn quadruple(x) { return double(double(x)); }
+            let y = quadruple(1);"#,
+        );
+        assert_eq!(
+            graph,
+            calls(&[
+                ("<global>", &["quadruple"]),
+                ("double", &[]),
+                ("quadruple", &["double"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_calls_resolved_by_declaration() {
+        // functions with the same name in different scopes are different functions
+        let (graph, unreachable) = call_graph(
+            r#"
+            fn outer() {
+                fn helper() { return 1; }
+                return helper();
+            }
+            fn other() {
+                fn helper() { return 2; }
+                return 0;
+            }
+            outer();"#,
+        );
+        assert_eq!(
+            graph,
+            calls(&[
+                ("<global>", &["outer"]),
+                ("outer", &["helper"]),
+                ("helper", &[]),
+                ("other", &[]),
+                ("helper", &[]),
+            ])
+        );
+        assert_eq!(unreachable, vec!["other", "helper"]);
+
+        // parameters and local variables with the name of a function are not calls
+        let (graph, _) = call_graph(
+            r#"
+            fn f() { return 1; }
+            fn g(f) { return f(); }
+            fn h() { let f = 2; return f; }
+            let x = g(h);"#,
+        );
+        assert_eq!(
+            graph,
+            calls(&[
+                ("<global>", &["g", "h"]),
+                ("f", &[]),
+                ("g", &[]),
+                ("h", &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_unreachable_functions() {
+        let (_, unreachable) = call_graph(
+            r#"
+            fn used() { return helper(); }
+            fn helper() { return 1; }
+            fn unused() { return helper() + unused(); }
+            used();"#,
+        );
+        assert_eq!(unreachable, vec!["unused"]);
+
+        // functions passed as values are reachable
+        let (_, unreachable) = call_graph(
+            r#"
+            fn apply(f, x) { return f(x); }
+            fn double(x) { return x * 2; }
+            apply(double, 1);"#,
+        );
+        assert!(unreachable.is_empty());
+    }
+}
@@ -1,3 +1,4 @@
 //! AST passes and transformations.
 
+pub mod call_graph;
 pub mod resolve;
@@ -1,366 +1,1704 @@
-//! Variable resolution pass.
-
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::ops::Range;
-use std::rc::Rc;
-
-use ella_parser::ast::{Expr, Stmt};
-use ella_parser::visitor::{walk_expr, Visitor};
-use ella_source::{Source, SyntaxError};
-use ella_value::BuiltinVars;
-
-/// Result of running [`Resolver`] pass.
-/// See [`Resolver::resolve_result`].
-#[derive(Debug, Clone, Copy)]
-pub struct ResolveResult<'a> {
-    symbol_table: &'a SymbolTable,
-    resolved_symbol_table: &'a ResolvedSymbolTable,
-}
-
-impl<'a> ResolveResult<'a> {
-    /// Lookup a [`Stmt`] (by reference) to get variable resolution metadata.
-    pub fn lookup_declaration(&self, stmt: &Stmt) -> Option<&'a Rc<RefCell<Symbol>>> {
-        self.symbol_table.get(&(stmt as *const Stmt))
-    }
-
-    /// Lookup a [`Expr`] (by reference) to get variable resolution metadata.
-    pub fn lookup_identifier(&self, expr: &Expr) -> Option<&'a ResolvedSymbol> {
-        self.resolved_symbol_table.get(&(expr as *const Expr))
-    }
-}
-
-/// Represents a symbol (created using `let` or `fn` declaration statement).
-#[derive(Debug, Clone, PartialEq)]
-pub struct Symbol {
-    ident: String,
-    scope_depth: u32,
-    pub is_captured: bool,
-    pub upvalues: Vec<ResolvedUpValue>,
-}
-
-/// Represents a resolved upvalue (captured variable).
-#[derive(Debug, Clone, PartialEq)]
-pub struct ResolvedUpValue {
-    pub is_local: bool,
-    pub index: i32,
-}
-
-/// Represents a resolved symbol (identifier or function call expressions).
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct ResolvedSymbol {
-    /// The offset relative to the current function's offset (`current_func_offset`).
-    pub offset: i32,
-    /// Optimization to emit `ldglobal` and `stglobal` instructions.
-    pub is_global: bool,
-    pub is_upvalue: bool,
-}
-
-/// A [`HashMap`] mapping [`Stmt`]s to [`Symbol`]s.
-pub type SymbolTable = HashMap<*const Stmt, Rc<RefCell<Symbol>>>;
-/// A [`HashMap`] mapping [`Expr`] to [`ResolvedSymbol`]s.
-pub type ResolvedSymbolTable = HashMap<*const Expr, ResolvedSymbol>;
-
-/// Variable resolution pass.
-pub struct Resolver<'a> {
-    /// A [`HashMap`] mapping all declaration [`Stmt`]s to [`Symbol`]s.
-    symbol_table: SymbolTable,
-    /// A [`HashMap`] mapping all [`Expr::Identifier`]s to [`ResolvedSymbol`]s.
-    resolved_symbol_table: ResolvedSymbolTable,
-    /// A [`Vec`] of symbols that are currently in (lexical) scope.
-    accessible_symbols: Vec<Rc<RefCell<Symbol>>>,
-    /// A stack of current function scope depths. `0` is global scope.
-    function_scope_depths: Vec<u32>,
-    /// Every time a new function scope is created, `current_func_offset` should be set to `self.resolved_symbols.len()`.
-    /// When exiting a function scope, the value should be reverted to previous value.
-    current_func_offset: i32,
-    /// A stack of current function upvalues.
-    function_upvalues: Vec<Vec<ResolvedUpValue>>,
-    source: &'a Source<'a>,
-}
-
-impl<'a> Resolver<'a> {
-    /// Create a new empty `Resolver`.
-    pub fn new(source: &'a Source) -> Self {
-        Self {
-            symbol_table: SymbolTable::new(),
-            resolved_symbol_table: ResolvedSymbolTable::new(),
-            accessible_symbols: Vec::new(),
-            function_scope_depths: vec![0],
-            current_func_offset: 0,
-            function_upvalues: vec![Vec::new()],
-            source,
-        }
-    }
-
-    /// Create a new `Resolver` with existing accessible symbols.
-    /// This method is used to implement REPL functionality (for restoring global variables).
-    /// See [`Self::accessible_symbols`].
-    pub fn new_with_existing_accessible_symbols(
-        source: &'a Source,
-        resolved_symbols: Vec<Rc<RefCell<Symbol>>>,
-    ) -> Self {
-        Self {
-            accessible_symbols: resolved_symbols,
-            ..Self::new(source)
-        }
-    }
-
-    /// Creates a [`ResolveResult`].
-    pub fn resolve_result(&self) -> ResolveResult {
-        ResolveResult {
-            symbol_table: &self.symbol_table,
-            resolved_symbol_table: &self.resolved_symbol_table,
-        }
-    }
-
-    /// Returns the list of accessible symbols.
-    /// This method is used to implement REPL functionality (for restoring global variables).
-    /// See [`Self::new_with_existing_accessible_symbols`].
-    pub fn accessible_symbols(&self) -> &Vec<Rc<RefCell<Symbol>>> {
-        &self.accessible_symbols
-    }
-
-    /// Enter a scope.
-    fn enter_scope(&mut self) {
-        *self.function_scope_depths.last_mut().unwrap() += 1;
-    }
-
-    /// Exit a scope. Removes all declarations introduced in previous scope.
-    fn exit_scope(&mut self) {
-        *self.function_scope_depths.last_mut().unwrap() -= 1;
-
-        // Remove all symbols in current scope.
-        self.accessible_symbols = self
-            .accessible_symbols
-            .iter()
-            .filter(|symbol| {
-                symbol.borrow().scope_depth <= *self.function_scope_depths.last().unwrap()
-            })
-            .cloned()
-            .collect();
-    }
-
-    /// Adds a symbol to `self.accessible_symbols` and `self.symbol_table`.
-    fn add_symbol(&mut self, ident: String, stmt: Option<&Stmt>) {
-        let symbol = Rc::new(RefCell::new(Symbol {
-            ident,
-            scope_depth: *self.function_scope_depths.last().unwrap(),
-            is_captured: false, // not captured by default
-            upvalues: Vec::new(),
-        }));
-        self.accessible_symbols.push(Rc::clone(&symbol));
-        if let Some(stmt) = stmt {
-            self.symbol_table.insert(stmt as *const Stmt, symbol);
-        }
-    }
-
-    /// Returns the function scope depth of the specified `scope_depth`.
-    fn find_function_scope_depth(&self, scope_depth: u32) -> usize {
-        for (i, function_scope_depth) in self.function_scope_depths.iter().enumerate().rev() {
-            if *function_scope_depth < scope_depth {
-                return i + 1;
-            }
-        }
-        return 0;
-    }
-
-    /// Returns `true` if both scope depths are in the same function (e.g. using block statements). Returns `false` otherwise.
-    fn in_same_function_scope(&self, first: u32, second: u32) -> bool {
-        self.find_function_scope_depth(first) == self.find_function_scope_depth(second)
-    }
-
-    /// Returns a `Some((usize, Rc<RefCell<Symbol>>))` or `None` if cannot be resolved.
-    /// The `usize` is the offset of the variable.
-    ///
-    /// # Params
-    /// * `ident` - The identifier to resolve.
-    /// * `span` - The span of the expression to resolve. This is used for error reporting in case the variable could not be resolved.
-    fn resolve_symbol(
-        &mut self,
-        ident: &str,
-        span: Range<usize>,
-    ) -> Option<(usize, Rc<RefCell<Symbol>>)> {
-        for (i, symbol) in self.accessible_symbols.iter().enumerate().rev() {
-            if symbol.borrow().ident == ident {
-                if self.find_function_scope_depth(symbol.borrow().scope_depth) == 0 {
-                    return Some((i, symbol.clone()));
-                }
-                else if self.in_same_function_scope(
-                    symbol.borrow().scope_depth,
-                    *self.function_scope_depths.last().unwrap(),
-                ) {
-                    return Some((i - self.current_func_offset as usize, symbol.clone()));
-                } else {
-                    // capture outer variable
-                    symbol.borrow_mut().is_captured = true;
-
-                    // thread upvalue in enclosing functions
-                    let mut prev_upvalue_index = 0;
-                    for scope_depth in self.find_function_scope_depth(symbol.borrow().scope_depth)
-                        + 1
-                        ..=self
-                            .find_function_scope_depth(*self.function_scope_depths.last().unwrap())
-                    {
-                        let is_local = scope_depth == symbol.borrow().scope_depth as usize + 1;
-                        self.function_upvalues[scope_depth as usize].push(ResolvedUpValue {
-                            is_local,
-                            index: if is_local {
-                                i as i32
-                            } else {
-                                prev_upvalue_index as i32
-                            },
-                        });
-
-                        prev_upvalue_index = self.function_upvalues[scope_depth as usize].len() - 1;
-                    }
-
-                    return Some((
-                        (self.function_upvalues.last().unwrap().len() - 1) as usize,
-                        symbol.clone(),
-                    ));
-                }
-            }
-        }
-        self.source.errors.add_error(SyntaxError::new(
-            format!("Cannot resolve symbol {}", ident),
-            span,
-        ));
-        None
-    }
-
-    /// Resolve a top-level function [`Stmt`]. This should be used over calling `visit_stmt`.
-    pub fn resolve_top_level(&mut self, func: &'a Stmt) {
-        match func {
-            Stmt::FnDeclaration { body, .. } => {
-                for stmt in body {
-                    self.visit_stmt(stmt);
-                }
-            }
-            _ => panic!("func is not a Stmt::FnDeclaration"),
-        }
-    }
-
-    /// Resolve builtin variables.
-    pub fn resolve_builtin_vars(&mut self, builtin_vars: &BuiltinVars) {
-        for (ident, _value) in &builtin_vars.values {
-            self.add_symbol(ident.clone(), None);
-        }
-    }
-}
-
-impl<'a> Visitor<'a> for Resolver<'a> {
-    fn visit_expr(&mut self, expr: &'a Expr) {
-        walk_expr(self, expr);
-
-        match expr {
-            Expr::Identifier(ident) => {
-                let symbol = self.resolve_symbol(ident, 0..0);
-                if let Some((offset, symbol)) = symbol {
-                    self.resolved_symbol_table.insert(
-                        expr as *const Expr,
-                        ResolvedSymbol {
-                            offset: offset as i32,
-                            is_global: self.find_function_scope_depth(symbol.borrow().scope_depth)
-                                == 0,
-                            is_upvalue: self.find_function_scope_depth(
-                                *self.function_scope_depths.last().unwrap(),
-                            ) > self
-                                .find_function_scope_depth(symbol.borrow().scope_depth),
-                        },
-                    );
-                }
-            }
-            Expr::FnCall { callee, args } => {
-                self.visit_expr(callee);
-                for expr in args {
-                    self.visit_expr(expr);
-                }
-            }
-            _ => {}
-        }
-    }
-
-    fn visit_stmt(&mut self, stmt: &'a Stmt) {
-        // Do not use default walking logic.
-
-        match stmt {
-            Stmt::LetDeclaration { ident, initializer } => {
-                self.visit_expr(initializer);
-                self.add_symbol(ident.clone(), Some(stmt));
-            }
-            Stmt::FnDeclaration {
-                ident,
-                params,
-                body,
-            } => {
-                self.add_symbol(ident.clone(), Some(stmt)); // Add symbol first to allow for recursion.
-
-                let old_func_offset = self.current_func_offset;
-
-                self.current_func_offset = self.accessible_symbols.len() as i32;
-                self.function_upvalues.push(Vec::new());
-                self.function_scope_depths
-                    .push(*self.function_scope_depths.last().unwrap());
-
-                self.enter_scope();
-                // add arguments
-                for param in params {
-                    self.add_symbol(param.clone(), Some(stmt));
-                }
-
-                for stmt in body {
-                    self.visit_stmt(stmt);
-                }
-                self.exit_scope();
-
-                // patch self.symbol_table with upvalues
-                self.symbol_table
-                    .get(&(stmt as *const Stmt))
-                    .unwrap()
-                    .borrow_mut()
-                    .upvalues = self.function_upvalues.pop().unwrap();
-                self.function_scope_depths.pop();
-
-                self.current_func_offset = old_func_offset;
-            }
-            Stmt::Block(body) => {
-                self.enter_scope();
-                for stmt in body {
-                    self.visit_stmt(stmt);
-                }
-                self.exit_scope();
-            }
-            Stmt::IfElseStmt {
-                condition,
-                if_block,
-                else_block,
-            } => {
-                self.visit_expr(condition);
-                self.enter_scope();
-                for stmt in if_block {
-                    self.visit_stmt(stmt);
-                }
-                self.exit_scope();
-                if let Some(else_block) = else_block {
-                    self.enter_scope();
-                    for stmt in else_block {
-                        self.visit_stmt(stmt);
-                    }
-                    self.exit_scope();
-                }
-            }
-            Stmt::WhileStmt { condition, body } => {
-                self.visit_expr(condition);
-                self.enter_scope();
-                for stmt in body {
-                    self.visit_stmt(stmt);
-                }
-                self.exit_scope();
-            }
-            Stmt::ExprStmt(expr) => self.visit_expr(expr),
-            Stmt::ReturnStmt(expr) => self.visit_expr(expr),
-            Stmt::Error => {}
-        }
-    }
-}
+//! Variable resolution pass.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use ella_parser::ast::{Expr, Stmt};
+use ella_parser::lexer::Token;
+use ella_parser::visitor::{walk_expr, walk_stmt, Visitor};
+use ella_source::{Source, SyntaxError};
+use ella_value::object::{Arity, ObjKind};
+use ella_value::{BuiltinVars, Value};
+
+/// The maximum number of local variables (including parameters and temporaries) in a function.
+/// This is also the maximum number of global variables (including builtins and temporaries) at the top level.
+/// Local and global variables are addressed with a single byte operand (e.g. `ldloc` and `ldglobal`).
+pub const MAX_LOCALS: usize = 256;
+/// The maximum number of variables in a tuple declaration (the operand of the `unpack` instruction is a `u8`).
+pub const MAX_TUPLE_DECLARATION_LEN: usize = u8::MAX as usize;
+
+/// Result of running [`Resolver`] pass.
+/// See [`Resolver::resolve_result`].
+#[derive(Debug, Clone, Copy)]
+pub struct ResolveResult<'a> {
+    symbol_table: &'a SymbolTable,
+    destructured_symbol_table: &'a DestructuredSymbolTable,
+    resolved_symbol_table: &'a ResolvedSymbolTable,
+    referenced_symbol_table: &'a ReferencedSymbolTable,
+}
+
+impl<'a> ResolveResult<'a> {
+    /// Lookup a [`Stmt`] (by reference) to get variable resolution metadata.
+    pub fn lookup_declaration(&self, stmt: &Stmt) -> Option<&'a Rc<RefCell<Symbol>>> {
+        self.symbol_table.get(&(stmt as *const Stmt))
+    }
+
+    /// Lookup a [`Stmt::TupleDeclaration`] (by reference) to get the variable resolution metadata of every declared
+    /// variable (in declaration order).
+    pub fn lookup_tuple_declaration(&self, stmt: &Stmt) -> Option<&'a [Rc<RefCell<Symbol>>]> {
+        self.destructured_symbol_table
+            .get(&(stmt as *const Stmt))
+            .map(Vec::as_slice)
+    }
+
+    /// Lookup a [`Expr`] (by reference) to get variable resolution metadata.
+    pub fn lookup_identifier(&self, expr: &Expr) -> Option<&'a ResolvedSymbol> {
+        self.resolved_symbol_table.get(&(expr as *const Expr))
+    }
+
+    /// Lookup an [`Expr::Identifier`] (by reference) to get the [`Symbol`] it refers to.
+    /// The symbol can be compared (with [`Rc::ptr_eq`]) with the result of [`Self::lookup_declaration`] to find the
+    /// declaration of the identifier.
+    pub fn lookup_referenced_symbol(&self, expr: &Expr) -> Option<&'a Rc<RefCell<Symbol>>> {
+        self.referenced_symbol_table.get(&(expr as *const Expr))
+    }
+
+    /// Lookup an [`Expr::Block`] (by reference) to get the stack slot where the value of the block is stored.
+    pub fn lookup_block(&self, expr: &Expr) -> Option<&'a ResolvedSymbol> {
+        self.resolved_symbol_table.get(&(expr as *const Expr))
+    }
+}
+
+/// Coarse information about the value bound to a [`Symbol`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// Declared with a `fn` declaration.
+    Fn,
+    /// Declared with a `let` declaration with a number literal initializer.
+    Number,
+    /// Declared with a `let` declaration with a bool literal initializer.
+    Bool,
+    /// Declared with a `let` declaration with a string literal initializer.
+    String,
+    /// Declared with a `let` declaration with an array literal initializer.
+    Array,
+    /// The value is not statically known (e.g. a function parameter or a reassigned variable).
+    Unknown,
+}
+
+impl SymbolKind {
+    /// Returns the [`SymbolKind`] of a `let` declaration with the specified `initializer`.
+    fn from_initializer(initializer: &Expr) -> Self {
+        match initializer {
+            Expr::NumberLit(_) => Self::Number,
+            Expr::BoolLit(_) => Self::Bool,
+            Expr::StringLit(_) => Self::String,
+            Expr::ArrayLit(_) => Self::Array,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Returns the [`Arity`] of a builtin symbol with the specified `value` or `None` if not a function.
+    fn arity_from_value(value: &Value) -> Option<Arity> {
+        match value {
+            Value::Object(obj) => match &obj.kind {
+                ObjKind::Fn(func) => Some(Arity::Exact(func.arity)),
+                ObjKind::Closure(closure) => Some(Arity::Exact(closure.func.arity)),
+                ObjKind::NativeFn(native_fn) => Some(native_fn.arity),
+                ObjKind::Str(_)
+                | ObjKind::Array(_)
+                | ObjKind::StringBuilder(_)
+                | ObjKind::Map(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the [`SymbolKind`] of a builtin symbol with the specified `value`.
+    fn from_value(value: &Value) -> Self {
+        match value {
+            Value::Number(_) => Self::Number,
+            Value::Bool(_) => Self::Bool,
+            Value::Nil => Self::Unknown,
+            Value::Object(obj) => match obj.kind {
+                ObjKind::Str(_) => Self::String,
+                ObjKind::Array(_) => Self::Array,
+                ObjKind::StringBuilder(_) | ObjKind::Map(_) => Self::Unknown,
+                ObjKind::Fn(_) | ObjKind::Closure(_) | ObjKind::NativeFn(_) => Self::Fn,
+            },
+        }
+    }
+}
+
+/// Represents a symbol (created using `let` or `fn` declaration statement).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    ident: String,
+    scope_depth: u32,
+    kind: SymbolKind,
+    /// The number of arguments the function accepts if the symbol is a function.
+    arity: Option<Arity>,
+    /// `true` if the symbol was declared by [`Resolver::resolve_builtin_vars`].
+    is_builtin: bool,
+    pub is_captured: bool,
+    pub upvalues: Vec<ResolvedUpValue>,
+}
+
+/// Represents a resolved upvalue (captured variable).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedUpValue {
+    pub is_local: bool,
+    pub index: i32,
+}
+
+/// Represents a resolved symbol (identifier or function call expressions).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolvedSymbol {
+    /// The offset relative to the current function's offset (`current_func_offset`).
+    pub offset: i32,
+    /// Optimization to emit `ldglobal` and `stglobal` instructions.
+    pub is_global: bool,
+    pub is_upvalue: bool,
+    /// The symbol could not be resolved and is looked up by name at runtime.
+    /// See [`Resolver::set_late_bound_globals`].
+    pub is_late_bound: bool,
+    /// The symbol is a builtin variable (see [`Resolver::resolve_builtin_vars`]) and is not shadowed.
+    pub is_builtin: bool,
+}
+
+/// A [`HashMap`] mapping [`Stmt`]s to [`Symbol`]s.
+pub type SymbolTable = HashMap<*const Stmt, Rc<RefCell<Symbol>>>;
+/// A [`HashMap`] mapping [`Stmt::TupleDeclaration`]s to the [`Symbol`]s of the declared variables.
+pub type DestructuredSymbolTable = HashMap<*const Stmt, Vec<Rc<RefCell<Symbol>>>>;
+/// A [`HashMap`] mapping [`Expr`] (identifiers and blocks) to [`ResolvedSymbol`]s.
+pub type ResolvedSymbolTable = HashMap<*const Expr, ResolvedSymbol>;
+/// A [`HashMap`] mapping [`Expr::Identifier`]s to the [`Symbol`]s they refer to.
+pub type ReferencedSymbolTable = HashMap<*const Expr, Rc<RefCell<Symbol>>>;
+
+/// Variables captured and assigned inside a `while` loop. See [`Resolver::check_loop_captures`].
+struct LoopScope {
+    /// The number of accessible symbols when entering the loop. Symbols below this are declared outside the loop.
+    symbols_len: usize,
+    /// The number of function scopes (`function_scope_depths.len()`) when entering the loop.
+    function_depth: usize,
+    /// The number of enclosing block expressions (`block_expr_depth`) when entering the loop.
+    block_expr_depth: usize,
+    /// Symbols declared outside the loop that are referenced by a function declared inside the loop.
+    captured: Vec<Rc<RefCell<Symbol>>>,
+    /// Symbols that are assigned inside the loop.
+    assigned: Vec<Rc<RefCell<Symbol>>>,
+}
+
+/// Variable resolution pass.
+pub struct Resolver<'a> {
+    /// A [`HashMap`] mapping all declaration [`Stmt`]s to [`Symbol`]s.
+    symbol_table: SymbolTable,
+    /// A [`HashMap`] mapping all [`Stmt::TupleDeclaration`]s to [`Symbol`]s.
+    destructured_symbol_table: DestructuredSymbolTable,
+    /// A [`HashMap`] mapping all [`Expr::Identifier`]s and [`Expr::Block`]s to [`ResolvedSymbol`]s.
+    resolved_symbol_table: ResolvedSymbolTable,
+    /// A [`HashMap`] mapping all resolved [`Expr::Identifier`]s to the [`Symbol`]s they refer to.
+    referenced_symbol_table: ReferencedSymbolTable,
+    /// A [`Vec`] of symbols that are currently in (lexical) scope.
+    accessible_symbols: Vec<Rc<RefCell<Symbol>>>,
+    /// A stack of current function scope depths. `0` is global scope.
+    function_scope_depths: Vec<u32>,
+    /// A stack of the offsets of the functions in `function_scope_depths` (the number of accessible symbols when the
+    /// function scope was created). Offsets of local variables and captured variables are relative to these.
+    func_offsets: Vec<i32>,
+    /// A stack of current function upvalues.
+    function_upvalues: Vec<Vec<ResolvedUpValue>>,
+    /// Symbols that are called as functions with the number of arguments and the span of the call.
+    /// A warning is reported at the end of resolution if the symbol is known to not be a function.
+    /// An error is reported if the function is known to accept fewer arguments.
+    called_symbols: Vec<(Rc<RefCell<Symbol>>, usize, Range<usize>)>,
+    /// A stack of the loops that are currently being resolved. The last element is the innermost loop.
+    loops: Vec<LoopScope>,
+    /// The number of [`Expr::Block`]s that are currently being resolved. `break` and `continue` statements cannot exit
+    /// a block expression because the values of the enclosing expressions are on the stack.
+    block_expr_depth: usize,
+    /// The top-level functions that can be referenced before they are declared. See [`Self::resolve_top_level`].
+    forward_fns: Vec<String>,
+    /// Identifiers referencing a function in `forward_fns` that is not declared yet.
+    /// They are resolved when the function is declared.
+    forward_references: Vec<(String, *const Expr)>,
+    /// If `true`, unresolved identifiers are treated as late-bound globals instead of errors.
+    late_bound_globals: bool,
+    /// If `true`, assignments used as function call arguments or array elements are reported.
+    /// See [`Resolver::set_assignment_expr_warnings`].
+    assignment_expr_warnings: bool,
+    source: &'a Source<'a>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Create a new empty `Resolver`.
+    pub fn new(source: &'a Source) -> Self {
+        Self {
+            symbol_table: SymbolTable::new(),
+            destructured_symbol_table: DestructuredSymbolTable::new(),
+            resolved_symbol_table: ResolvedSymbolTable::new(),
+            referenced_symbol_table: ReferencedSymbolTable::new(),
+            accessible_symbols: Vec::new(),
+            function_scope_depths: vec![0],
+            func_offsets: vec![0],
+            function_upvalues: vec![Vec::new()],
+            called_symbols: Vec::new(),
+            loops: Vec::new(),
+            block_expr_depth: 0,
+            forward_fns: Vec::new(),
+            forward_references: Vec::new(),
+            late_bound_globals: false,
+            assignment_expr_warnings: false,
+            source,
+        }
+    }
+
+    /// Create a new `Resolver` with existing accessible symbols.
+    /// This method is used to implement REPL functionality (for restoring global variables).
+    /// See [`Self::accessible_symbols`].
+    pub fn new_with_existing_accessible_symbols(
+        source: &'a Source,
+        resolved_symbols: Vec<Rc<RefCell<Symbol>>>,
+    ) -> Self {
+        Self {
+            accessible_symbols: resolved_symbols,
+            ..Self::new(source)
+        }
+    }
+
+    /// Enables or disables late-bound globals mode (disabled by default).
+    /// In this mode, identifiers that cannot be resolved do not produce an error.
+    /// Instead, they are treated as global variables that are looked up by name when the code is executed.
+    /// This allows referencing globals that are defined later (e.g. injected by the host).
+    pub fn set_late_bound_globals(&mut self, late_bound_globals: bool) {
+        self.late_bound_globals = late_bound_globals;
+    }
+
+    /// Enables or disables warnings for assignments used as function call arguments or array elements (disabled by
+    /// default). An assignment evaluates to the assigned value, so `f(x = 5)` is valid but `f(x == 5)` was likely
+    /// intended.
+    pub fn set_assignment_expr_warnings(&mut self, assignment_expr_warnings: bool) {
+        self.assignment_expr_warnings = assignment_expr_warnings;
+    }
+
+    /// Creates a [`ResolveResult`].
+    pub fn resolve_result(&self) -> ResolveResult<'_> {
+        ResolveResult {
+            symbol_table: &self.symbol_table,
+            destructured_symbol_table: &self.destructured_symbol_table,
+            resolved_symbol_table: &self.resolved_symbol_table,
+            referenced_symbol_table: &self.referenced_symbol_table,
+        }
+    }
+
+    /// Returns the list of accessible symbols.
+    /// This method is used to implement REPL functionality (for restoring global variables).
+    /// See [`Self::new_with_existing_accessible_symbols`].
+    pub fn accessible_symbols(&self) -> &Vec<Rc<RefCell<Symbol>>> {
+        &self.accessible_symbols
+    }
+
+    /// Returns a [`HashMap`] mapping the identifiers of all accessible global symbols to their stack slots.
+    /// Should be called after [`Self::resolve_top_level`]. If a global is shadowed, the most recent declaration is used.
+    pub fn global_slots(&self) -> HashMap<String, usize> {
+        self.accessible_symbols
+            .iter()
+            .enumerate()
+            .filter(|(_, symbol)| self.find_function_scope_depth(symbol.borrow().scope_depth) == 0)
+            .map(|(slot, symbol)| (symbol.borrow().ident.clone(), slot))
+            .collect()
+    }
+
+    /// Enter a scope.
+    fn enter_scope(&mut self) {
+        *self.function_scope_depths.last_mut().unwrap() += 1;
+    }
+
+    /// Exit a scope. Removes all declarations introduced in previous scope.
+    fn exit_scope(&mut self) {
+        *self.function_scope_depths.last_mut().unwrap() -= 1;
+
+        // Remove all symbols in current scope.
+        self.accessible_symbols = self
+            .accessible_symbols
+            .iter()
+            .filter(|symbol| {
+                symbol.borrow().scope_depth <= *self.function_scope_depths.last().unwrap()
+            })
+            .cloned()
+            .collect();
+    }
+
+    /// Adds a symbol to `self.accessible_symbols` and `self.symbol_table`.
+    /// Reports an error at `span` (the span of the declaration) if the innermost function (or the top level) has more
+    /// than [`MAX_LOCALS`] variables.
+    fn add_symbol(
+        &mut self,
+        ident: String,
+        stmt: Option<&Stmt>,
+        kind: SymbolKind,
+        span: Range<usize>,
+    ) {
+        if self.accessible_symbols.len() - self.current_func_offset() as usize == MAX_LOCALS {
+            let in_function = self.function_scope_depths.len() > 1;
+            let message = if in_function {
+                format!(
+                    "Too many local variables in function. A function can have at most {} local variables",
+                    MAX_LOCALS
+                )
+            } else {
+                format!(
+                    "Too many global variables. The top level can have at most {} variables (including builtins)",
+                    MAX_LOCALS
+                )
+            };
+            self.source
+                .errors
+                .add_error(SyntaxError::new(message, span));
+        }
+
+        let symbol = Rc::new(RefCell::new(Symbol {
+            ident,
+            scope_depth: *self.function_scope_depths.last().unwrap(),
+            kind,
+            arity: None,
+            is_builtin: false,
+            is_captured: false, // not captured by default
+            upvalues: Vec::new(),
+        }));
+        self.accessible_symbols.push(Rc::clone(&symbol));
+        if let Some(stmt) = stmt {
+            self.symbol_table.insert(stmt as *const Stmt, symbol);
+        }
+    }
+
+    /// Adds a placeholder symbol for a temporary value on the stack (e.g. the left operand of a binary expression).
+    /// This keeps the offsets of symbols declared inside block expressions in sync with the VM stack.
+    /// Placeholders have an empty identifier and can never be resolved.
+    fn push_temporary(&mut self) {
+        // temporaries are not declared in the source
+        self.add_symbol(String::new(), None, SymbolKind::Unknown, 0..0);
+    }
+
+    /// Removes the last `count` placeholder symbols added with [`Self::push_temporary`].
+    fn pop_temporaries(&mut self, count: usize) {
+        let len = self.accessible_symbols.len();
+        self.accessible_symbols.truncate(len - count);
+    }
+
+    /// Returns the [`ResolvedSymbol`] for the last placeholder added with [`Self::push_temporary`].
+    fn resolve_temporary(&self) -> ResolvedSymbol {
+        let index = self.accessible_symbols.len() - 1;
+        let is_global =
+            self.find_function_scope_depth(*self.function_scope_depths.last().unwrap()) == 0;
+        ResolvedSymbol {
+            offset: if is_global {
+                index as i32
+            } else {
+                index as i32 - self.current_func_offset()
+            },
+            is_global,
+            is_upvalue: false,
+            is_late_bound: false,
+            is_builtin: false,
+        }
+    }
+
+    /// Returns the offset of the innermost function.
+    fn current_func_offset(&self) -> i32 {
+        *self.func_offsets.last().unwrap()
+    }
+
+    /// Sets the [`Arity`] of the most recently added symbol.
+    fn set_last_symbol_arity(&mut self, arity: Option<Arity>) {
+        self.accessible_symbols.last().unwrap().borrow_mut().arity = arity;
+    }
+
+    /// Returns the function scope depth of the specified `scope_depth`.
+    fn find_function_scope_depth(&self, scope_depth: u32) -> usize {
+        for (i, function_scope_depth) in self.function_scope_depths.iter().enumerate().rev() {
+            if *function_scope_depth < scope_depth {
+                return i + 1;
+            }
+        }
+        0
+    }
+
+    /// Returns `true` if the `symbol` is accessed with its absolute position on the stack (`ldglobal` and `stglobal`).
+    /// Symbols declared in a block of the top-level function are only global when accessed from the top-level
+    /// function. Nested functions capture them instead because the block can end before the function is called.
+    fn is_global_symbol(&self, symbol: &Symbol) -> bool {
+        self.find_function_scope_depth(symbol.scope_depth) == 0
+            && (symbol.scope_depth == 0
+                || self.find_function_scope_depth(*self.function_scope_depths.last().unwrap()) == 0)
+    }
+
+    /// Returns `true` if both scope depths are in the same function (e.g. using block statements). Returns `false` otherwise.
+    fn in_same_function_scope(&self, first: u32, second: u32) -> bool {
+        self.find_function_scope_depth(first) == self.find_function_scope_depth(second)
+    }
+
+    /// Returns the accessible symbol with the specified `ident` or `None` if not found.
+    /// Unlike [`Self::resolve_symbol`], this does not capture the symbol or report any errors.
+    fn lookup_symbol(&self, ident: &str) -> Option<&Rc<RefCell<Symbol>>> {
+        self.accessible_symbols
+            .iter()
+            .rev()
+            .find(|symbol| symbol.borrow().ident == ident)
+    }
+
+    /// Returns a `Some((usize, Rc<RefCell<Symbol>>))` or `None` if cannot be resolved.
+    /// The `usize` is the offset of the variable.
+    /// No error is reported for unresolved symbols if late-bound globals mode is enabled.
+    ///
+    /// # Params
+    /// * `ident` - The identifier to resolve.
+    /// * `span` - The span of the expression to resolve. This is used for error reporting in case the variable could not be resolved.
+    fn resolve_symbol(
+        &mut self,
+        ident: &str,
+        span: Range<usize>,
+    ) -> Option<(usize, Rc<RefCell<Symbol>>)> {
+        for (i, symbol) in self.accessible_symbols.iter().enumerate().rev() {
+            if symbol.borrow().ident == ident {
+                if self.is_global_symbol(&symbol.borrow()) {
+                    return Some((i, symbol.clone()));
+                } else if self.in_same_function_scope(
+                    symbol.borrow().scope_depth,
+                    *self.function_scope_depths.last().unwrap(),
+                ) {
+                    return Some((i - self.current_func_offset() as usize, symbol.clone()));
+                } else {
+                    // capture outer variable
+                    symbol.borrow_mut().is_captured = true;
+
+                    // thread upvalue in enclosing functions
+                    let symbol_func_depth =
+                        self.find_function_scope_depth(symbol.borrow().scope_depth);
+                    let mut prev_upvalue_index = 0;
+                    for scope_depth in symbol_func_depth + 1
+                        ..=self
+                            .find_function_scope_depth(*self.function_scope_depths.last().unwrap())
+                    {
+                        let is_local = scope_depth == symbol_func_depth + 1;
+                        self.function_upvalues[scope_depth].push(ResolvedUpValue {
+                            is_local,
+                            index: if is_local {
+                                // relative to the function that declares the symbol
+                                i as i32 - self.func_offsets[symbol_func_depth]
+                            } else {
+                                prev_upvalue_index as i32
+                            },
+                        });
+
+                        prev_upvalue_index = self.function_upvalues[scope_depth].len() - 1;
+                    }
+
+                    return Some((
+                        self.function_upvalues.last().unwrap().len() - 1,
+                        symbol.clone(),
+                    ));
+                }
+            }
+        }
+        if !self.late_bound_globals {
+            self.source.errors.add_error(SyntaxError::new(
+                format!("Cannot resolve symbol {}", ident),
+                span,
+            ));
+        }
+        None
+    }
+
+    /// Resolve a top-level function [`Stmt`]. This should be used over calling `visit_stmt`.
+    ///
+    /// A function declared at the top level can reference the functions declared after it in the same group of
+    /// consecutive function declarations (e.g. mutually recursive functions). This is safe because no other code can
+    /// run until all the functions of the group are declared.
+    pub fn resolve_top_level(&mut self, func: &'a Stmt) {
+        match func {
+            Stmt::FnDeclaration { body, .. } => {
+                self.check_unreachable(body);
+                for (i, stmt) in body.iter().enumerate() {
+                    self.forward_fns = match stmt {
+                        Stmt::FnDeclaration { .. } => body[i + 1..]
+                            .iter()
+                            .map_while(|stmt| match stmt {
+                                Stmt::FnDeclaration { ident, .. } => Some(ident.clone()),
+                                _ => None,
+                            })
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    self.visit_stmt(stmt);
+                    if let Stmt::FnDeclaration { ident, .. } = stmt {
+                        self.resolve_forward_references(ident);
+                    }
+                }
+                self.forward_fns.clear();
+            }
+            _ => panic!("func is not a Stmt::FnDeclaration"),
+        }
+
+        self.check_called_symbols();
+    }
+
+    /// Resolves the references to the top-level function `ident` that were found before it was declared.
+    fn resolve_forward_references(&mut self, ident: &str) {
+        let slot = match self
+            .accessible_symbols
+            .iter()
+            .rposition(|symbol| symbol.borrow().ident == ident)
+        {
+            Some(slot) => slot,
+            None => return,
+        };
+        let resolved_symbol_table = &mut self.resolved_symbol_table;
+        let referenced_symbol_table = &mut self.referenced_symbol_table;
+        let symbol = &self.accessible_symbols[slot];
+        self.forward_references.retain(|(forward_ident, expr)| {
+            if forward_ident != ident {
+                return true;
+            }
+            referenced_symbol_table.insert(*expr, Rc::clone(symbol));
+            resolved_symbol_table.insert(
+                *expr,
+                ResolvedSymbol {
+                    offset: slot as i32,
+                    is_global: true,
+                    // the reference is inside the body of another function
+                    is_upvalue: true,
+                    is_late_bound: false,
+                    is_builtin: false,
+                },
+            );
+            false
+        });
+    }
+
+    /// Resolve builtin variables.
+    pub fn resolve_builtin_vars(&mut self, builtin_vars: &BuiltinVars) {
+        for (ident, value) in &builtin_vars.values {
+            self.add_symbol(ident.clone(), None, SymbolKind::from_value(value), 0..0);
+            self.set_last_symbol_arity(SymbolKind::arity_from_value(value));
+            self.accessible_symbols.last().unwrap().borrow_mut().is_builtin = true;
+        }
+    }
+
+    /// Reports a warning for every called symbol that is known to not be a function and an error for every call to a
+    /// known function with too many arguments.
+    /// This is done after resolving the whole program because the symbol could be reassigned after the call.
+    fn check_called_symbols(&mut self) {
+        for (symbol, arg_count, span) in self.called_symbols.drain(..) {
+            let symbol = symbol.borrow();
+            let kind = match symbol.kind {
+                SymbolKind::Number => "number",
+                SymbolKind::Bool => "bool",
+                SymbolKind::String => "string",
+                SymbolKind::Array => "array",
+                SymbolKind::Fn => {
+                    let max_arg_count = match symbol.arity {
+                        Some(Arity::Exact(arity)) => arity,
+                        Some(Arity::Range { max, .. }) => max,
+                        Some(Arity::Variadic { .. }) | None => continue,
+                    };
+                    if arg_count > max_arg_count as usize {
+                        self.source.errors.add_error(SyntaxError::new(
+                            format!(
+                                "Too many arguments in call to function {}. Expected {} argument(s), received {}",
+                                symbol.ident,
+                                symbol.arity.unwrap(),
+                                arg_count
+                            ),
+                            span,
+                        ));
+                    }
+                    continue;
+                }
+                SymbolKind::Unknown => continue,
+            };
+            self.source.errors.add_error(SyntaxError::warning(
+                format!(
+                    "Cannot call symbol {} because it is a {}",
+                    symbol.ident, kind
+                ),
+                span,
+            ));
+        }
+    }
+}
+
+/// Returns the first statement of `body` after which the rest of `body` is never executed. See [`always_jumps`].
+fn first_jump(body: &[Stmt]) -> Option<&Stmt> {
+    body.iter().find(|stmt| always_jumps(stmt))
+}
+
+/// Returns `true` if executing `stmt` always returns from the current function.
+/// An `if` statement always returns only if both branches always return.
+fn always_returns(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::ReturnStmt { .. } => true,
+        Stmt::Block(body) => first_jump(body).is_some_and(always_returns),
+        // the body of a do-while loop is always executed at least once
+        Stmt::DoWhileStmt { body, .. } => {
+            !body.iter().any(contains_loop_jump) && first_jump(body).is_some_and(always_returns)
+        }
+        Stmt::IfElseStmt {
+            if_block,
+            else_block: Some(else_block),
+            ..
+        } => {
+            first_jump(if_block).is_some_and(always_returns)
+                && first_jump(else_block).is_some_and(always_returns)
+        }
+        _ => false,
+    }
+}
+
+/// Returns `true` if executing `stmt` always returns, breaks or continues. The statements following `stmt` in the same
+/// body are never executed. A `break` or `continue` inside an `if` statement only jumps if both branches jump.
+fn always_jumps(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::ReturnStmt { .. } | Stmt::BreakStmt { .. } | Stmt::ContinueStmt { .. } => true,
+        Stmt::Block(body) => first_jump(body).is_some(),
+        Stmt::IfElseStmt {
+            if_block,
+            else_block: Some(else_block),
+            ..
+        } => first_jump(if_block).is_some() && first_jump(else_block).is_some(),
+        _ => always_returns(stmt),
+    }
+}
+
+/// Returns `true` if `stmt` contains a `break` or `continue` statement of the enclosing loop.
+fn contains_loop_jump(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::BreakStmt { .. } | Stmt::ContinueStmt { .. } => true,
+        Stmt::Block(body) => body.iter().any(contains_loop_jump),
+        Stmt::IfElseStmt {
+            if_block,
+            else_block,
+            ..
+        } => if_block
+            .iter()
+            .chain(else_block.iter().flatten())
+            .any(contains_loop_jump),
+        // nested loops and functions have their own `break` and `continue` statements
+        _ => false,
+    }
+}
+
+/// Returns the span of the 1-based `line` of `content` without the leading and trailing whitespace.
+fn line_span(content: &str, line: usize) -> Range<usize> {
+    let start: usize = content
+        .split('\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum();
+    let text = content
+        .get(start..)
+        .and_then(|rest| rest.split('\n').next())
+        .unwrap_or("");
+    let end = start + text.trim_end().len();
+    (start + text.len() - text.trim_start().len()).min(end)..end
+}
+
+/// Returns the value of `condition` if it is a boolean known at compile time (e.g. `true`, `!false` or `1 < 2`).
+/// Returns `None` otherwise.
+fn constant_condition(condition: &Expr) -> Option<bool> {
+    match condition {
+        Expr::BoolLit(value) => Some(*value),
+        Expr::Unary {
+            op: Token::LogicalNot,
+            arg,
+        } => constant_condition(arg).map(|value| !value),
+        Expr::Binary { lhs, op, rhs } => match (op, lhs.as_ref(), rhs.as_ref()) {
+            (Token::LogicalAnd, _, _) => Some(constant_condition(lhs)? && constant_condition(rhs)?),
+            (Token::LogicalOr, _, _) => Some(constant_condition(lhs)? || constant_condition(rhs)?),
+            (_, Expr::NumberLit(lhs), Expr::NumberLit(rhs)) => match op {
+                Token::EqualsEquals => Some(lhs == rhs),
+                Token::NotEquals => Some(lhs != rhs),
+                Token::GreaterThan => Some(lhs > rhs),
+                Token::GreaterThanEquals => Some(lhs >= rhs),
+                Token::LessThan => Some(lhs < rhs),
+                Token::LessThanEquals => Some(lhs <= rhs),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Collects the identifiers that are read and assigned by a loop. See [`Resolver::check_loop_progress`].
+#[derive(Default)]
+struct LoopVariables<'a> {
+    read: Vec<&'a str>,
+    assigned: Vec<&'a str>,
+    /// `true` if the loop contains a function call or a `return` or `break` statement. Function calls can modify
+    /// variables that are not visible in the loop and `return` and `break` can exit the loop regardless of the
+    /// condition.
+    has_untracked_effects: bool,
+}
+
+impl<'a> Visitor<'a> for LoopVariables<'a> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Identifier(ident) => self.read.push(ident),
+            Expr::Binary {
+                lhs,
+                op:
+                    Token::Equals
+                    | Token::PlusEquals
+                    | Token::MinusEquals
+                    | Token::AsteriskEquals
+                    | Token::SlashEquals,
+                ..
+            } => {
+                if let Expr::Identifier(ident) = lhs.as_ref() {
+                    self.assigned.push(ident);
+                }
+            }
+            Expr::FnCall { .. } => self.has_untracked_effects = true,
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        if let Stmt::ReturnStmt { .. } | Stmt::BreakStmt { .. } = stmt {
+            self.has_untracked_effects = true;
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+impl<'a> Resolver<'a> {
+    /// Reports a warning if no variable in the `condition` of a `while` loop is assigned by the loop, in which case the
+    /// loop never ends once it is entered. The analysis is conservative: loops containing function calls, `return` or
+    /// `break` statements are not reported.
+    fn check_loop_progress(&mut self, condition: &Expr, body: &[Stmt]) {
+        let mut variables = LoopVariables::default();
+        variables.visit_expr(condition);
+        let mut condition_idents = std::mem::take(&mut variables.read);
+        for stmt in body {
+            variables.visit_stmt(stmt);
+        }
+
+        if condition_idents.is_empty()
+            || variables.has_untracked_effects
+            || condition_idents
+                .iter()
+                .any(|ident| variables.assigned.contains(ident))
+        {
+            return;
+        }
+        condition_idents.sort_unstable();
+        condition_idents.dedup();
+        self.source.errors.add_error(SyntaxError::warning(
+            format!(
+                "Variables in the loop condition are never modified by the loop: {}",
+                condition_idents.join(", ")
+            ),
+            0..0,
+        ));
+    }
+
+    /// Reports a warning if a statement in `body` can never be executed because a previous statement always returns,
+    /// breaks or continues. Only the first unreachable statement of `body` is reported.
+    fn check_unreachable(&mut self, body: &[Stmt]) {
+        if let Some(index) = body.iter().position(always_jumps) {
+            if let Some(unreachable) = body[index + 1..]
+                .iter()
+                .find(|stmt| !matches!(stmt, Stmt::EmptyStmt))
+            {
+                self.source.errors.add_error(SyntaxError::warning(
+                    "Unreachable code",
+                    self.stmt_span(unreachable),
+                ));
+            }
+        }
+    }
+
+    /// Returns the span of `stmt` used to report diagnostics. Statements that do not record their span are reported at
+    /// their first line.
+    fn stmt_span(&self, stmt: &Stmt) -> Range<usize> {
+        match stmt {
+            Stmt::LetDeclaration { span, .. }
+            | Stmt::TupleDeclaration { span, .. }
+            | Stmt::FnDeclaration { span, .. }
+            | Stmt::BreakStmt { span, .. }
+            | Stmt::ContinueStmt { span, .. } => span.clone(),
+            Stmt::Block(body) => body.first().map_or(0..0, |stmt| self.stmt_span(stmt)),
+            _ => stmt
+                .line()
+                .map_or(0..0, |line| line_span(&self.source.content, line)),
+        }
+    }
+
+    /// Reports an error if the `break` or `continue` statement (`keyword`) at `span` is not inside a loop of the
+    /// current function or if it would exit a block expression.
+    fn check_loop_jump(&mut self, keyword: &str, span: &Range<usize>) {
+        let message = match self.loops.last() {
+            Some(loop_scope) if loop_scope.function_depth == self.function_scope_depths.len() => {
+                if loop_scope.block_expr_depth == self.block_expr_depth {
+                    return;
+                }
+                format!("Cannot use {} inside of a block expression", keyword)
+            }
+            _ => format!("Cannot use {} outside of a loop", keyword),
+        };
+        self.source
+            .errors
+            .add_error(SyntaxError::new(message, span.clone()));
+    }
+
+    /// Records that `symbol` is referenced in every loop that encloses the current function but not the declaration of
+    /// `symbol`. Only references from functions declared inside the loop are recorded.
+    fn record_loop_capture(&mut self, symbol: &Rc<RefCell<Symbol>>) {
+        let function_depth = self.function_scope_depths.len();
+        for loop_scope in &mut self.loops {
+            if function_depth > loop_scope.function_depth
+                && self.accessible_symbols[..loop_scope.symbols_len]
+                    .iter()
+                    .any(|outer| Rc::ptr_eq(outer, symbol))
+                && !loop_scope.captured.iter().any(|captured| Rc::ptr_eq(captured, symbol))
+            {
+                loop_scope.captured.push(Rc::clone(symbol));
+            }
+        }
+    }
+
+    /// Reports a warning for every variable declared outside the innermost loop that is captured by a function declared
+    /// inside the loop and assigned inside the loop. Variables are captured by reference so all the closures created
+    /// by the loop observe the last value of the variable.
+    fn check_loop_captures(&mut self) {
+        let loop_scope = self.loops.pop().unwrap();
+        for symbol in &loop_scope.captured {
+            if loop_scope
+                .assigned
+                .iter()
+                .any(|assigned| Rc::ptr_eq(assigned, symbol))
+            {
+                self.source.errors.add_error(SyntaxError::warning(
+                    format!(
+                        "Closure captures variable {} which is modified by the enclosing loop. All closures created by the loop share the same variable",
+                        symbol.borrow().ident
+                    ),
+                    0..0,
+                ));
+                // only report the variable for the innermost loop
+                for outer in &mut self.loops {
+                    outer.captured.retain(|captured| !Rc::ptr_eq(captured, symbol));
+                }
+            }
+        }
+    }
+
+    /// Reports a warning if the `body` of an `if`, `else` or `while` statement is empty.
+    fn check_body(&mut self, body: &[Stmt], kind: &str) {
+        if body.is_empty() {
+            self.source
+                .errors
+                .add_error(SyntaxError::warning(format!("Empty {} body", kind), 0..0));
+        }
+    }
+
+    /// Reports a warning for every assignment in `exprs` if enabled with [`Self::set_assignment_expr_warnings`].
+    /// `position` describes where the expressions are used (e.g. `"a function argument"`).
+    fn check_assignment_exprs(&mut self, exprs: &[Expr], position: &str) {
+        if !self.assignment_expr_warnings {
+            return;
+        }
+        for expr in exprs {
+            if let Expr::Binary {
+                op: Token::Equals, ..
+            } = expr
+            {
+                self.source.errors.add_error(SyntaxError::warning(
+                    format!("Assignment used as {}, did you mean ==?", position),
+                    0..0,
+                ));
+            }
+        }
+    }
+
+    /// Reports a warning if the `if` or `while` `condition` is an assignment (likely a typo for `==`) or if it is always
+    /// `true` or always `false`. `while true` is allowed as it is the idiomatic infinite loop.
+    fn check_condition(&mut self, condition: &Expr, is_while: bool) {
+        if let Expr::Binary {
+            op: Token::Equals, ..
+        } = condition
+        {
+            self.source.errors.add_error(SyntaxError::warning(
+                "Assignment used as a condition, did you mean ==?",
+                0..0,
+            ));
+            return;
+        }
+        if is_while && *condition == Expr::BoolLit(true) {
+            return;
+        }
+        if let Some(value) = constant_condition(condition) {
+            self.source.errors.add_error(SyntaxError::warning(
+                format!("Condition is always {}", value),
+                0..0,
+            ));
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for Resolver<'a> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        // Values that are on the stack while evaluating sub-expressions are tracked as temporaries.
+        match expr {
+            // the lhs of `&&` and `||` is popped before evaluating the rhs
+            Expr::Binary { lhs, op, rhs }
+                if !matches!(op, Token::Equals | Token::LogicalAnd | Token::LogicalOr) =>
+            {
+                self.visit_expr(lhs);
+                self.push_temporary();
+                self.visit_expr(rhs);
+                self.pop_temporaries(1);
+            }
+            Expr::FnCall { callee, args, .. } => {
+                for arg in args {
+                    self.visit_expr(arg);
+                    self.push_temporary();
+                }
+                self.visit_expr(callee);
+                self.pop_temporaries(args.len());
+            }
+            Expr::ArrayLit(elements) => {
+                for element in elements {
+                    self.visit_expr(element);
+                    self.push_temporary();
+                }
+                self.pop_temporaries(elements.len());
+            }
+            Expr::Block { body, value } => {
+                // slot for the value of the block
+                self.push_temporary();
+                let resolved_symbol = self.resolve_temporary();
+                self.resolved_symbol_table
+                    .insert(expr as *const Expr, resolved_symbol);
+
+                self.check_unreachable(body);
+                self.block_expr_depth += 1;
+                self.enter_scope();
+                for stmt in body {
+                    self.visit_stmt(stmt);
+                }
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                self.exit_scope();
+                self.block_expr_depth -= 1;
+                self.pop_temporaries(1);
+            }
+            Expr::FnLit(func) => {
+                // the closure is a temporary on the stack. `<anonymous>` can never be resolved.
+                self.visit_stmt(func);
+                self.pop_temporaries(1);
+            }
+            _ => walk_expr(self, expr),
+        }
+
+        match expr {
+            Expr::Identifier(ident)
+                if self.lookup_symbol(ident).is_none() && self.forward_fns.contains(ident) =>
+            {
+                // resolved when the function is declared
+                self.forward_references
+                    .push((ident.clone(), expr as *const Expr));
+            }
+            Expr::Identifier(ident) => {
+                let symbol = self.resolve_symbol(ident, 0..0);
+                if let Some((offset, symbol)) = symbol {
+                    self.record_loop_capture(&symbol);
+                    self.referenced_symbol_table
+                        .insert(expr as *const Expr, Rc::clone(&symbol));
+                    self.resolved_symbol_table.insert(
+                        expr as *const Expr,
+                        ResolvedSymbol {
+                            offset: offset as i32,
+                            is_global: self.is_global_symbol(&symbol.borrow()),
+                            is_upvalue: self.find_function_scope_depth(
+                                *self.function_scope_depths.last().unwrap(),
+                            ) > self
+                                .find_function_scope_depth(symbol.borrow().scope_depth),
+                            is_late_bound: false,
+                            is_builtin: symbol.borrow().is_builtin,
+                        },
+                    );
+                } else if self.late_bound_globals {
+                    self.resolved_symbol_table.insert(
+                        expr as *const Expr,
+                        ResolvedSymbol {
+                            offset: 0,
+                            is_global: true,
+                            is_upvalue: false,
+                            is_late_bound: true,
+                            is_builtin: false,
+                        },
+                    );
+                }
+            }
+            Expr::FnCall { callee, args, span } => {
+                if let Expr::Identifier(ident) = callee.as_ref() {
+                    if let Some(symbol) = self.lookup_symbol(ident) {
+                        let symbol = Rc::clone(symbol);
+                        self.called_symbols.push((symbol, args.len(), span.clone()));
+                    }
+                }
+                self.check_assignment_exprs(args, "a function argument");
+            }
+            Expr::ArrayLit(elements) => self.check_assignment_exprs(elements, "an array element"),
+            Expr::Binary {
+                lhs,
+                op:
+                    Token::Equals
+                    | Token::PlusEquals
+                    | Token::MinusEquals
+                    | Token::AsteriskEquals
+                    | Token::SlashEquals,
+                ..
+            } => {
+                if let Expr::Identifier(ident) = lhs.as_ref() {
+                    // Value is no longer statically known.
+                    if let Some(symbol) = self.lookup_symbol(ident) {
+                        symbol.borrow_mut().kind = SymbolKind::Unknown;
+                        let symbol = Rc::clone(symbol);
+                        for loop_scope in &mut self.loops {
+                            loop_scope.assigned.push(Rc::clone(&symbol));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        // Do not use default walking logic.
+
+        match stmt {
+            Stmt::LetDeclaration {
+                ident,
+                initializer,
+                span,
+                ..
+            } => {
+                self.visit_expr(initializer);
+                self.add_symbol(
+                    ident.clone(),
+                    Some(stmt),
+                    SymbolKind::from_initializer(initializer),
+                    span.clone(),
+                );
+            }
+            Stmt::TupleDeclaration {
+                idents,
+                initializer,
+                span,
+                ..
+            } => {
+                if idents.len() > MAX_TUPLE_DECLARATION_LEN {
+                    self.source.errors.add_error(SyntaxError::new(
+                        format!(
+                            "Too many variables in tuple declaration. A tuple declaration can have at most {} variables",
+                            MAX_TUPLE_DECLARATION_LEN
+                        ),
+                        span.clone(),
+                    ));
+                }
+                self.visit_expr(initializer);
+                // the elements of the tuple are unpacked onto the stack in order
+                let mut symbols = Vec::with_capacity(idents.len());
+                for ident in idents {
+                    self.add_symbol(ident.clone(), None, SymbolKind::Unknown, span.clone());
+                    symbols.push(Rc::clone(self.accessible_symbols.last().unwrap()));
+                }
+                self.destructured_symbol_table
+                    .insert(stmt as *const Stmt, symbols);
+            }
+            Stmt::FnDeclaration {
+                ident,
+                params,
+                body,
+                span,
+                ..
+            } => {
+                // Add symbol first to allow for recursion.
+                self.add_symbol(ident.clone(), Some(stmt), SymbolKind::Fn, span.clone());
+                self.set_last_symbol_arity(Some(Arity::Exact(params.len() as u32)));
+
+                self.func_offsets.push(self.accessible_symbols.len() as i32);
+                self.function_upvalues.push(Vec::new());
+                self.function_scope_depths
+                    .push(*self.function_scope_depths.last().unwrap());
+
+                self.check_unreachable(body);
+                self.enter_scope();
+                // add arguments
+                for param in params {
+                    // `None` to keep `stmt` mapped to the symbol of the function.
+                    self.add_symbol(param.clone(), None, SymbolKind::Unknown, span.clone());
+                }
+
+                for stmt in body {
+                    self.visit_stmt(stmt);
+                }
+                self.exit_scope();
+
+                // patch self.symbol_table with upvalues
+                self.symbol_table
+                    .get(&(stmt as *const Stmt))
+                    .unwrap()
+                    .borrow_mut()
+                    .upvalues = self.function_upvalues.pop().unwrap();
+                self.function_scope_depths.pop();
+                self.func_offsets.pop();
+            }
+            Stmt::Block(body) => {
+                self.check_unreachable(body);
+                self.enter_scope();
+                for stmt in body {
+                    self.visit_stmt(stmt);
+                }
+                self.exit_scope();
+            }
+            Stmt::IfElseStmt {
+                condition,
+                if_block,
+                else_block,
+                ..
+            } => {
+                self.check_condition(condition, false);
+                self.check_body(if_block, "if");
+                self.check_unreachable(if_block);
+                if let Some(else_block) = else_block {
+                    self.check_body(else_block, "else");
+                    self.check_unreachable(else_block);
+                }
+                self.visit_expr(condition);
+                self.enter_scope();
+                for stmt in if_block {
+                    self.visit_stmt(stmt);
+                }
+                self.exit_scope();
+                if let Some(else_block) = else_block {
+                    self.enter_scope();
+                    for stmt in else_block {
+                        self.visit_stmt(stmt);
+                    }
+                    self.exit_scope();
+                }
+            }
+            Stmt::WhileStmt {
+                condition, body, ..
+            } => {
+                self.check_condition(condition, true);
+                self.check_body(body, "while");
+                self.check_unreachable(body);
+                self.check_loop_progress(condition, body);
+                self.loops.push(LoopScope {
+                    symbols_len: self.accessible_symbols.len(),
+                    function_depth: self.function_scope_depths.len(),
+                    block_expr_depth: self.block_expr_depth,
+                    captured: Vec::new(),
+                    assigned: Vec::new(),
+                });
+                self.visit_expr(condition);
+                self.enter_scope();
+                for stmt in body {
+                    self.visit_stmt(stmt);
+                }
+                self.exit_scope();
+                self.check_loop_captures();
+            }
+            Stmt::DoWhileStmt {
+                body, condition, ..
+            } => {
+                // `do { ... } while false;` is allowed as it runs the body exactly once
+                if *condition != Expr::BoolLit(false) {
+                    self.check_condition(condition, true);
+                }
+                self.check_body(body, "do");
+                self.check_unreachable(body);
+                self.check_loop_progress(condition, body);
+                self.loops.push(LoopScope {
+                    symbols_len: self.accessible_symbols.len(),
+                    function_depth: self.function_scope_depths.len(),
+                    block_expr_depth: self.block_expr_depth,
+                    captured: Vec::new(),
+                    assigned: Vec::new(),
+                });
+                // variables declared in the body are not accessible in the condition
+                self.enter_scope();
+                for stmt in body {
+                    self.visit_stmt(stmt);
+                }
+                self.exit_scope();
+                self.visit_expr(condition);
+                self.check_loop_captures();
+            }
+            Stmt::ExprStmt { expr, .. } => self.visit_expr(expr),
+            Stmt::ReturnStmt { expr, .. } => self.visit_expr(expr),
+            Stmt::BreakStmt { span, .. } => self.check_loop_jump("break", span),
+            Stmt::ContinueStmt { span, .. } => self.check_loop_jump("continue", span),
+            Stmt::EmptyStmt => {}
+            Stmt::Error => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ella_parser::parser::Parser;
+
+    /// Resolves the `source` and returns the reported warnings.
+    fn resolve(source: &str) -> Vec<SyntaxError> {
+        let source = source.into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+        source.errors.warnings()
+    }
+
+    #[test]
+    fn test_capture_block_scoped_global() {
+        let source = "{ let x = 1; fn get() { return x; } }".into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+
+        let block = match &ast {
+            Stmt::FnDeclaration { body, .. } => match &body[0] {
+                Stmt::Block(block) => block,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        // `x` is popped at the end of the block, possibly before `get` is called.
+        let resolve_result = resolver.resolve_result();
+        let x = resolve_result.lookup_declaration(&block[0]).unwrap();
+        assert!(x.borrow().is_captured);
+        let get = resolve_result.lookup_declaration(&block[1]).unwrap();
+        assert_eq!(get.borrow().upvalues.len(), 1);
+    }
+
+    #[test]
+    fn test_late_bound_globals() {
+        let source = "x; fn f() { return y; }".into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.set_late_bound_globals(true);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+
+        let source = "x;".into();
+        let ast = Parser::new(&source).parse_program();
+        Resolver::new(&source).resolve_top_level(&ast);
+        assert!(!source.has_no_errors());
+    }
+
+    #[test]
+    fn test_forward_references() {
+        let source = r#"
+            fn is_even(n) { if n == 0 { return true; } return is_odd(n - 1); }
+            fn is_odd(n) { if n == 0 { return false; } return is_even(n - 1); }
+            is_even(2);"#
+            .into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+        let resolved: Vec<_> = resolver.resolved_symbol_table.values().collect();
+        assert!(resolved.iter().any(|symbol| symbol.is_global && symbol.offset == 1));
+
+        // code between the declarations could call the function before the referenced function is declared
+        for source in &[
+            "fn f() { return g(); } f(); fn g() {}",
+            "g(); fn g() {}",
+            "fn f() { return g(); } let x = 1; fn g() {}",
+        ] {
+            let source = (*source).into();
+            let ast = Parser::new(&source).parse_program();
+            Resolver::new(&source).resolve_top_level(&ast);
+            assert!(!source.has_no_errors());
+        }
+    }
+
+    #[test]
+    fn test_global_slots() {
+        let source = "let x = 1; let y = 2; fn f(a) { let z = 3; } let x = 4;".into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+
+        let global_slots = resolver.global_slots();
+        assert_eq!(global_slots.len(), 3);
+        assert_eq!(global_slots["x"], 3);
+        assert_eq!(global_slots["y"], 1);
+        assert_eq!(global_slots["f"], 2);
+    }
+
+    #[test]
+    fn test_tuple_declaration() {
+        let source = "let (x, y) = (1, 2); fn f() { let (a, b) = (x, y); }".into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+
+        let global_slots = resolver.global_slots();
+        assert_eq!(global_slots["x"], 0);
+        assert_eq!(global_slots["y"], 1);
+        let body = match &ast {
+            Stmt::FnDeclaration { body, .. } => body,
+            _ => unreachable!(),
+        };
+        let symbols = resolver
+            .resolve_result()
+            .lookup_tuple_declaration(&body[0])
+            .unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[1].borrow().ident, "y");
+    }
+
+    #[test]
+    fn test_tuple_declaration_too_many_variables() {
+        let idents = |count: usize| -> Vec<String> {
+            (0..count)
+                .map(|i| {
+                    let first = (b'a' + (i / 26) as u8) as char;
+                    let second = (b'a' + (i % 26) as u8) as char;
+                    format!("x{}{}", first, second)
+                })
+                .collect()
+        };
+        let source = format!("fn f(t) {{ let ({}) = t; }}", idents(256).join(", ")).into();
+        let ast = Parser::new(&source).parse_program();
+        Resolver::new(&source).resolve_top_level(&ast);
+        let errors = source.errors.errors();
+        assert_eq!(
+            errors[0].message(),
+            "Too many variables in tuple declaration. A tuple declaration can have at most 255 variables"
+        );
+        assert_eq!(errors[0].span(), 10..source.content.len() - 2);
+
+        let source = format!("fn f(t) {{ let ({}) = t; }}", idents(255).join(", "));
+        assert!(resolve(&source).is_empty());
+    }
+
+    #[test]
+    fn test_too_many_locals() {
+        // identifiers cannot contain digits
+        let lets = |count: usize| -> String {
+            (0..count)
+                .map(|i| {
+                    let first = (b'a' + (i / 26) as u8) as char;
+                    let second = (b'a' + (i % 26) as u8) as char;
+                    format!("let x{}{} = {};", first, second, i)
+                })
+                .collect()
+        };
+        let source = format!("fn f(a) {{ {} }}", lets(300)).into();
+        let ast = Parser::new(&source).parse_program();
+        Resolver::new(&source).resolve_top_level(&ast);
+        let errors = source.errors.errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .message()
+            .starts_with("Too many local variables in function"));
+        // the 256th declaration is the 257th local variable
+        let start = "fn f(a) { ".len() + lets(255).len();
+        assert_eq!(errors[0].span(), start..start + "let xjv = 255;".len());
+
+        // the parameter is the first local variable
+        assert!(resolve(&format!("fn f(a) {{ {} }}", lets(255))).is_empty());
+
+        // builtins are global variables
+        let mut builtin_vars = BuiltinVars::new();
+        for i in 0..6 {
+            builtin_vars.add_constant(format!("c{}", i), Value::Number(0.0));
+        }
+        let source = lets(251).into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_builtin_vars(&builtin_vars);
+        resolver.resolve_top_level(&ast);
+        let errors = source.errors.errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message().starts_with("Too many global variables"));
+        assert_eq!(errors[0].span(), lets(250).len()..source.content.len());
+
+        let source = lets(250).into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_builtin_vars(&builtin_vars);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+    }
+
+    #[test]
+    fn test_call_non_callable() {
+        let warnings = resolve("let x = 5; x();");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message().contains("number"));
+        assert_eq!(resolve(r#"let x = "str"; x();"#).len(), 1);
+        assert_eq!(resolve("let x = true; fn f() { x(); }").len(), 1);
+        assert!(resolve("let x = [1]; x();")[0].message().contains("array"));
+    }
+
+    #[test]
+    fn test_call_builtin_constant() {
+        let mut builtin_vars = BuiltinVars::new();
+        builtin_vars.add_constant("PI", Value::Number(std::f64::consts::PI));
+
+        let source = "PI();".into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver = Resolver::new(&source);
+        resolver.resolve_builtin_vars(&builtin_vars);
+        resolver.resolve_top_level(&ast);
+        let warnings = source.errors.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message().contains("number"));
+    }
+
+    #[test]
+    fn test_call_too_many_args() {
+        let source = "fn f(a) {} f(1, 2, 3);".into();
+        let ast = Parser::new(&source).parse_program();
+        Resolver::new(&source).resolve_top_level(&ast);
+        let errors = source.errors.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message(),
+            "Too many arguments in call to function f. Expected 1 argument(s), received 3"
+        );
+        assert_eq!(errors[0].span(), 11..21);
+
+        assert!(resolve("fn f(a) {} f(1);").is_empty());
+        // fewer arguments and indirect calls are checked at runtime
+        assert!(resolve("fn f(a, b) {} f(1);").is_empty());
+        assert!(resolve("fn f(a) {} let g = f; g(1, 2);").is_empty());
+        // f is reassigned after the call
+        assert!(resolve("fn f(a) {} fn g(a, b) {} fn h() { f(1, 2); } f = g; h();").is_empty());
+    }
+
+    #[test]
+    fn test_constant_condition() {
+        let warnings = resolve("fn x() {} if false { x(); }");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message(), "Condition is always false");
+        assert_eq!(
+            resolve("if !false || 1 > 2 { 1; } else { 2; }")[0].message(),
+            "Condition is always true"
+        );
+        assert_eq!(resolve("while false { 1; }").len(), 1);
+        assert_eq!(resolve("while 1 == 1 { 1; }").len(), 1);
+
+        // idiomatic infinite loop
+        assert!(resolve("while true { 1; }").is_empty());
+        assert!(resolve("do { 1; } while true;").is_empty());
+        // idiomatic body that runs once
+        assert!(resolve("do { 1; } while false;").is_empty());
+        assert_eq!(resolve("do { 1; } while !true;").len(), 1);
+        assert!(resolve("let x = true; if x { 1; } while x && true { x = false; }").is_empty());
+    }
+
+    #[test]
+    fn test_assignment_condition() {
+        let warnings = resolve("let x = 0; if x = 5 { 1; }");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message(),
+            "Assignment used as a condition, did you mean ==?"
+        );
+        assert_eq!(resolve("let x = 0; while x = 5 { 1; }").len(), 1);
+        assert!(resolve("let x = 0; if x == 5 { 1; }").is_empty());
+    }
+
+    #[test]
+    fn test_assignment_expr_warnings() {
+        let warnings = |source: &str| {
+            let source = source.into();
+            let ast = Parser::new(&source).parse_program();
+            let mut resolver = Resolver::new(&source);
+            resolver.set_assignment_expr_warnings(true);
+            resolver.resolve_top_level(&ast);
+            assert!(source.has_no_errors());
+            source
+                .errors
+                .warnings()
+                .iter()
+                .map(|warning| warning.message().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            warnings("fn f(a) {} let x = 0; f(x = 5);"),
+            vec!["Assignment used as a function argument, did you mean ==?"]
+        );
+        assert_eq!(
+            warnings("let x = 0; let a = [1, x = 5];"),
+            vec!["Assignment used as an array element, did you mean ==?"]
+        );
+        assert!(warnings("fn f(a) {} let x = 0; x = 5; f(x == 5); f((x = 5) + 1);").is_empty());
+        // disabled by default
+        assert!(resolve("fn f(a) {} let x = 0; f(x = 5);").is_empty());
+    }
+
+    #[test]
+    fn test_empty_body() {
+        let warnings = resolve("let x = true; if x {}");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message(), "Empty if body");
+        assert_eq!(
+            resolve("let x = true; if x { 1; } else {}")[0].message(),
+            "Empty else body"
+        );
+        // busy wait
+        assert_eq!(
+            resolve("let x = true; while x {}")[0].message(),
+            "Empty while body"
+        );
+        assert!(resolve("let x = true; if x { 1; } else if x { 2; }").is_empty());
+        assert!(resolve("let x = true; while x { x = false; }").is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let warnings = resolve("fn f() { return 1; f(); }");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message(), "Unreachable code");
+        assert_eq!(
+            resolve("fn f(x) { if x { return 1; } else { return 2; } f(x); }").len(),
+            1
+        );
+        // a return inside only one branch does not make the code after the `if` unreachable
+        assert!(resolve("fn f(x) { if x { return 1; } return 2; }").is_empty());
+        assert!(resolve("fn f(x) { while x { return 1; } return 2; }").is_empty());
+        assert!(resolve("fn f() { return 1;; }").is_empty());
+
+        // the warning is reported at the first unreachable statement
+        assert_eq!(
+            resolve("fn f() {\n  return 1;\n  f();\n  f();\n}")[0].span(),
+            23..27
+        );
+        assert_eq!(resolve("fn f() { return 1; let x = 2; }")[0].span(), 19..29);
+    }
+
+    #[test]
+    fn test_unreachable_code_after_break_and_continue() {
+        let warnings = resolve("let i = 0;\nwhile i < 3 {\n  i += 1;\n  break;\n  i;\n}");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message(), "Unreachable code");
+        assert_eq!(warnings[0].span(), 46..48);
+        assert_eq!(
+            resolve("let i = 0; do { i += 1; continue; let x = i; } while i < 3;")[0].span(),
+            34..44
+        );
+        assert_eq!(
+            resolve("let i = 0; while i < 3 { i += 1; if i > 1 { break; } else { continue; } i; }")
+                .len(),
+            1
+        );
+        // a conditional break does not make the code after the `if` unreachable
+        assert!(resolve("let i = 0; while i < 3 { i += 1; if i == 2 { break; } i; }").is_empty());
+        // a loop can be exited with break so the code after the loop is reachable
+        assert!(resolve("fn f() { while true { break; } return 1; }").is_empty());
+        assert!(
+            resolve("fn f() { do { if true { break; } return 1; } while true; f(); }")
+                .iter()
+                .all(|warning| warning.message() != "Unreachable code")
+        );
+        assert!(resolve("fn f() { do { return 1; } while true; f(); }")
+            .iter()
+            .any(|warning| warning.message() == "Unreachable code"));
+    }
+
+    #[test]
+    fn test_break_and_continue_outside_of_loop() {
+        let errors = |source: &str| {
+            let source = source.into();
+            let ast = Parser::new(&source).parse_program();
+            Resolver::new(&source).resolve_top_level(&ast);
+            source
+                .errors
+                .errors()
+                .iter()
+                .map(|error| (error.message().to_string(), error.span()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            errors("break;"),
+            vec![("Cannot use break outside of a loop".to_string(), 0..6)]
+        );
+        assert_eq!(
+            errors("while true { fn f() { continue; } }"),
+            vec![("Cannot use continue outside of a loop".to_string(), 22..31)]
+        );
+        assert_eq!(
+            errors("while true { let x = { break; 1 }; }"),
+            vec![(
+                "Cannot use break inside of a block expression".to_string(),
+                23..29
+            )]
+        );
+        assert!(errors("while true { let x = { while true { break; } 1 }; continue; }").is_empty());
+    }
+
+    #[test]
+    fn test_loop_progress() {
+        let warnings = resolve("let i = 0; let x = 0; while i < 3 { x = x + 1; }");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message(),
+            "Variables in the loop condition are never modified by the loop: i"
+        );
+        assert_eq!(
+            resolve("let a = 0; let b = 1; while a < b && a < 2 { 1; }")[0].message(),
+            "Variables in the loop condition are never modified by the loop: a, b"
+        );
+        assert!(resolve("let i = 0; while i < 3 { i += 1; }").is_empty());
+        assert!(resolve("let i = 0; while (i += 1) < 3 { 1; }").is_empty());
+        // the loop can be exited or the variable modified without an assignment in the loop
+        assert!(resolve("fn f(i) { while i < 3 { return 1; } }").is_empty());
+        assert!(resolve("let i = 0; fn inc() { i += 1; } while i < 3 { inc(); }").is_empty());
+    }
+
+    #[test]
+    fn test_loop_capture() {
+        let warnings = resolve("let i = 0; while i < 3 { fn f() { return i; } i += 1; }");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message(),
+            "Closure captures variable i which is modified by the enclosing loop. All closures created by the loop share the same variable"
+        );
+        assert_eq!(
+            resolve("fn g() { let i = 0; while i < 3 { let f = fn() { return i; }; i += 1; } }").len(),
+            1
+        );
+        // nested loops report the variable once
+        assert_eq!(
+            resolve("let i = 0; while i < 3 { while i < 2 { fn f() { return i; } i += 1; } }").len(),
+            1
+        );
+        // the variable is not modified by the loop
+        assert!(resolve("let i = 0; while true { fn f() { return i; } }").is_empty());
+        // the variable is declared inside the loop
+        assert!(
+            resolve("let i = 0; while i < 3 { let j = i; fn f() { return j; } i += 1; }").is_empty()
+        );
+        // the variable is not captured
+        assert!(resolve("let i = 0; while i < 3 { i += 1; }").is_empty());
+    }
+
+    #[test]
+    fn test_call_callable() {
+        assert!(resolve("fn f() {} f();").is_empty());
+        assert!(resolve("fn f(g) { g(); }").is_empty());
+        assert!(resolve("fn f() {} let x = f; x();").is_empty());
+        // x is reassigned after the call
+        assert!(resolve("fn f() {} let x = 5; fn g() { x(); } x = f; g();").is_empty());
+    }
+}
@@ -1,11 +1,13 @@
 //! Source code representation and error management.
 
-use std::{cell::RefCell, fmt, ops::Range};
+use std::{borrow::Cow, cell::RefCell, fmt, ops::Range};
 
 /// Represents source code.
 pub struct Source<'a> {
-    /// Original source code.
-    pub content: &'a str,
+    /// Original source code. Can be either borrowed or owned.
+    pub content: Cow<'a, str>,
+    /// Name of the file the source code was read from, if any. Used for error reporting.
+    pub filename: Option<String>,
     /// Accumulated errors.
     pub errors: ErrorReporter,
 }
@@ -14,28 +16,96 @@ impl<'a> Source<'a> {
     /// Create a new `Source` with the specified `content`.
     pub fn new(content: &'a str) -> Self {
         Self {
-            content,
+            content: Cow::Borrowed(content),
+            filename: None,
             errors: ErrorReporter::new(),
         }
     }
 
+    /// Sets the `filename` of the `Source`.
+    pub fn with_filename(mut self, filename: impl ToString) -> Self {
+        self.filename = Some(filename.to_string());
+        self
+    }
+
+    /// Returns the 1-based line and column of the byte `position` in the source code.
+    pub fn line_col(&self, position: usize) -> (usize, usize) {
+        let before = &self.content[..position.min(self.content.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = match before.rfind('\n') {
+            Some(line_start) => before[line_start + 1..].chars().count() + 1,
+            None => before.chars().count() + 1,
+        };
+        (line, col)
+    }
+
+    /// Returns a displayable version of `error`.
+    /// If the `Source` has a `filename`, the error is prefixed with `path:line:col`.
+    pub fn display_error<'s>(&'s self, error: &'s SyntaxError) -> impl fmt::Display + 's {
+        struct DisplayError<'s, 'a> {
+            source: &'s Source<'a>,
+            error: &'s SyntaxError,
+        }
+
+        impl fmt::Display for DisplayError<'_, '_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if let Some(filename) = &self.source.filename {
+                    let (line, col) = self.source.line_col(self.error.span.start);
+                    write!(f, "{}:{}:{}: ", filename, line, col)?;
+                }
+                write!(f, "{}", self.error)
+            }
+        }
+
+        DisplayError {
+            source: self,
+            error,
+        }
+    }
+
     /// Returns `true` if `Source` has no accumulated errors. Returns `false` otherwise.
+    /// Warnings are not considered errors.
     pub fn has_no_errors(&self) -> bool {
-        self.errors.errors.borrow().len() == 0
+        self.errors
+            .errors
+            .borrow()
+            .iter()
+            .all(|error| error.severity == Severity::Warning)
     }
 }
 
-impl<'a> Into<Source<'a>> for &'a str {
-    fn into(self) -> Source<'a> {
-        Source::new(self)
+impl<'a> From<&'a str> for Source<'a> {
+    fn from(content: &'a str) -> Self {
+        Source::new(content)
     }
 }
 
+impl From<String> for Source<'static> {
+    fn from(content: String) -> Self {
+        Self {
+            content: Cow::Owned(content),
+            filename: None,
+            errors: ErrorReporter::new(),
+        }
+    }
+}
+
+/// The severity of a [`SyntaxError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Prevents the program from being executed.
+    Error,
+    /// Reported to the user but does not prevent the program from being executed.
+    Warning,
+}
+
 /// Represents a syntax error (compile time error).
+/// A `SyntaxError` can also be a warning (see [`Severity`]).
 #[derive(Debug, Clone)]
 pub struct SyntaxError {
     message: String,
     span: Range<usize>,
+    severity: Severity,
 }
 
 impl SyntaxError {
@@ -44,8 +114,47 @@ impl SyntaxError {
         Self {
             message: message.to_string(),
             span,
+            severity: Severity::Error,
         }
     }
+
+    /// Create a new warning with the specified `message` and `span`.
+    pub fn warning(message: impl ToString, span: Range<usize>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(message, span)
+        }
+    }
+
+    /// Returns the error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the span of the source code where the error occurred.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// Returns the severity of the error.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{severity}: {message} at position {position}",
+            severity = match self.severity {
+                Severity::Error => "ERROR",
+                Severity::Warning => "WARNING",
+            },
+            message = self.message,
+            position = self.span.start
+        )
+    }
 }
 
 /// Manages all the errors.
@@ -67,6 +176,55 @@ impl ErrorReporter {
         // This should be the only place where self.errors is borrowed mutably.
         self.errors.borrow_mut().push(error);
     }
+
+    /// Returns all the accumulated errors with the specified `severity`.
+    fn with_severity(&self, severity: Severity) -> Vec<SyntaxError> {
+        self.errors
+            .borrow()
+            .iter()
+            .filter(|error| error.severity == severity)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns all the accumulated errors with [`Severity::Error`].
+    pub fn errors(&self) -> Vec<SyntaxError> {
+        self.with_severity(Severity::Error)
+    }
+
+    /// Returns all the accumulated errors with [`Severity::Warning`].
+    pub fn warnings(&self) -> Vec<SyntaxError> {
+        self.with_severity(Severity::Warning)
+    }
+
+    /// Returns all the accumulated errors sorted by span start, where errors with the same severity and overlapping or
+    /// adjacent spans are merged into a single error. This reduces the noise of cascading errors.
+    /// A merged error keeps the message of the first error and spans all the merged errors.
+    /// Errors with an empty span are never merged because the span does not locate them (e.g. two different undefined
+    /// symbols reported without a position).
+    pub fn merged(&self) -> Vec<SyntaxError> {
+        let mut merged: Vec<SyntaxError> = Vec::new();
+        for &severity in &[Severity::Error, Severity::Warning] {
+            let mut errors = self.with_severity(severity);
+            errors.sort_by_key(|error| error.span.start);
+
+            let start = merged.len();
+            for error in errors {
+                if let Some(last) = merged[start..].last_mut() {
+                    let can_merge = !last.span.is_empty()
+                        && !error.span.is_empty()
+                        && error.span.start <= last.span.end;
+                    if can_merge {
+                        last.span.end = last.span.end.max(error.span.end);
+                        continue;
+                    }
+                }
+                merged.push(error);
+            }
+        }
+        merged.sort_by_key(|error| error.span.start);
+        merged
+    }
 }
 
 impl Default for ErrorReporter {
@@ -79,14 +237,69 @@ impl fmt::Display for ErrorReporter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let errors = self.errors.borrow();
         for error in errors.iter() {
-            writeln!(
-                f,
-                "ERROR: {message} at position {position}",
-                message = error.message,
-                position = error.span.start
-            )?;
+            writeln!(f, "{}", error)?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let source: Source = "let x = 1;\nlet y = x;".into();
+        assert_eq!(source.line_col(0), (1, 1));
+        assert_eq!(source.line_col(4), (1, 5));
+        assert_eq!(source.line_col(11), (2, 1));
+        assert_eq!(source.line_col(15), (2, 5));
+    }
+
+    #[test]
+    fn test_merged() {
+        let reporter = ErrorReporter::new();
+        reporter.add_error(SyntaxError::new("Unexpected token", 8..9));
+        reporter.add_error(SyntaxError::new("Expected expression", 8..9));
+        let merged = reporter.merged();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].message(), "Unexpected token");
+        assert_eq!(merged[0].span(), 8..9);
+
+        // adjacent spans are merged, other severities and distant spans are not
+        reporter.add_error(SyntaxError::new("Unexpected token", 9..12));
+        reporter.add_error(SyntaxError::warning("Unused value", 10..11));
+        reporter.add_error(SyntaxError::new("Unexpected token", 20..21));
+        let merged = reporter.merged();
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].span(), 8..12);
+        assert_eq!(merged[1].severity(), Severity::Warning);
+        assert_eq!(merged[2].span(), 20..21);
+
+        // errors without a span are never merged, even if they are identical
+        let reporter = ErrorReporter::new();
+        reporter.add_error(SyntaxError::warning("Unreachable code", 0..0));
+        reporter.add_error(SyntaxError::warning("Unreachable code", 0..0));
+        reporter.add_error(SyntaxError::warning("Empty if body", 0..0));
+        reporter.add_error(SyntaxError::new("Unexpected token", 3..3));
+        reporter.add_error(SyntaxError::new("Unexpected token", 3..4));
+        assert_eq!(reporter.merged().len(), 5);
+    }
+
+    #[test]
+    fn test_display_error() {
+        let source = Source::from("let x = 1;\nlet y = ;".to_string());
+        let error = SyntaxError::new("Unexpected token", 19..20);
+        assert_eq!(
+            source.display_error(&error).to_string(),
+            "ERROR: Unexpected token at position 19"
+        );
+
+        let source = source.with_filename("main.ella");
+        assert_eq!(
+            source.display_error(&error).to_string(),
+            "main.ella:2:9: ERROR: Unexpected token at position 19"
+        );
+    }
+}
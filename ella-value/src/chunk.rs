@@ -1,233 +1,818 @@
-//! Definitions for [`Chunk`] and [`OpCode`].
-
-use std::collections::HashMap;
-
-use crate::{Value, ValueArray};
-use enum_primitive_derive::Primitive;
-
-/// Represents an opcode. Internally represented using 1 byte (`u8`).
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Primitive)]
-#[repr(u8)]
-pub enum OpCode {
-    /// Load a constant onto the stack.
-    /// *2 bytes (1 operand)*
-    Ldc = 0,
-    /// Load a f64 onto the stack.
-    /// *9 bytes (1 f64 le operand)*
-    Ldf64 = 26,
-    /// Load the constant 0 onto the stack.
-    /// *1 byte*
-    Ld0 = 27,
-    /// Load the constant 1 onto the stack.
-    /// *1 byte*
-    Ld1 = 28,
-    /// Load a local variable onto the stack.
-    /// *2 bytes (1 operand)*
-    LdLoc = 15,
-    /// Stores the top value on the stack into a local variable.
-    /// *2 bytes (1 operand)*
-    StLoc = 16,
-    /// Loads a global variable onto the stack.
-    /// The operand is the absolute position of the variable on the stack.
-    /// *2 bytes (1 operand)*
-    LdGlobal = 24,
-    /// Sets a global variable onto the stack.
-    /// The operand is the absolute position of the variable on the stack.
-    /// *2 bytes (1 operand)*
-    StGlobal = 25,
-    /// Loads an upvalue onto the stack.
-    /// *2 bytes (1 operand)*
-    LdUpVal = 17,
-    /// Stores the top value on the stack into an upvalue.
-    /// *2 bytes (1 operand)*
-    StUpVal = 18,
-    /// Closes an upvalue.
-    /// *1 byte*
-    CloseUpVal = 20,
-    /// Negate the last value on the stack.
-    /// *1 byte*
-    Neg = 1,
-    /// Logical not on a boolean value.
-    /// *1 byte*
-    Not = 2,
-    Add = 3,
-    Sub = 4,
-    Mul = 5,
-    Div = 6,
-    /// Returns the last value on the stack.
-    /// *1 byte*
-    Ret = 7,
-    /// Return the constant 0.
-    /// *1 byte*
-    Ret0 = 29,
-    /// Return the constant 1.
-    /// *1 byte*
-    Ret1 = 30,
-    /// Loads `true` onto the stack.
-    /// *1 byte*
-    LdTrue = 8,
-    /// Loads `false` onto the stack.
-    /// *1 byte*
-    LdFalse = 9,
-    Eq = 10,
-    Greater = 11,
-    Less = 12,
-    /// Pops and disposes the last value on the stack.
-    /// *1 byte*
-    Pop = 13,
-    /// Calls the function on the top of the stack.
-    /// To load the function, use `ldc` to load a function object.
-    /// Arity is the operand.
-    /// *2 bytes (1 operand)*
-    Calli = 14,
-    /// Creates a closure with a constant function and pushes it onto the stack.
-    /// *Variable number of operands*
-    Closure = 19,
-    /// Jump with the specified offset.
-    /// **NOTE**: `jmp` cannot jump backwards. To jump backwards, use [`OpCode::Loop`].
-    /// *2 bytes (1 u16 operand)*
-    Jmp = 21,
-    /// Jump with the specified offset if the last value on the stack is `true`.
-    /// **NOTE**: This instruction does not pop the stack.
-    /// *2 bytes (1 u16 operand)*
-    JmpIfFalse = 22,
-    /// Jump backwards with the specified offset.
-    /// *2 bytes (1 u16 operand)*
-    Loop = 23,
-}
-
-/// Represents a chunk of bytecode.
-#[derive(Debug, Clone)]
-pub struct Chunk {
-    /// A [`Vec`] of [`OpCode`]s and operands.
-    pub code: Vec<u8>, // a byte array
-    /// Source code positions for each byte in `code`.
-    pub lines: Vec<usize>,
-    /// Constant table for this [`Chunk`].
-    pub constants: ValueArray,
-    /// The name of the chunk.
-    /// For most cases, should be the name of the function.
-    /// If the [`Chunk`] is the top-level chunk, the name should `<global>`.
-    pub name: String,
-    /// Per line debug annotations.
-    pub(crate) debug_annotations: HashMap<usize, String>,
-}
-
-/// `u8` and `OpCode` should implement this trait.
-pub trait ToByteCode {
-    /// Transforms `self` into an `u8`.
-    fn to_byte_code(&self) -> u8;
-}
-
-impl ToByteCode for OpCode {
-    fn to_byte_code(&self) -> u8 {
-        *self as u8
-    }
-}
-
-impl ToByteCode for u8 {
-    fn to_byte_code(&self) -> u8 {
-        *self
-    }
-}
-
-impl Chunk {
-    /// Create an empty chunk with the specified `name`.
-    ///
-    /// # Example
-    /// ```
-    /// use ella_value::chunk::Chunk;
-    /// let chunk = Chunk::new("my_chunk".to_string());
-    /// assert_eq!(chunk.name, "my_chunk");
-    /// ```
-    pub fn new(name: String) -> Self {
-        Self {
-            code: Vec::new(),
-            lines: Vec::new(),
-            constants: ValueArray::new(),
-            name,
-            debug_annotations: HashMap::new(),
-        }
-    }
-
-    /// Write data to the [`Chunk`]. This can be an [`OpCode`] or an operand (`u8`).
-    /// Returns the index of the added byte.
-    ///
-    /// # Params
-    /// * `opcode` - The data to write to the chunk.
-    /// * `line` - The original source line. This is used for runtime error messages and debugging.
-    ///
-    /// # Example
-    /// ```
-    /// use ella_value::chunk::{Chunk, OpCode};
-    /// let mut chunk = Chunk::new("my_chunk".to_string());
-    /// let index = chunk.write_chunk(OpCode::Ldc, 0);
-    /// assert_eq!(index, 0);
-    /// let index = chunk.write_chunk(1, 0);
-    /// assert_eq!(index, 1);
-    /// assert_eq!(chunk.code, vec![0, 1]);
-    /// assert_eq!(chunk.lines, vec![0, 0]);
-    /// ```
-    pub fn write_chunk(&mut self, opcode: impl ToByteCode, line: usize) -> usize {
-        debug_assert_eq!(self.code.len(), self.lines.len());
-        self.code.push(opcode.to_byte_code());
-        self.lines.push(line);
-        debug_assert_eq!(self.code.len(), self.lines.len());
-        return self.code.len() - 1; // -1 to include the effect of adding the byte to self.code
-    }
-
-    /// Patches a `jmp` or `jmp_if_false` instruction to jump to current position.
-    pub fn patch_jump(&mut self, offset: usize) {
-        // -2 to adjust for the bytecode for the jump itself.
-        let jump = self.code.len() - offset - 2;
-
-        if jump > std::u16::MAX as usize {
-            panic!("cannot jump more than std::u16::MAX");
-        }
-
-        self.code[offset] = ((jump >> 8) & 0xff) as u8;
-        self.code[offset + 1] = (jump & 0xff) as u8;
-    }
-
-    /// Creates a `ldf64` instruction with the specified value.
-    pub fn emit_ldf64(&mut self, value: f64, line: usize) {
-        self.write_chunk(OpCode::Ldf64, line);
-        let bytes = value.to_le_bytes();
-        for byte in bytes.iter() {
-            self.write_chunk(*byte, line);
-        }
-    }
-
-    /// Add a constant to the constant table.
-    /// Returns the index of the added constant.
-    ///
-    /// # Example
-    /// ```
-    /// use ella_value::chunk::Chunk;
-    /// use ella_value::Value;
-    /// let mut chunk = Chunk::new("my_chunk".to_string());
-    /// let index = chunk.add_constant(Value::Bool(true));
-    /// assert_eq!(index, 0);
-    /// assert_eq!(chunk.constants, vec![Value::Bool(true)]);
-    /// let index = chunk.add_constant(Value::Number(2.0));
-    /// assert_eq!(index, 1);
-    /// assert_eq!(chunk.constants, vec![Value::Bool(true), Value::Number(2.0)]);
-    /// ```
-    pub fn add_constant(&mut self, value: Value) -> u8 {
-        self.constants.push(value);
-        let loc = self.constants.len() - 1;
-        if loc as u8 as usize != loc {
-            todo!("load constant wide");
-        }
-        loc as u8
-    }
-
-    /// Adds a debug annotation (shown when disassembling) to the last byte in the chunk.
-    /// This method should be called right after writing the [`OpCode`] and before writing any operands.
-    ///
-    /// **NOTE**: overrides any existing debug annotation.
-    pub fn add_debug_annotation_at_last(&mut self, message: impl ToString) {
-        self.debug_annotations
-            .insert(self.code.len() - 1, message.to_string());
-    }
-}
+//! Definitions for [`Chunk`] and [`OpCode`].
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::object::ObjKind;
+use crate::{Value, ValueArray};
+use enum_primitive_derive::Primitive;
+use num_traits::FromPrimitive;
+
+/// Represents an opcode. Internally represented using 1 byte (`u8`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Primitive)]
+#[repr(u8)]
+pub enum OpCode {
+    /// Load a constant onto the stack.
+    /// *2 bytes (1 operand)*
+    Ldc = 0,
+    /// Load a f64 onto the stack.
+    /// *9 bytes (1 f64 le operand)*
+    Ldf64 = 26,
+    /// Load the constant 0 onto the stack.
+    /// *1 byte*
+    Ld0 = 27,
+    /// Load the constant 1 onto the stack.
+    /// *1 byte*
+    Ld1 = 28,
+    /// Load a local variable onto the stack.
+    /// *2 bytes (1 operand)*
+    LdLoc = 15,
+    /// Stores the top value on the stack into a local variable.
+    /// *2 bytes (1 operand)*
+    StLoc = 16,
+    /// Load the local variable with offset 0 onto the stack.
+    /// *1 byte*
+    LdLoc0 = 31,
+    /// Load the local variable with offset 1 onto the stack.
+    /// *1 byte*
+    LdLoc1 = 32,
+    /// Load the local variable with offset 2 onto the stack.
+    /// *1 byte*
+    LdLoc2 = 33,
+    /// Load the local variable with offset 3 onto the stack.
+    /// *1 byte*
+    LdLoc3 = 34,
+    /// Stores the top value on the stack into the local variable with offset 0.
+    /// *1 byte*
+    StLoc0 = 35,
+    /// Stores the top value on the stack into the local variable with offset 1.
+    /// *1 byte*
+    StLoc1 = 36,
+    /// Stores the top value on the stack into the local variable with offset 2.
+    /// *1 byte*
+    StLoc2 = 37,
+    /// Stores the top value on the stack into the local variable with offset 3.
+    /// *1 byte*
+    StLoc3 = 38,
+    /// Loads a global variable onto the stack.
+    /// The operand is the absolute position of the variable on the stack.
+    /// *2 bytes (1 operand)*
+    LdGlobal = 24,
+    /// Sets a global variable onto the stack.
+    /// The operand is the absolute position of the variable on the stack.
+    /// *2 bytes (1 operand)*
+    StGlobal = 25,
+    /// Loads a late-bound global variable onto the stack.
+    /// The operand is the index of a string constant with the name of the variable.
+    /// *2 bytes (1 operand)*
+    LdLateGlobal = 39,
+    /// Sets a late-bound global variable.
+    /// The operand is the index of a string constant with the name of the variable.
+    /// *2 bytes (1 operand)*
+    StLateGlobal = 40,
+    /// Loads an upvalue onto the stack.
+    /// *2 bytes (1 operand)*
+    LdUpVal = 17,
+    /// Stores the top value on the stack into an upvalue.
+    /// *2 bytes (1 operand)*
+    StUpVal = 18,
+    /// Closes an upvalue.
+    /// *1 byte*
+    CloseUpVal = 20,
+    /// Negate the last value on the stack.
+    /// *1 byte*
+    Neg = 1,
+    /// Logical not on a boolean value.
+    /// *1 byte*
+    Not = 2,
+    Add = 3,
+    Sub = 4,
+    Mul = 5,
+    Div = 6,
+    /// Returns the last value on the stack.
+    /// *1 byte*
+    Ret = 7,
+    /// Return the constant 0.
+    /// *1 byte*
+    Ret0 = 29,
+    /// Return the constant 1.
+    /// *1 byte*
+    Ret1 = 30,
+    /// Return `nil`. Emitted at the end of every function body.
+    /// *1 byte*
+    RetNil = 41,
+    /// Loads `true` onto the stack.
+    /// *1 byte*
+    LdTrue = 8,
+    /// Loads `false` onto the stack.
+    /// *1 byte*
+    LdFalse = 9,
+    /// Loads `nil` onto the stack.
+    /// *1 byte*
+    LdNil = 42,
+    /// Pops the specified number of values and pushes an array with the values (in push order).
+    /// *2 bytes (1 operand)*
+    MakeArray = 44,
+    /// Pops an array and pushes its elements (in order). Used for destructuring tuples.
+    /// Raises a runtime error if the value is not an array or if the length of the array is not the operand.
+    /// *2 bytes (1 operand)*
+    Unpack = 45,
+    Eq = 10,
+    Greater = 11,
+    Less = 12,
+    /// Pops and disposes the last value on the stack.
+    /// *1 byte*
+    Pop = 13,
+    /// Calls the function on the top of the stack.
+    /// To load the function, use `ldc` to load a function object.
+    /// Arity is the operand.
+    /// *2 bytes (1 operand)*
+    Calli = 14,
+    /// Creates a closure with a constant function and pushes it onto the stack.
+    /// *Variable number of operands*
+    Closure = 19,
+    /// Jump with the specified offset.
+    /// **NOTE**: `jmp` cannot jump backwards. To jump backwards, use [`OpCode::Loop`].
+    /// *2 bytes (1 u16 operand)*
+    Jmp = 21,
+    /// Jump with the specified offset if the last value on the stack is `false`.
+    /// Raises a runtime error if the last value on the stack is not a boolean.
+    /// **NOTE**: This instruction does not pop the stack.
+    /// *2 bytes (1 u16 operand)*
+    JmpIfFalse = 22,
+    /// Jump with the specified offset if the last value on the stack is `true`.
+    /// Raises a runtime error if the last value on the stack is not a boolean.
+    /// **NOTE**: This instruction does not pop the stack.
+    /// *2 bytes (1 u16 operand)*
+    JmpIfTrue = 43,
+    /// Jump backwards with the specified offset.
+    /// *2 bytes (1 u16 operand)*
+    Loop = 23,
+    /// Divides the last two values on the stack and rounds the quotient down to the nearest integer.
+    /// *1 byte*
+    FloorDiv = 46,
+}
+
+impl OpCode {
+    /// Returns the number of operand bytes following the opcode.
+    /// For [`OpCode::Closure`], this does not include the upvalue operands which depend on the function constant.
+    /// See [`Chunk::instr_len`].
+    pub fn operand_len(&self) -> usize {
+        match self {
+            OpCode::Ldf64 => 8,
+            OpCode::Ldc
+            | OpCode::LdLoc
+            | OpCode::StLoc
+            | OpCode::LdGlobal
+            | OpCode::StGlobal
+            | OpCode::LdLateGlobal
+            | OpCode::StLateGlobal
+            | OpCode::LdUpVal
+            | OpCode::StUpVal
+            | OpCode::Calli
+            | OpCode::MakeArray
+            | OpCode::Unpack
+            | OpCode::Closure => 1,
+            OpCode::Jmp | OpCode::JmpIfFalse | OpCode::JmpIfTrue | OpCode::Loop => 2,
+            OpCode::Ld0
+            | OpCode::Ld1
+            | OpCode::LdLoc0
+            | OpCode::LdLoc1
+            | OpCode::LdLoc2
+            | OpCode::LdLoc3
+            | OpCode::StLoc0
+            | OpCode::StLoc1
+            | OpCode::StLoc2
+            | OpCode::StLoc3
+            | OpCode::CloseUpVal
+            | OpCode::Neg
+            | OpCode::Not
+            | OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::FloorDiv
+            | OpCode::Ret
+            | OpCode::Ret0
+            | OpCode::Ret1
+            | OpCode::RetNil
+            | OpCode::LdTrue
+            | OpCode::LdFalse
+            | OpCode::LdNil
+            | OpCode::Eq
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Pop => 0,
+        }
+    }
+
+    /// Returns the change in stack height caused by executing the instruction.
+    /// `operand` is the first operand byte (only used by [`OpCode::Calli`], [`OpCode::MakeArray`] and
+    /// [`OpCode::Unpack`]).
+    /// Return instructions leave the current frame and are reported as `0`.
+    pub fn stack_effect(&self, operand: u8) -> i32 {
+        match self {
+            OpCode::Ldc
+            | OpCode::Ldf64
+            | OpCode::Ld0
+            | OpCode::Ld1
+            | OpCode::LdLoc
+            | OpCode::LdLoc0
+            | OpCode::LdLoc1
+            | OpCode::LdLoc2
+            | OpCode::LdLoc3
+            | OpCode::LdGlobal
+            | OpCode::LdLateGlobal
+            | OpCode::LdUpVal
+            | OpCode::LdTrue
+            | OpCode::LdFalse
+            | OpCode::LdNil
+            | OpCode::Closure => 1,
+            OpCode::StLoc
+            | OpCode::StLoc0
+            | OpCode::StLoc1
+            | OpCode::StLoc2
+            | OpCode::StLoc3
+            | OpCode::StGlobal
+            | OpCode::StLateGlobal
+            | OpCode::StUpVal
+            | OpCode::Neg
+            | OpCode::Not
+            | OpCode::Ret
+            | OpCode::Ret0
+            | OpCode::Ret1
+            | OpCode::RetNil
+            | OpCode::Jmp
+            | OpCode::JmpIfFalse
+            | OpCode::JmpIfTrue
+            | OpCode::Loop => 0,
+            OpCode::CloseUpVal
+            | OpCode::Pop
+            | OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::FloorDiv
+            | OpCode::Eq
+            | OpCode::Greater
+            | OpCode::Less => -1,
+            // pops the arguments and the function, pushes the return value
+            OpCode::Calli => -(operand as i32),
+            OpCode::MakeArray => 1 - operand as i32,
+            OpCode::Unpack => operand as i32 - 1,
+        }
+    }
+}
+
+/// Returns `true` if both constants are identical and can share the same entry in the constant table.
+/// Unlike [`Value`]'s `PartialEq`, numbers are compared by their bit pattern so that `NaN`s with the same bits are
+/// identical and `0.0` and `-0.0` are distinct. Objects are compared by identity.
+///
+/// # Example
+/// ```
+/// use ella_value::chunk::same_constant;
+/// use ella_value::Value;
+///
+/// assert!(same_constant(&Value::Number(f64::NAN), &Value::Number(f64::NAN)));
+/// assert!(!same_constant(&Value::Number(0.0), &Value::Number(-0.0)));
+/// ```
+pub fn same_constant(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.to_bits() == r.to_bits(),
+        (Value::Bool(l), Value::Bool(r)) => l == r,
+        (Value::Nil, Value::Nil) => true,
+        (Value::Object(l), Value::Object(r)) => Rc::ptr_eq(l, r),
+        _ => false,
+    }
+}
+
+/// Represents a chunk of bytecode.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    /// A [`Vec`] of [`OpCode`]s and operands.
+    pub code: Vec<u8>, // a byte array
+    /// Run-length encoded source code positions for the bytes in `code`.
+    /// Each entry is a `(line, run_length)` pair. Use [`Chunk::line_at`] to get the line of a byte.
+    pub(crate) lines: Vec<(usize, usize)>,
+    /// Constant table for this [`Chunk`].
+    pub constants: ValueArray,
+    /// The name of the chunk.
+    /// For most cases, should be the name of the function.
+    /// If the [`Chunk`] is the top-level chunk, the name should `<global>`.
+    pub name: String,
+    /// The source line of the declaration of the function this [`Chunk`] belongs to.
+    /// `None` for the top-level chunk and for chunks that are not created from source code.
+    pub declaration_line: Option<usize>,
+    /// Per line debug annotations.
+    pub(crate) debug_annotations: HashMap<usize, String>,
+}
+
+/// `u8` and `OpCode` should implement this trait.
+pub trait ToByteCode {
+    /// Transforms `self` into an `u8`.
+    fn to_byte_code(&self) -> u8;
+}
+
+impl ToByteCode for OpCode {
+    fn to_byte_code(&self) -> u8 {
+        *self as u8
+    }
+}
+
+impl ToByteCode for u8 {
+    fn to_byte_code(&self) -> u8 {
+        *self
+    }
+}
+
+impl Chunk {
+    /// Create an empty chunk with the specified `name`.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::Chunk;
+    /// let chunk = Chunk::new("my_chunk".to_string());
+    /// assert_eq!(chunk.name, "my_chunk");
+    /// ```
+    pub fn new(name: String) -> Self {
+        Self {
+            code: Vec::new(),
+            lines: Vec::new(),
+            constants: ValueArray::new(),
+            name,
+            declaration_line: None,
+            debug_annotations: HashMap::new(),
+        }
+    }
+
+    /// Write data to the [`Chunk`]. This can be an [`OpCode`] or an operand (`u8`).
+    /// Returns the index of the added byte.
+    ///
+    /// # Params
+    /// * `opcode` - The data to write to the chunk.
+    /// * `line` - The original source line. This is used for runtime error messages and debugging.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::{Chunk, OpCode};
+    /// let mut chunk = Chunk::new("my_chunk".to_string());
+    /// let index = chunk.write_chunk(OpCode::Ldc, 0);
+    /// assert_eq!(index, 0);
+    /// let index = chunk.write_chunk(1, 0);
+    /// assert_eq!(index, 1);
+    /// assert_eq!(chunk.code, vec![0, 1]);
+    /// assert_eq!(chunk.line_at(1), 0);
+    /// ```
+    pub fn write_chunk(&mut self, opcode: impl ToByteCode, line: usize) -> usize {
+        self.code.push(opcode.to_byte_code());
+        match self.lines.last_mut() {
+            Some((last_line, run_length)) if *last_line == line => *run_length += 1,
+            _ => self.lines.push((line, 1)),
+        }
+        self.code.len() - 1 // -1 to include the effect of adding the byte to self.code
+    }
+
+    /// Returns the source line of the byte at `offset`.
+    ///
+    /// # Panics
+    /// Panics if `offset` is out of bounds.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::{Chunk, OpCode};
+    /// let mut chunk = Chunk::new("my_chunk".to_string());
+    /// chunk.write_chunk(OpCode::Ld0, 1);
+    /// chunk.write_chunk(OpCode::Ld1, 1);
+    /// chunk.write_chunk(OpCode::Add, 2);
+    /// chunk.write_chunk(OpCode::Pop, 1);
+    /// chunk.write_chunk(OpCode::Ld0, 3);
+    /// chunk.write_chunk(OpCode::Pop, 3);
+    /// assert_eq!(chunk.line_at(0), 1);
+    /// assert_eq!(chunk.line_at(1), 1);
+    /// assert_eq!(chunk.line_at(2), 2);
+    /// assert_eq!(chunk.line_at(3), 1);
+    /// assert_eq!(chunk.line_at(4), 3);
+    /// assert_eq!(chunk.line_at(5), 3);
+    /// ```
+    pub fn line_at(&self, offset: usize) -> usize {
+        let mut run_start = 0;
+        for (line, run_length) in &self.lines {
+            run_start += run_length;
+            if offset < run_start {
+                return *line;
+            }
+        }
+        panic!("offset {} is out of bounds", offset);
+    }
+
+    /// Patches a `jmp`, `jmp_if_false` or `jmp_if_true` instruction to jump to current position.
+    pub fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the bytecode for the jump itself.
+        let jump = self.code.len() - offset - 2;
+
+        if jump > u16::MAX as usize {
+            panic!("cannot jump more than std::u16::MAX");
+        }
+
+        self.code[offset] = ((jump >> 8) & 0xff) as u8;
+        self.code[offset + 1] = (jump & 0xff) as u8;
+    }
+
+    /// Creates a `ldf64` instruction with the specified value.
+    pub fn emit_ldf64(&mut self, value: f64, line: usize) {
+        self.write_chunk(OpCode::Ldf64, line);
+        let bytes = value.to_le_bytes();
+        for byte in bytes.iter() {
+            self.write_chunk(*byte, line);
+        }
+    }
+
+    /// Emits a `ldloc` instruction for the local variable at `local_offset`.
+    /// Emits the specialized 1 byte `ldloc0` to `ldloc3` instructions when possible.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::{Chunk, OpCode};
+    ///
+    /// let mut chunk = Chunk::new("<global>".to_string());
+    /// chunk.emit_ldloc(1, 0);
+    /// chunk.emit_ldloc(4, 0);
+    /// assert_eq!(chunk.code, vec![OpCode::LdLoc1 as u8, OpCode::LdLoc as u8, 4]);
+    /// ```
+    pub fn emit_ldloc(&mut self, local_offset: u8, line: usize) {
+        if local_offset <= 3 {
+            // ldloc0 to ldloc3 have consecutive opcodes
+            self.write_chunk(OpCode::LdLoc0 as u8 + local_offset, line);
+        } else {
+            self.write_chunk(OpCode::LdLoc, line);
+            self.write_chunk(local_offset, line);
+        }
+    }
+
+    /// Emits a `stloc` instruction for the local variable at `local_offset`.
+    /// Emits the specialized 1 byte `stloc0` to `stloc3` instructions when possible.
+    pub fn emit_stloc(&mut self, local_offset: u8, line: usize) {
+        if local_offset <= 3 {
+            // stloc0 to stloc3 have consecutive opcodes
+            self.write_chunk(OpCode::StLoc0 as u8 + local_offset, line);
+        } else {
+            self.write_chunk(OpCode::StLoc, line);
+            self.write_chunk(local_offset, line);
+        }
+    }
+
+    /// Add a constant to the constant table.
+    /// Returns the index of the added constant.
+    /// If an identical constant already exists, it is reused instead (see [`same_constant`]).
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::Chunk;
+    /// use ella_value::Value;
+    /// let mut chunk = Chunk::new("my_chunk".to_string());
+    /// let index = chunk.add_constant(Value::Bool(true));
+    /// assert_eq!(index, 0);
+    /// assert_eq!(chunk.constants, vec![Value::Bool(true)]);
+    /// let index = chunk.add_constant(Value::Number(2.0));
+    /// assert_eq!(index, 1);
+    /// assert_eq!(chunk.constants, vec![Value::Bool(true), Value::Number(2.0)]);
+    /// let index = chunk.add_constant(Value::Bool(true));
+    /// assert_eq!(index, 0);
+    /// ```
+    pub fn add_constant(&mut self, value: Value) -> u8 {
+        let loc = match self
+            .constants
+            .iter()
+            .position(|constant| same_constant(constant, &value))
+        {
+            Some(loc) => loc,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        };
+        if loc as u8 as usize != loc {
+            todo!("load constant wide");
+        }
+        loc as u8
+    }
+
+    /// Appends the bytecode of `other` to the end of this chunk.
+    /// The constants of `other` are added to the constant table of this chunk and the constant operands are remapped
+    /// accordingly. Jump offsets are relative to the jump instruction and remain valid without patching.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::{Chunk, OpCode};
+    /// use ella_value::Value;
+    ///
+    /// let mut chunk = Chunk::new("first".to_string());
+    /// let constant = chunk.add_constant(Value::Number(2.0));
+    /// chunk.write_chunk(OpCode::Ldc, 0);
+    /// chunk.write_chunk(constant, 0);
+    ///
+    /// let mut other = Chunk::new("second".to_string());
+    /// let constant = other.add_constant(Value::Number(3.0));
+    /// other.write_chunk(OpCode::Ldc, 1);
+    /// other.write_chunk(constant, 1);
+    ///
+    /// chunk.append(&other);
+    /// assert_eq!(chunk.code, vec![OpCode::Ldc as u8, 0, OpCode::Ldc as u8, 1]);
+    /// assert_eq!(chunk.constants, vec![Value::Number(2.0), Value::Number(3.0)]);
+    /// assert_eq!(chunk.line_at(2), 1);
+    /// ```
+    pub fn append(&mut self, other: &Chunk) {
+        let base = self.code.len();
+        let constant_map: Vec<u8> = other
+            .constants
+            .iter()
+            .map(|constant| self.add_constant(constant.clone()))
+            .collect();
+
+        let mut bytes = other.code.iter();
+        for &(line, run_length) in &other.lines {
+            for byte in bytes.by_ref().take(run_length) {
+                self.write_chunk(*byte, line);
+            }
+        }
+
+        for offset in other.instr_offsets() {
+            match OpCode::from_u8(other.code[offset]) {
+                Some(OpCode::Ldc)
+                | Some(OpCode::LdLateGlobal)
+                | Some(OpCode::StLateGlobal)
+                | Some(OpCode::Closure) => {
+                    let constant_index = other.code[offset + 1] as usize;
+                    self.code[base + offset + 1] = constant_map[constant_index];
+                }
+                _ => {}
+            }
+        }
+
+        for (offset, annotation) in &other.debug_annotations {
+            self.debug_annotations
+                .insert(base + offset, annotation.clone());
+        }
+    }
+
+    /// Returns the length (in bytes) of the instruction at `offset`, including the opcode and all operands.
+    /// Invalid opcodes are treated as 1 byte instructions.
+    pub fn instr_len(&self, offset: usize) -> usize {
+        match OpCode::from_u8(self.code[offset]) {
+            Some(OpCode::Closure) => {
+                let constant_index = self.code[offset + 1];
+                let upvalues_count = match &self.constants[constant_index as usize] {
+                    Value::Object(obj) => match &obj.kind {
+                        ObjKind::Fn(func) => func.upvalues_count,
+                        _ => 0,
+                    },
+                    _ => 0,
+                };
+                // 2 bytes (is_local and index) per upvalue
+                1 + OpCode::Closure.operand_len() + upvalues_count * 2
+            }
+            Some(opcode) => 1 + opcode.operand_len(),
+            None => 1,
+        }
+    }
+
+    /// Simulates the stack height of the instructions in `range`, following jumps, and returns the net change in
+    /// stack height when execution falls through to `range.end`.
+    /// Returns `Ok(None)` if every path leaves the frame with a return instruction.
+    ///
+    /// Returns an error if the stack height would drop below the height at `range.start`, if a jump leaves `range`
+    /// or if two paths reach the same instruction with different stack heights.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::{Chunk, OpCode};
+    /// let mut chunk = Chunk::new("my_chunk".to_string());
+    /// chunk.write_chunk(OpCode::Ld1, 0);
+    /// chunk.write_chunk(OpCode::Ld1, 0);
+    /// chunk.write_chunk(OpCode::Add, 0);
+    /// assert_eq!(chunk.net_stack_effect(0..3), Ok(Some(1)));
+    /// chunk.write_chunk(OpCode::Pop, 0);
+    /// assert_eq!(chunk.net_stack_effect(0..4), Ok(Some(0)));
+    /// ```
+    pub fn net_stack_effect(&self, range: Range<usize>) -> Result<Option<i32>, String> {
+        let mut heights = HashMap::new();
+        let mut exit_height = None;
+        let mut worklist = vec![(range.start, 0)];
+
+        while let Some((offset, height)) = worklist.pop() {
+            if height < 0 {
+                return Err(format!("stack underflow at offset {}", offset));
+            }
+            if offset == range.end {
+                match exit_height {
+                    Some(exit_height) if exit_height != height => {
+                        return Err(format!(
+                            "paths exit with different stack heights ({} and {})",
+                            exit_height, height
+                        ))
+                    }
+                    _ => exit_height = Some(height),
+                }
+                continue;
+            }
+            if !range.contains(&offset) {
+                return Err(format!("jump to offset {} outside of range", offset));
+            }
+            match heights.insert(offset, height) {
+                Some(previous) if previous != height => {
+                    return Err(format!(
+                        "offset {} is reached with different stack heights ({} and {})",
+                        offset, previous, height
+                    ))
+                }
+                Some(_) => continue, // already visited
+                None => {}
+            }
+
+            let opcode = match OpCode::from_u8(self.code[offset]) {
+                Some(opcode) => opcode,
+                None => return Err(format!("invalid opcode at offset {}", offset)),
+            };
+            let next = offset + self.instr_len(offset);
+            let read_u16 =
+                || ((self.code[offset + 1] as usize) << 8) | self.code[offset + 2] as usize;
+            match opcode {
+                OpCode::Ret | OpCode::Ret0 | OpCode::Ret1 | OpCode::RetNil => {}
+                OpCode::Jmp => worklist.push((next + read_u16(), height)),
+                OpCode::JmpIfFalse | OpCode::JmpIfTrue => {
+                    worklist.push((next, height));
+                    worklist.push((next + read_u16(), height));
+                }
+                OpCode::Loop => worklist.push((next - read_u16(), height)),
+                _ => {
+                    let operand = self.code.get(offset + 1).copied().unwrap_or(0);
+                    worklist.push((next, height + opcode.stack_effect(operand)));
+                }
+            }
+        }
+
+        Ok(exit_height)
+    }
+
+    /// Returns an iterator over the offsets of every instruction in the chunk.
+    fn instr_offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset < self.code.len() {
+                let current = offset;
+                offset += self.instr_len(offset);
+                Some(current)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the size of the bytecode in bytes.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::{Chunk, OpCode};
+    /// let mut chunk = Chunk::new("my_chunk".to_string());
+    /// chunk.emit_ldf64(2.0, 0);
+    /// chunk.write_chunk(OpCode::Neg, 0);
+    /// assert_eq!(chunk.code_size(), 10);
+    /// ```
+    pub fn code_size(&self) -> usize {
+        self.code.len()
+    }
+
+    /// Returns the number of instructions in the chunk. Operands are not counted.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::{Chunk, OpCode};
+    /// let mut chunk = Chunk::new("my_chunk".to_string());
+    /// chunk.emit_ldf64(2.0, 0);
+    /// chunk.write_chunk(OpCode::Neg, 0);
+    /// chunk.write_chunk(OpCode::Ld1, 0);
+    /// chunk.write_chunk(OpCode::Add, 0);
+    /// assert_eq!(chunk.instruction_count(), 4);
+    /// ```
+    pub fn instruction_count(&self) -> usize {
+        self.instr_offsets().count()
+    }
+
+    /// Returns the number of times each [`OpCode`] appears in the chunk.
+    /// Invalid opcodes are not included.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::chunk::{Chunk, OpCode};
+    /// let mut chunk = Chunk::new("my_chunk".to_string());
+    /// chunk.write_chunk(OpCode::Ld1, 0);
+    /// chunk.write_chunk(OpCode::Ld1, 0);
+    /// chunk.write_chunk(OpCode::Add, 0);
+    /// let histogram = chunk.opcode_histogram();
+    /// assert_eq!(histogram[&OpCode::Ld1], 2);
+    /// assert_eq!(histogram[&OpCode::Add], 1);
+    /// assert_eq!(histogram.get(&OpCode::Sub), None);
+    /// ```
+    pub fn opcode_histogram(&self) -> HashMap<OpCode, usize> {
+        let mut histogram = HashMap::new();
+        for offset in self.instr_offsets() {
+            if let Some(opcode) = OpCode::from_u8(self.code[offset]) {
+                *histogram.entry(opcode).or_insert(0) += 1;
+            }
+        }
+        histogram
+    }
+
+    /// Adds a debug annotation (shown when disassembling) to the last byte in the chunk.
+    /// This method should be called right after writing the [`OpCode`] and before writing any operands.
+    ///
+    /// **NOTE**: overrides any existing debug annotation.
+    pub fn add_debug_annotation_at_last(&mut self, message: impl ToString) {
+        self.add_debug_annotation(self.code.len() - 1, message);
+    }
+
+    /// Adds a debug annotation (shown when disassembling) to the instruction at `offset`.
+    ///
+    /// **NOTE**: overrides any existing debug annotation.
+    pub fn add_debug_annotation(&mut self, offset: usize, message: impl ToString) {
+        self.debug_annotations.insert(offset, message.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_constant_dedup() {
+        let mut chunk = Chunk::new("my_chunk".to_string());
+        let nan = chunk.add_constant(Value::Number(f64::NAN));
+        assert_eq!(chunk.add_constant(Value::Number(f64::NAN)), nan);
+
+        let zero = chunk.add_constant(Value::Number(0.0));
+        let neg_zero = chunk.add_constant(Value::Number(-0.0));
+        assert_ne!(zero, neg_zero);
+        assert_eq!(chunk.add_constant(Value::Number(-0.0)), neg_zero);
+        assert_eq!(chunk.constants.len(), 3);
+    }
+
+    #[test]
+    fn test_append_remaps_constants() {
+        let mut chunk = Chunk::new("first".to_string());
+        chunk.add_constant(Value::Number(1.0));
+        chunk.add_constant(Value::Number(2.0));
+
+        let mut other = Chunk::new("second".to_string());
+        let two = other.add_constant(Value::Number(2.0));
+        let three = other.add_constant(Value::Number(3.0));
+        other.write_chunk(OpCode::Ldc, 4);
+        other.write_chunk(three, 4);
+        other.write_chunk(OpCode::StLateGlobal, 5);
+        other.write_chunk(two, 5);
+        other.add_debug_annotation(2, "annotation");
+
+        chunk.write_chunk(OpCode::Ld0, 1);
+        chunk.append(&other);
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::Ld0 as u8,
+                OpCode::Ldc as u8,
+                2,
+                OpCode::StLateGlobal as u8,
+                1
+            ]
+        );
+        assert_eq!(chunk.constants.len(), 3);
+        assert_eq!(chunk.line_at(0), 1);
+        assert_eq!(chunk.line_at(2), 4);
+        assert_eq!(chunk.line_at(4), 5);
+        assert_eq!(chunk.debug_annotations[&3], "annotation");
+    }
+
+    #[test]
+    fn test_net_stack_effect_branches() {
+        let mut chunk = Chunk::new("my_chunk".to_string());
+        chunk.write_chunk(OpCode::LdTrue, 0);
+        chunk.write_chunk(OpCode::JmpIfFalse, 0);
+        chunk.write_chunk(0, 0);
+        chunk.write_chunk(1, 0);
+        chunk.write_chunk(OpCode::Ld1, 0); // only pushed on one path
+        assert!(chunk.net_stack_effect(0..chunk.code.len()).is_err());
+
+        let mut chunk = Chunk::new("my_chunk".to_string());
+        chunk.write_chunk(OpCode::LdTrue, 0);
+        chunk.write_chunk(OpCode::JmpIfFalse, 0);
+        chunk.write_chunk(0, 0);
+        chunk.write_chunk(1, 0);
+        chunk.write_chunk(OpCode::Ret, 0);
+        chunk.write_chunk(OpCode::Pop, 0);
+        assert_eq!(chunk.net_stack_effect(0..chunk.code.len()), Ok(Some(0)));
+
+        let mut chunk = Chunk::new("my_chunk".to_string());
+        chunk.write_chunk(OpCode::Ld1, 0);
+        chunk.write_chunk(OpCode::Ret, 0);
+        assert_eq!(chunk.net_stack_effect(0..2), Ok(None));
+    }
+}
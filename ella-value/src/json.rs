@@ -0,0 +1,157 @@
+//! Conversion of [`Value`]s to and from JSON. Requires the `serde` feature.
+//!
+//! Numbers, booleans, `nil`, strings, arrays and maps (JSON objects) can be converted. Functions cannot be represented
+//! in JSON and are reported as errors. String builders are converted to the string they contain.
+
+use serde_json::Value as Json;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::object::{Obj, ObjKind};
+use crate::Value;
+
+/// Serializes `value` to a JSON string.
+/// Returns an error if `value` contains a function, a number that is not finite (`NaN` or infinity) or a map that
+/// contains itself.
+///
+/// # Example
+/// ```
+/// use ella_value::json::value_to_json;
+/// use ella_value::Value;
+///
+/// let value = Value::from(vec![Value::Number(1.0), Value::from("a"), Value::Nil]);
+/// assert_eq!(value_to_json(&value).unwrap(), r#"[1.0,"a",null]"#);
+/// ```
+pub fn value_to_json(value: &Value) -> Result<String, String> {
+    Ok(to_json(value, &mut Vec::new())?.to_string())
+}
+
+/// Deserializes a [`Value`] from a JSON string. JSON objects are deserialized into maps.
+/// Returns an error if `json` is not valid JSON.
+pub fn value_from_json(json: &str) -> Result<Value, String> {
+    let json: Json = serde_json::from_str(json).map_err(|err| format!("Invalid JSON: {}", err))?;
+    from_json(&json)
+}
+
+/// `ancestors` contains the maps that are currently being serialized (the maps containing `value`).
+fn to_json(value: &Value, ancestors: &mut Vec<*const Obj>) -> Result<Json, String> {
+    match value {
+        Value::Number(val) => serde_json::Number::from_f64(*val)
+            .map(Json::Number)
+            .ok_or_else(|| format!("Cannot serialize {} to JSON", val)),
+        Value::Bool(val) => Ok(Json::Bool(*val)),
+        Value::Nil => Ok(Json::Null),
+        Value::Object(obj) => match &obj.kind {
+            ObjKind::Str(str) => Ok(Json::String(str.to_string())),
+            ObjKind::Array(elements) => elements
+                .iter()
+                .map(|element| to_json(element, ancestors))
+                .collect::<Result<_, _>>()
+                .map(Json::Array),
+            ObjKind::StringBuilder(buffer) => Ok(Json::String(buffer.borrow().clone())),
+            ObjKind::Map(entries) => {
+                let ptr = Rc::as_ptr(obj);
+                if ancestors.contains(&ptr) {
+                    return Err(format!(
+                        "Cannot serialize {} to JSON, it contains itself",
+                        value
+                    ));
+                }
+                ancestors.push(ptr);
+                let object = entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), to_json(value, ancestors)?)))
+                    .collect::<Result<_, String>>();
+                ancestors.pop();
+                Ok(Json::Object(object?))
+            }
+            ObjKind::Fn(_) | ObjKind::Closure(_) | ObjKind::NativeFn(_) => Err(format!(
+                "Cannot serialize {} ({}) to JSON",
+                value,
+                value.type_name()
+            )),
+        },
+    }
+}
+
+fn from_json(json: &Json) -> Result<Value, String> {
+    match json {
+        Json::Null => Ok(Value::Nil),
+        Json::Bool(val) => Ok(Value::Bool(*val)),
+        // every JSON number can be represented as a f64 (possibly with a loss of precision)
+        Json::Number(val) => Ok(Value::Number(val.as_f64().unwrap())),
+        Json::String(str) => Ok(Value::from(str.as_str())),
+        Json::Array(elements) => elements
+            .iter()
+            .map(from_json)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::from),
+        Json::Object(object) => {
+            let entries = object
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), from_json(value)?)))
+                .collect::<Result<_, String>>()?;
+            Ok(Value::Object(Rc::new(Obj {
+                kind: ObjKind::Map(RefCell::new(entries)),
+            })))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = Value::from(vec![
+            Value::Number(1.5),
+            Value::Bool(true),
+            Value::Nil,
+            Value::from("string"),
+            Value::from(vec![Value::from(vec![]), Value::Number(-2.0)]),
+        ]);
+        let json = value_to_json(&value).unwrap();
+        assert_eq!(json, r#"[1.5,true,null,"string",[[],-2.0]]"#);
+        assert_eq!(
+            value_from_json(&json).unwrap().to_string(),
+            value.to_string()
+        );
+
+        assert_eq!(
+            value_from_json("[1, [2, 3]]").unwrap().to_string(),
+            "[1, [2, 3]]"
+        );
+    }
+
+    #[test]
+    fn test_map_round_trip() {
+        let value = value_from_json(r#"{"b": [1], "a": {"c": null}}"#).unwrap();
+        assert_eq!(value.to_string(), "{a: {c: nil}, b: [1]}");
+        assert_eq!(
+            value_to_json(&value).unwrap(),
+            r#"{"a":{"c":null},"b":[1.0]}"#
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(value_to_json(&Value::Number(f64::NAN)).is_err());
+        assert!(value_from_json("[1,").is_err());
+
+        let map = Rc::new(Obj::new_map());
+        if let ObjKind::Map(entries) = &map.kind {
+            entries
+                .borrow_mut()
+                .insert("self".to_string(), Value::Object(map.clone()));
+        }
+        assert_eq!(
+            value_to_json(&Value::Object(map.clone())).unwrap_err(),
+            "Cannot serialize {self: {...}} to JSON, it contains itself"
+        );
+        if let ObjKind::Map(entries) = &map.kind {
+            entries.borrow_mut().clear(); // break the reference cycle
+        }
+    }
+}
@@ -6,12 +6,14 @@
 
 pub mod chunk;
 pub mod disassemble;
+#[cfg(feature = "serde")]
+pub mod json;
 pub mod object;
 
 use std::fmt;
 use std::rc::Rc;
 
-use object::{Closure, Function, NativeFn, Obj, ObjKind};
+use object::{Arity, Closure, Function, NativeFn, NativeFnPtr, Obj, ObjKind, ReentrantNativeFn};
 
 /// Symbols that are available globally.
 #[derive(Default)]
@@ -24,18 +26,71 @@ impl BuiltinVars {
         Self::default()
     }
 
-    /// Add a builtin symbol.
+    /// Add a builtin symbol. The value can be any [`Value`], including a function object.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::{BuiltinVars, Value};
+    ///
+    /// let mut builtin_vars = BuiltinVars::new();
+    /// builtin_vars.add_value("answer".to_string(), Value::Number(42.0));
+    /// assert_eq!(builtin_vars.values[0].1, Value::Number(42.0));
+    /// ```
     pub fn add_value(&mut self, ident: String, value: Value) {
         self.values.push((ident, value));
     }
 
+    /// Add a builtin constant (e.g. a math constant like `PI`). Alias for [`Self::add_value`].
+    /// Builtin constants are global variables like any other builtin symbol. Scripts are not prevented from reassigning them.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::{BuiltinVars, Value};
+    ///
+    /// let mut builtin_vars = BuiltinVars::new();
+    /// builtin_vars.add_constant("PI", Value::Number(std::f64::consts::PI));
+    /// assert_eq!(builtin_vars.values[0].0, "PI");
+    /// ```
+    pub fn add_constant(&mut self, ident: impl ToString, value: Value) {
+        self.add_value(ident.to_string(), value);
+    }
+
     /// Add a builtin native function. Alias for [`Self::add_value`] for simplifying [`ObjKind::NativeFn`] creation.
+    /// `arity` can either be a `u32` for an exact number of arguments or an [`Arity`].
     pub fn add_native_fn(
         &mut self,
         ident: impl ToString,
         func: &'static dyn Fn(&mut [Value]) -> Value,
-        arity: u32,
+        arity: impl Into<Arity>,
+    ) {
+        self.add_native_fn_ptr(ident, NativeFnPtr::Infallible(func), arity.into());
+    }
+
+    /// Add a builtin native function that can fail. Returning an `Err` from `func` raises a runtime error with the message.
+    /// `arity` can either be a `u32` for an exact number of arguments or an [`Arity`].
+    pub fn add_fallible_native_fn(
+        &mut self,
+        ident: impl ToString,
+        func: &'static dyn Fn(&mut [Value]) -> Result<Value, String>,
+        arity: impl Into<Arity>,
+    ) {
+        self.add_native_fn_ptr(ident, NativeFnPtr::Fallible(func), arity.into());
+    }
+
+    /// Add a builtin native function that can call back into the VM (e.g. to call a function passed as an argument).
+    /// See [`object::CallContext`]. Returning an `Err` from `func` raises a runtime error with the kind and message of the
+    /// [`object::CallError`].
+    /// `arity` can either be a `u32` for an exact number of arguments or an [`Arity`].
+    pub fn add_reentrant_native_fn(
+        &mut self,
+        ident: impl ToString,
+        func: &'static ReentrantNativeFn,
+        arity: impl Into<Arity>,
     ) {
+        self.add_native_fn_ptr(ident, NativeFnPtr::Reentrant(func), arity.into());
+    }
+
+    fn add_native_fn_ptr(&mut self, ident: impl ToString, func: NativeFnPtr, arity: Arity) {
         let obj = Value::Object(Rc::new(Obj {
             kind: ObjKind::NativeFn(NativeFn {
                 arity,
@@ -48,10 +103,15 @@ impl BuiltinVars {
 }
 
 /// Represents a runtime value. The [`Value::Object`] variant holds a [`Rc`] to the heap.
-#[derive(Clone, PartialEq, PartialOrd)]
+///
+/// The [`fmt::Display`] implementation is user-facing (what `print` outputs) while the [`fmt::Debug`] implementation
+/// shows the variant and the kind of object (e.g. `Object(Str("hi"))`).
+#[derive(Debug, Clone, PartialOrd)]
 pub enum Value {
     Number(f64),
     Bool(bool),
+    /// The absence of a value.
+    Nil,
     Object(Rc<object::Obj>),
 }
 
@@ -60,7 +120,7 @@ impl Value {
     pub fn cast_to_str(&self) -> Option<&str> {
         match self {
             Self::Object(obj) => match &obj.kind {
-                object::ObjKind::Str(string) => Some(&string),
+                object::ObjKind::Str(string) => Some(string),
                 _ => None,
             },
             _ => None,
@@ -75,13 +135,130 @@ impl Value {
         }
     }
 
+    /// Attempts to cast the [`Value`] into a slice of the array elements or `None` if wrong type.
+    pub fn cast_to_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Object(obj) => match &obj.kind {
+                object::ObjKind::Array(elements) => Some(elements),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Attempts to cast the [`Value`] into a `bool` or `None` if wrong type.
+    /// Casting is strict: only [`Value::Bool`] can be casted into a `bool`.
+    pub fn cast_to_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Returns the user-facing name of the type of the [`Value`] (e.g. `"number"` or `"closure"`).
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Number(_) => "number",
+            Self::Bool(_) => "bool",
+            Self::Nil => "nil",
+            Self::Object(obj) => match &obj.kind {
+                ObjKind::Str(_) => "string",
+                ObjKind::Fn(_) => "function",
+                ObjKind::Closure(_) => "closure",
+                ObjKind::NativeFn(_) => "native function",
+                ObjKind::Array(_) => "array",
+                ObjKind::StringBuilder(_) => "string builder",
+                ObjKind::Map(_) => "map",
+            },
+        }
+    }
+
     /// Prints the object with the specified [`fmt::Formatter`].
-    fn print_obj(f: &mut fmt::Formatter<'_>, obj: &object::Obj) -> fmt::Result {
+    /// `ancestors` contains the arrays and maps that are currently being printed (the objects containing `obj`). An
+    /// array or a map that contains itself is printed as `[...]` or `{...}` instead of recursing forever. Arrays are
+    /// immutable so they can only contain themselves through a map.
+    fn print_obj(
+        f: &mut fmt::Formatter<'_>,
+        obj: &object::Obj,
+        ancestors: &mut Vec<*const object::Obj>,
+    ) -> fmt::Result {
         match &obj.kind {
             ObjKind::Str(str) => write!(f, "{}", str),
             ObjKind::Fn(Function { ident, .. }) => write!(f, "<fn {}>", ident),
             ObjKind::Closure(Closure { func, .. }) => write!(f, "<fn closure {}>", func.ident),
             ObjKind::NativeFn(object::NativeFn { ident, .. }) => write!(f, "<native fn {}>", ident),
+            ObjKind::Array(elements) => {
+                let ptr = obj as *const object::Obj;
+                if ancestors.contains(&ptr) {
+                    return write!(f, "[...]");
+                }
+                ancestors.push(ptr);
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Self::print_nested(f, element, ancestors)?;
+                }
+                ancestors.pop();
+                write!(f, "]")
+            }
+            ObjKind::StringBuilder(buffer) => write!(f, "{}", buffer.borrow()),
+            ObjKind::Map(entries) => {
+                let ptr = obj as *const object::Obj;
+                if ancestors.contains(&ptr) {
+                    return write!(f, "{{...}}");
+                }
+                ancestors.push(ptr);
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key)?;
+                    Self::print_nested(f, value, ancestors)?;
+                }
+                ancestors.pop();
+                write!(f, "}}")
+            }
+        }
+    }
+
+    /// Prints a value contained in an array or a map. See [`Value::print_obj`].
+    fn print_nested(
+        f: &mut fmt::Formatter<'_>,
+        value: &Value,
+        ancestors: &mut Vec<*const object::Obj>,
+    ) -> fmt::Result {
+        match value {
+            Value::Object(obj) => Self::print_obj(f, obj, ancestors),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Objects are compared by identity (same heap allocation) except for strings which are compared by value.
+///
+/// # Example
+/// ```
+/// use ella_value::object::Obj;
+/// use ella_value::Value;
+/// use std::rc::Rc;
+///
+/// let a = Value::Object(Rc::new(Obj::new_string("a".to_string())));
+/// let b = Value::Object(Rc::new(Obj::new_string("a".to_string())));
+/// assert_eq!(a, b);
+/// assert_ne!(Value::Number(1.0), Value::Bool(true));
+/// assert_ne!(Value::Number(f64::NAN), Value::Number(f64::NAN));
+/// ```
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::Bool(l), Value::Bool(r)) => l == r,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Object(l), Value::Object(r)) => Rc::ptr_eq(l, r) || l == r,
+            _ => false,
         }
     }
 }
@@ -91,16 +268,174 @@ impl fmt::Display for Value {
         match self {
             Value::Number(val) => write!(f, "{}", val),
             Value::Bool(val) => write!(f, "{}", val),
-            Value::Object(val) => Self::print_obj(f, val),
+            Value::Nil => write!(f, "nil"),
+            Value::Object(val) => Self::print_obj(f, val, &mut Vec::new()),
         }
     }
 }
 
-impl fmt::Debug for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Display::fmt(self, f)
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Self::Number(val)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        Self::Bool(val)
+    }
+}
+
+impl From<String> for Value {
+    fn from(str: String) -> Self {
+        Self::Object(Rc::new(Obj::new_string(str)))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(str: &str) -> Self {
+        Self::from(str.to_string())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(elements: Vec<Value>) -> Self {
+        Self::Object(Rc::new(Obj::new_array(elements)))
     }
 }
 
 /// Type alias for `Vec<Value>`.
 pub type ValueArray = Vec<Value>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::Obj;
+
+    #[test]
+    fn test_cast_to_bool() {
+        assert_eq!(Value::Bool(true).cast_to_bool(), Some(true));
+        assert_eq!(Value::Bool(false).cast_to_bool(), Some(false));
+        assert_eq!(Value::Number(1.0).cast_to_bool(), None);
+        assert_eq!(Value::Number(0.0).cast_to_bool(), None);
+        assert_eq!(Value::Nil.cast_to_bool(), None);
+        let string = Value::Object(Rc::new(Obj::new_string("true".to_string())));
+        assert_eq!(string.cast_to_bool(), None);
+    }
+
+    #[test]
+    fn test_type_name() {
+        assert_eq!(Value::Number(1.0).type_name(), "number");
+        assert_eq!(Value::Bool(true).type_name(), "bool");
+        assert_eq!(Value::Nil.type_name(), "nil");
+        assert_eq!(Value::from("hi").type_name(), "string");
+        assert_eq!(Value::from(vec![Value::Nil]).type_name(), "array");
+        let builder = Value::Object(Rc::new(Obj::new_string_builder()));
+        assert_eq!(builder.type_name(), "string builder");
+        let map = Value::Object(Rc::new(Obj::new_map()));
+        assert_eq!(map.type_name(), "map");
+
+        let func = Function {
+            ident: "foo".to_string(),
+            arity: 0,
+            chunk: Rc::new(chunk::Chunk::new("foo".to_string())),
+            upvalues_count: 0,
+        };
+        let closure = Value::Object(Rc::new(Obj {
+            kind: ObjKind::Closure(Closure {
+                func: func.clone(),
+                upvalues: Default::default(),
+            }),
+        }));
+        let func = Value::Object(Rc::new(Obj {
+            kind: ObjKind::Fn(func),
+        }));
+        assert_eq!(func.type_name(), "function");
+        assert_eq!(closure.type_name(), "closure");
+
+        fn nop(_args: &mut [Value]) -> Value {
+            Value::Nil
+        }
+        let mut builtin_vars = BuiltinVars::new();
+        builtin_vars.add_native_fn("nop", &nop, 0);
+        assert_eq!(builtin_vars.values[0].1.type_name(), "native function");
+    }
+
+    #[test]
+    fn test_display_nested_array() {
+        let inner = Value::from(vec![Value::from(2.0), Value::from("a")]);
+        let array = Value::from(vec![Value::from(1.0), inner.clone(), inner, Value::Nil]);
+        assert_eq!(format!("{}", array), "[1, [2, a], [2, a], nil]");
+        assert_eq!(format!("{}", Value::from(Vec::<Value>::new())), "[]");
+    }
+
+    #[test]
+    fn test_display_self_referential_array() {
+        // arrays are immutable, they can only contain themselves through a map
+        let map = Rc::new(Obj::new_map());
+        let entries = match &map.kind {
+            ObjKind::Map(entries) => entries,
+            _ => unreachable!(),
+        };
+        let array = Value::from(vec![Value::from(1.0), Value::Object(map.clone())]);
+        entries
+            .borrow_mut()
+            .insert("array".to_string(), array.clone());
+        assert_eq!(format!("{}", array), "[1, {array: [...]}]");
+        entries.borrow_mut().clear(); // break the reference cycle
+    }
+
+    #[test]
+    fn test_display_map() {
+        let map = Obj::new_map();
+        if let ObjKind::Map(entries) = &map.kind {
+            let mut entries = entries.borrow_mut();
+            entries.insert("b".to_string(), Value::from(vec![Value::from(1.0)]));
+            entries.insert("a".to_string(), Value::from("x"));
+        }
+        assert_eq!(format!("{}", Value::Object(Rc::new(map))), "{a: x, b: [1]}");
+        assert_eq!(format!("{}", Value::Object(Rc::new(Obj::new_map()))), "{}");
+    }
+
+    #[test]
+    fn test_display_self_referential_map() {
+        let map = Rc::new(Obj::new_map());
+        let entries = match &map.kind {
+            ObjKind::Map(entries) => entries,
+            _ => unreachable!(),
+        };
+        let array = Value::from(vec![Value::Object(map.clone())]);
+        entries.borrow_mut().insert("array".to_string(), array);
+        entries
+            .borrow_mut()
+            .insert("self".to_string(), Value::Object(map.clone()));
+        assert_eq!(
+            format!("{}", Value::Object(map.clone())),
+            "{array: [{...}], self: {...}}"
+        );
+        entries.borrow_mut().clear(); // break the reference cycle
+    }
+
+    #[test]
+    fn test_debug_distinct_from_display() {
+        let string = Value::from("hi");
+        assert_eq!(format!("{}", string), "hi");
+        assert_eq!(format!("{:?}", string), r#"Object(Str("hi"))"#);
+        assert_eq!(format!("{:?}", Value::from(1.5)), "Number(1.5)");
+        assert_eq!(format!("{:?}", Value::Nil), "Nil");
+
+        let func = Value::Object(Rc::new(Obj {
+            kind: ObjKind::Fn(Function {
+                ident: "foo".to_string(),
+                arity: 2,
+                chunk: Rc::new(chunk::Chunk::new("foo".to_string())),
+                upvalues_count: 0,
+            }),
+        }));
+        assert_eq!(format!("{}", func), "<fn foo>");
+        assert_eq!(
+            format!("{:?}", func),
+            r#"Object(Fn { ident: "foo", arity: 2, upvalues_count: 0 })"#
+        );
+    }
+}
@@ -1,23 +1,139 @@
 //! Object (heap allocated) values.
 
 use crate::chunk::Chunk;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Deref, Range};
 use std::rc::Rc;
 
 use super::Value;
 
+/// Represents the number of arguments a [`NativeFn`] accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    /// Accepts exactly the specified number of arguments.
+    Exact(u32),
+    /// Accepts any number of arguments between `min` and `max` (inclusive).
+    Range { min: u32, max: u32 },
+    /// Accepts `min` or more arguments.
+    Variadic { min: u32 },
+}
+
+impl Arity {
+    /// Returns `true` if a call with `arg_count` arguments is valid. Returns `false` otherwise.
+    /// # Example
+    /// ```
+    /// use ella_value::object::Arity;
+    ///
+    /// assert!(Arity::Exact(1).accepts(1));
+    /// assert!(!Arity::Exact(1).accepts(2));
+    /// let arity = Arity::Range { min: 0, max: 1 };
+    /// assert!(arity.accepts(0));
+    /// assert!(arity.accepts(1));
+    /// assert!(!arity.accepts(2));
+    /// let arity = Arity::Variadic { min: 1 };
+    /// assert!(!arity.accepts(0));
+    /// assert!(arity.accepts(100));
+    /// ```
+    pub fn accepts(&self, arg_count: u32) -> bool {
+        match *self {
+            Self::Exact(arity) => arg_count == arity,
+            Self::Range { min, max } => (min..=max).contains(&arg_count),
+            Self::Variadic { min } => arg_count >= min,
+        }
+    }
+}
+
+impl From<u32> for Arity {
+    fn from(arity: u32) -> Self {
+        Self::Exact(arity)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(arity) => write!(f, "{}", arity),
+            Self::Range { min, max } => write!(f, "{} to {}", min, max),
+            Self::Variadic { min } => write!(f, "at least {}", min),
+        }
+    }
+}
+
 /// Represents a native function (implemented in Rust).
 #[derive(Clone)]
 pub struct NativeFn {
     /// The identifier of the native function.
     pub ident: String,
     /// The number of arguments the native function accepts.
-    pub arity: u32,
+    pub arity: Arity,
     /// A function pointer to the Rust implementation.
     /// The function accepts a `&mut [Value]` which is a slice into the VM's stack where the function arguments are stored.
+    /// The length of the slice is the number of arguments the function was called with.
     /// The function returns a [`Value`] which is the return value for the function.
-    pub func: &'static dyn Fn(&mut [Value]) -> Value,
+    pub func: NativeFnPtr,
+}
+
+/// A function pointer to the Rust implementation of a [`NativeFn`].
+#[derive(Clone, Copy)]
+pub enum NativeFnPtr {
+    /// A native function that cannot fail.
+    Infallible(&'static dyn Fn(&mut [Value]) -> Value),
+    /// A native function that can fail. Returning an `Err` raises a runtime error with the message.
+    Fallible(&'static dyn Fn(&mut [Value]) -> Result<Value, String>),
+    /// A native function that can call back into the VM using the [`CallContext`] (e.g. to call a closure passed as
+    /// an argument). Returning an `Err` raises a runtime error with the kind and message of the [`CallError`].
+    Reentrant(&'static ReentrantNativeFn),
+}
+
+/// The signature of a [`NativeFnPtr::Reentrant`] native function.
+pub type ReentrantNativeFn = dyn Fn(&mut dyn CallContext, &mut [Value]) -> Result<Value, CallError>;
+
+/// Allows native functions to call functions (closures or native functions) while the VM is running.
+/// See [`NativeFnPtr::Reentrant`].
+pub trait CallContext {
+    /// Calls the function `func` with `args` and returns the return value.
+    /// Returns an `Err` with the error kind and message if the call raises a runtime error.
+    fn call_value(&mut self, func: Value, args: &[Value]) -> Result<Value, CallError>;
+}
+
+/// The category of a runtime error.
+/// Allows embedders to handle runtime errors without inspecting the error message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A value has the wrong type (e.g. adding a number and a boolean or calling a number).
+    TypeError,
+    /// A value has the right type but an invalid value (e.g. a negative string repetition count).
+    ValueError,
+    /// A function was called with the wrong number of arguments.
+    ArityError,
+    /// A late-bound global variable is not defined.
+    UndefinedGlobal,
+    /// A native function returned an error (e.g. a failed assertion).
+    NativeError,
+    /// The bytecode is malformed (e.g. an invalid opcode or a stack underflow).
+    InvalidBytecode,
+}
+
+/// A runtime error raised by a function called with [`CallContext::call_value`] or returned by a
+/// [`NativeFnPtr::Reentrant`] native function.
+/// Returning the error of [`CallContext::call_value`] from the native function re-raises it with its original kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl From<String> for CallError {
+    /// Creates a [`ErrorKind::NativeError`] with the message.
+    fn from(message: String) -> Self {
+        Self {
+            kind: ErrorKind::NativeError,
+            message,
+        }
+    }
 }
 
 /// Represents a function. Functions are usually created at compile time and stored in the constant table.
@@ -28,7 +144,8 @@ pub struct Function {
     /// The number of arguments the function accepts.
     pub arity: u32,
     /// The chunk of the function.
-    pub chunk: Chunk,
+    /// The chunk is reference counted to make cloning a function (e.g. when creating a closure) cheap.
+    pub chunk: Rc<Chunk>,
     /// The number of upvalues this function captures.
     /// If the function does not capture any variable, this should be `0`.
     pub upvalues_count: usize,
@@ -86,15 +203,129 @@ impl UpValue {
     }
 }
 
+/// An immutable string. Cloning and slicing a [`Str`] shares the underlying storage instead of copying the bytes.
+#[derive(Clone)]
+pub struct Str {
+    data: Rc<str>,
+    /// The byte range of `data` that is part of the string.
+    range: Range<usize>,
+}
+
+impl Str {
+    /// Returns the content of the string.
+    pub fn as_str(&self) -> &str {
+        &self.data[self.range.clone()]
+    }
+
+    /// Returns the substring with the byte `range` (relative to this string). The substring shares the storage of
+    /// `self`.
+    ///
+    /// # Panics
+    /// Panics if `range` is out of bounds or does not lie on `char` boundaries.
+    ///
+    /// # Example
+    /// ```
+    /// use ella_value::object::Str;
+    ///
+    /// let string = Str::from("hello world".to_string());
+    /// assert_eq!(string.slice(6..11).as_str(), "world");
+    /// assert_eq!(string.slice(6..11).slice(1..3).as_str(), "or");
+    /// ```
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        let _ = &self.as_str()[range.clone()]; // check bounds and char boundaries
+        Self {
+            data: Rc::clone(&self.data),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+}
+
+impl From<String> for Str {
+    fn from(string: String) -> Self {
+        let range = 0..string.len();
+        Self {
+            data: string.into(),
+            range,
+        }
+    }
+}
+
+impl Deref for Str {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for Str {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl fmt::Display for Str {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl fmt::Debug for Str {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
 /// Inner representation for [`Obj`].
 #[derive(Clone)]
 pub enum ObjKind {
-    Str(String),
+    Str(Str),
     Fn(Function),
     Closure(Closure),
     NativeFn(NativeFn),
+    /// An immutable array of values.
+    Array(Vec<Value>),
+    /// A mutable string buffer. Appending to it does not copy the existing content.
+    StringBuilder(RefCell<String>),
+    /// A mutable map from strings to values. The entries are ordered by key.
+    Map(RefCell<BTreeMap<String, Value>>),
+}
+
+/// Shows the kind of object and its salient fields (e.g. `Fn { ident: "foo", arity: 2 }`).
+/// Function chunks are not printed. Use the disassembler instead.
+impl fmt::Debug for ObjKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Str(str) => f.debug_tuple("Str").field(str).finish(),
+            Self::Fn(func) => f
+                .debug_struct("Fn")
+                .field("ident", &func.ident)
+                .field("arity", &func.arity)
+                .field("upvalues_count", &func.upvalues_count)
+                .finish(),
+            Self::Closure(closure) => f
+                .debug_struct("Closure")
+                .field("ident", &closure.func.ident)
+                .field("arity", &closure.func.arity)
+                .field("upvalues_count", &closure.func.upvalues_count)
+                .finish(),
+            Self::NativeFn(native_fn) => f
+                .debug_struct("NativeFn")
+                .field("ident", &native_fn.ident)
+                .field("arity", &native_fn.arity)
+                .finish(),
+            Self::Array(elements) => f.debug_tuple("Array").field(elements).finish(),
+            Self::StringBuilder(buffer) => f
+                .debug_tuple("StringBuilder")
+                .field(&buffer.borrow())
+                .finish(),
+            Self::Map(entries) => f.debug_tuple("Map").field(&entries.borrow()).finish(),
+        }
+    }
 }
 
+/// Only strings and arrays are compared by value (arrays element-wise). Other objects are never equal.
+/// To compare objects by identity, compare the [`Value`]s instead.
 impl PartialEq for ObjKind {
     fn eq(&self, other: &ObjKind) -> bool {
         match self {
@@ -102,6 +333,10 @@ impl PartialEq for ObjKind {
                 Self::Str(r) => l == r,
                 _ => false,
             },
+            Self::Array(l) => match other {
+                Self::Array(r) => l == r,
+                _ => false,
+            },
             _ => false,
         }
     }
@@ -117,10 +352,44 @@ pub struct Obj {
 impl Obj {
     /// Create a new heap allocated string ([`ObjKind::Str`]).
     pub fn new_string(str: String) -> Self {
+        Self {
+            kind: ObjKind::Str(str.into()),
+        }
+    }
+
+    /// Create a new heap allocated array ([`ObjKind::Array`]).
+    pub fn new_array(elements: Vec<Value>) -> Self {
+        Self {
+            kind: ObjKind::Array(elements),
+        }
+    }
+
+    /// Create a new heap allocated string ([`ObjKind::Str`]) that may share its storage with other strings.
+    pub fn new_str(str: Str) -> Self {
         Self {
             kind: ObjKind::Str(str),
         }
     }
+
+    /// Create a new empty heap allocated string builder ([`ObjKind::StringBuilder`]).
+    pub fn new_string_builder() -> Self {
+        Self {
+            kind: ObjKind::StringBuilder(RefCell::new(String::new())),
+        }
+    }
+
+    /// Create a new empty heap allocated map ([`ObjKind::Map`]).
+    pub fn new_map() -> Self {
+        Self {
+            kind: ObjKind::Map(RefCell::new(BTreeMap::new())),
+        }
+    }
+}
+
+impl fmt::Debug for Obj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
 }
 
 impl PartialOrd for Obj {
@@ -129,25 +398,85 @@ impl PartialOrd for Obj {
     }
 }
 
-/// Set to `true` to print a debug message when objects (heap allocated) are dropped.
-const LOG_OBJECT_DROP: bool = false;
+thread_local! {
+    /// If `true`, a debug message is printed to stderr when objects (heap allocated) are dropped on this thread.
+    /// See [`set_drop_logging`].
+    static LOG_OBJECT_DROP: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Enables or disables printing a debug message to stderr when objects are dropped on the current thread.
+/// Objects are not `Send` so this only affects the VMs running on the current thread.
+/// This is useful for investigating object lifetime issues. Disabled by default.
+pub fn set_drop_logging(enabled: bool) {
+    LOG_OBJECT_DROP.with(|log| log.set(enabled));
+}
+
+impl Obj {
+    /// Returns the debug message printed when the object is dropped.
+    fn drop_log_message(&self) -> String {
+        match &self.kind {
+            ObjKind::Str(string) => format!("Collecting object {:?}", string),
+            ObjKind::Fn(Function { ident, .. }) => {
+                format!("Collecting function object {:?}", ident)
+            }
+            ObjKind::Closure(Closure { func, .. }) => {
+                format!("Collecting closure object {:?}", func.ident)
+            }
+            ObjKind::NativeFn(NativeFn { ident, .. }) => {
+                format!("Collecting native function object {:?}", ident)
+            }
+            ObjKind::Array(elements) => {
+                format!("Collecting array object of length {}", elements.len())
+            }
+            ObjKind::StringBuilder(buffer) => format!(
+                "Collecting string builder object of length {}",
+                buffer.borrow().len()
+            ),
+            ObjKind::Map(entries) => {
+                format!("Collecting map object of length {}", entries.borrow().len())
+            }
+        }
+    }
+}
 
 /// `Drop` is implemented for `Obj` merely to ease gc debugging.
 impl Drop for Obj {
     fn drop(&mut self) {
-        if LOG_OBJECT_DROP {
-            match &self.kind {
-                ObjKind::Str(string) => eprintln!("Collecting object {:?}", string),
-                ObjKind::Fn(Function { ident, .. }) => {
-                    eprintln!("Collecting function object {:?}", ident)
-                }
-                ObjKind::Closure(Closure { func, .. }) => {
-                    eprintln!("Collecting closure object {:?}", func.ident)
-                }
-                ObjKind::NativeFn(NativeFn { ident, .. }) => {
-                    eprintln!("Collecting native function object {:?}", ident)
-                }
-            }
+        if LOG_OBJECT_DROP.with(Cell::get) {
+            eprintln!("{}", self.drop_log_message());
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_logging() {
+        let obj = Obj::new_array(vec![Value::Number(1.0), Value::Bool(true)]);
+        assert_eq!(
+            obj.drop_log_message(),
+            "Collecting array object of length 2"
+        );
+        let obj = Obj::new_string("hello".to_string());
+        assert_eq!(obj.drop_log_message(), r#"Collecting object "hello""#);
+
+        set_drop_logging(true);
+        // the flag does not leak into other threads (e.g. tests running in parallel)
+        std::thread::spawn(|| assert!(!LOG_OBJECT_DROP.with(Cell::get)))
+            .join()
+            .unwrap();
+        drop(obj);
+        set_drop_logging(false);
+    }
+
+    #[test]
+    fn test_str_slice_shares_storage() {
+        let string = Str::from("hello world".to_string());
+        let substring = string.slice(6..11);
+        assert!(Rc::ptr_eq(&string.data, &substring.data));
+        assert_eq!(substring, Str::from("world".to_string()));
+        assert_eq!(substring.len(), 5);
+    }
+}
@@ -0,0 +1,120 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ella::builtin_functions::default_builtin_vars;
+use ella_parser::parser::Parser;
+use ella_passes::resolve::Resolver;
+use ella_value::chunk::Chunk;
+use ella_value::BuiltinVars;
+use ella_vm::codegen::Codegen;
+use ella_vm::vm::{InterpretResult, Vm};
+use std::rc::Rc;
+
+/// Compiles the `source` with the default builtin functions.
+/// Returns the chunk for the builtin functions and the chunk for the program.
+fn compile(builtin_vars: &BuiltinVars, source: &str) -> (Rc<Chunk>, Rc<Chunk>) {
+    let dummy_source = "".into();
+    let mut resolver = Resolver::new(&dummy_source);
+    resolver.resolve_builtin_vars(builtin_vars);
+    let accessible_symbols = resolver.accessible_symbols().clone();
+    let mut codegen = Codegen::new("<global>".to_string(), resolver.resolve_result());
+    codegen.codegen_builtin_vars(builtin_vars);
+    let builtins_chunk = Rc::new(codegen.into_inner_chunk());
+
+    let source = source.into();
+    let ast = Parser::new(&source).parse_program();
+    let mut resolver = Resolver::new_with_existing_accessible_symbols(&source, accessible_symbols);
+    resolver.resolve_top_level(&ast);
+    assert!(source.has_no_errors());
+
+    let mut codegen = Codegen::new("<global>".to_string(), resolver.resolve_result());
+    codegen.codegen_function(&ast);
+    (builtins_chunk, Rc::new(codegen.into_inner_chunk()))
+}
+
+/// Benchmarks running the `source` from a fresh [`Vm`].
+fn bench_program(c: &mut Criterion, name: &str, source: &str) {
+    let builtin_vars = default_builtin_vars();
+    let (builtins_chunk, chunk) = compile(&builtin_vars, source);
+
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            let mut vm = Vm::new(&builtin_vars);
+            vm.interpret(Rc::clone(&builtins_chunk));
+            assert_eq!(vm.interpret(Rc::clone(&chunk)), InterpretResult::Ok);
+        })
+    });
+}
+
+fn local_loop(c: &mut Criterion) {
+    bench_program(
+        c,
+        "local-loop",
+        r#"
+        fn count() {
+            let i = 0;
+            let sum = 0;
+            while i < 1000 {
+                sum += i;
+                i += 1;
+            }
+            return sum;
+        }
+        assert_eq(count(), 499500);"#,
+    );
+}
+
+fn fibonacci(c: &mut Criterion) {
+    bench_program(
+        c,
+        "fibonacci",
+        r#"
+        fn fib(x) {
+            if x <= 1 { return 1; }
+            return fib(x - 1) + fib(x - 2);
+        }
+        assert_eq(fib(15), 987);"#,
+    );
+}
+
+fn string_building(c: &mut Criterion) {
+    bench_program(
+        c,
+        "string-building",
+        r#"
+        let s = "";
+        let i = 0;
+        while i < 1000 {
+            s = s + "a";
+            i += 1;
+        }"#,
+    );
+}
+
+fn closures(c: &mut Criterion) {
+    bench_program(
+        c,
+        "closures",
+        r#"
+        fn make_counter() {
+            let count = 0;
+            fn counter() {
+                count += 1;
+                return count;
+            }
+            return counter;
+        }
+        let i = 0;
+        while i < 100 {
+            let counter = make_counter();
+            let j = 0;
+            while j < 10 {
+                counter();
+                j += 1;
+            }
+            assert_eq(counter(), 11);
+            i += 1;
+        }"#,
+    );
+}
+
+criterion_group!(benches, local_loop, fibonacci, string_building, closures);
+criterion_main!(benches);
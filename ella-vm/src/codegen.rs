@@ -5,7 +5,7 @@ use ella_parser::{
     lexer::Token,
     visitor::Visitor,
 };
-use ella_passes::resolve::{ResolveResult, Symbol};
+use ella_passes::resolve::{ResolveResult, ResolvedSymbol, Symbol};
 use ella_value::chunk::{Chunk, OpCode};
 use ella_value::object::{Function, Obj, ObjKind};
 use ella_value::{BuiltinVars, Value};
@@ -14,6 +14,24 @@ use std::{collections::HashMap, rc::Rc};
 
 const DUMP_CHUNK: bool = true;
 
+/// The `break` and `continue` statements of a loop that is being generated.
+struct LoopJumps {
+    /// The number of scopes in `scope_stack` outside of the loop body. The variables declared in the loop body are
+    /// cleaned up before jumping.
+    scope_depth: usize,
+    /// The number of enclosing block expressions (`block_expr_depth`) when entering the loop.
+    block_expr_depth: usize,
+    /// The offset of the condition of a `while` loop. `continue` jumps back to it.
+    /// `None` for `do`/`while` loops, where the condition follows the body.
+    condition_start: Option<usize>,
+    /// The jumps of `break` statements. Patched to the end of the loop.
+    break_jumps: Vec<usize>,
+    /// The jumps of `continue` statements in `do`/`while` loops. Patched to the condition of the loop.
+    continue_jumps: Vec<usize>,
+    /// The number of `break` and `continue` statements of the loop generated so far.
+    count: usize,
+}
+
 /// Generate bytecode from an abstract syntax tree.
 pub struct Codegen<'a> {
     chunk: Chunk,
@@ -22,6 +40,19 @@ pub struct Codegen<'a> {
     /// Every time a new scope is created, a new value is pushed onto the stack.
     /// This is to keep track of how many `pop` instructions to emit when exiting the scope.
     scope_stack: Vec<Vec<Rc<RefCell<Symbol>>>>,
+    /// A stack of the loops that are currently being generated. The last element is the innermost loop.
+    loops: Vec<LoopJumps>,
+    /// The number of [`Expr::Block`]s that are currently being generated.
+    block_expr_depth: usize,
+    /// `true` if generating the chunk of a function body. `false` if generating the top-level chunk.
+    /// Function chunks always end with an explicit `ret_nil` instruction.
+    in_function: bool,
+    /// If `true`, the stack height is simulated after every statement to check that it is balanced.
+    /// See [`Codegen::set_debug_assert_stack_balanced`].
+    debug_assert_stack_balanced: bool,
+    /// The source line of the statement being generated. Recorded in the line table of the chunk for every emitted
+    /// instruction. `0` outside of statements (e.g. for the implicit `ret_nil` at the end of a function).
+    line: usize,
 }
 
 impl<'a> Codegen<'a> {
@@ -31,9 +62,22 @@ impl<'a> Codegen<'a> {
             constant_strings: HashMap::new(),
             resolve_result,
             scope_stack: vec![Vec::new()],
+            loops: Vec::new(),
+            block_expr_depth: 0,
+            in_function: false,
+            debug_assert_stack_balanced: false,
+            line: 0,
         }
     }
 
+    /// Enables or disables the stack balance verification mode.
+    /// When enabled, the bytecode of every statement is checked to leave the stack balanced (e.g. an expression
+    /// statement should have a net stack effect of zero and a `let` declaration should push exactly one value).
+    /// An unbalanced statement panics in debug builds. This is useful for catching codegen bugs.
+    pub fn set_debug_assert_stack_balanced(&mut self, enabled: bool) {
+        self.debug_assert_stack_balanced = enabled;
+    }
+
     /// Consumes `self` and returns the generated [`Chunk`].
     #[must_use]
     pub fn into_inner_chunk(self) -> Chunk {
@@ -55,6 +99,11 @@ impl<'a> Codegen<'a> {
             _ => panic!("func is not a Stmt::FnDeclaration"),
         }
 
+        if self.in_function {
+            // return nil if the end of the function body is reached without an explicit return
+            self.chunk.write_chunk(OpCode::RetNil, self.line);
+        }
+
         if DUMP_CHUNK {
             eprintln!("{}", self.chunk);
         }
@@ -63,8 +112,8 @@ impl<'a> Codegen<'a> {
     pub fn codegen_builtin_vars(&mut self, builtin_vars: &BuiltinVars) {
         for (_ident, value) in &builtin_vars.values {
             let constant = self.chunk.add_constant(value.clone());
-            self.chunk.write_chunk(OpCode::Ldc, 0);
-            self.chunk.write_chunk(constant, 0);
+            self.chunk.write_chunk(OpCode::Ldc, self.line);
+            self.chunk.write_chunk(constant, self.line);
         }
     }
 
@@ -77,22 +126,146 @@ impl<'a> Codegen<'a> {
         self.scope_stack.last_mut().unwrap().push(Rc::clone(symbol));
     }
 
+    /// Adds the symbols of all the variables declared by a [`Stmt::TupleDeclaration`].
+    fn add_tuple_symbols(&mut self, stmt: &Stmt) {
+        let symbols = self.resolve_result.lookup_tuple_declaration(stmt).unwrap();
+        self.scope_stack
+            .last_mut()
+            .unwrap()
+            .extend(symbols.iter().cloned());
+    }
+
     fn exit_scope(&mut self) {
         let scope = self.scope_stack.pop().unwrap();
-        for symbol in scope {
+        // the last declared symbol is on the top of the stack
+        for symbol in scope.into_iter().rev() {
             match symbol.borrow().is_captured {
                 true => {
-                    self.chunk.write_chunk(OpCode::CloseUpVal, 0);
+                    self.chunk.write_chunk(OpCode::CloseUpVal, self.line);
                 }
                 false => {
-                    self.chunk.write_chunk(OpCode::Pop, 0);
+                    self.chunk.write_chunk(OpCode::Pop, self.line);
                     self.chunk
-                        .add_debug_annotation_at_last(format!("cleanup local variable"));
+                        .add_debug_annotation_at_last("cleanup local variable");
                 }
             };
         }
     }
 
+    /// Generates the chunk of the function declared by `stmt` (a [`Stmt::FnDeclaration`]) and emits a `closure`
+    /// instruction that pushes the function onto the stack.
+    fn emit_closure(&mut self, stmt: &'a Stmt) {
+        let (ident, arity, line) = match stmt {
+            Stmt::FnDeclaration {
+                ident,
+                params,
+                body: _, // Body is codegen in a new `Codegen` instance.
+                line,
+                doc: _,
+                span: _,
+            } => (ident.clone(), params.len() as u32, *line),
+            _ => panic!("stmt is not a Stmt::FnDeclaration"),
+        };
+
+        // NOTE: we don't need to create a new scope here because the VM automatically cleans up the created local variables.
+        // Create a new `Codegen` instance, codegen the function, and add the chunk to the `ObjKind::Fn`.
+        let fn_chunk = {
+            let mut cg = Codegen::new(ident.clone(), self.resolve_result);
+            cg.in_function = true;
+            cg.debug_assert_stack_balanced = self.debug_assert_stack_balanced;
+            cg.chunk.declaration_line = Some(line);
+            cg.codegen_function(stmt);
+            cg.chunk
+        };
+
+        let symbol = self.resolve_result.lookup_declaration(stmt).unwrap();
+
+        let func = Rc::new(Obj {
+            kind: ObjKind::Fn(Function {
+                ident,
+                arity,
+                chunk: Rc::new(fn_chunk),
+                upvalues_count: symbol.borrow().upvalues.len(),
+            }),
+        });
+        let constant = self.chunk.add_constant(Value::Object(func));
+        self.chunk.write_chunk(OpCode::Closure, self.line);
+        self.chunk.write_chunk(constant, self.line);
+
+        for symbol in &symbol.borrow().upvalues {
+            self.chunk.write_chunk(symbol.is_local as u8, self.line);
+            self.chunk.write_chunk(symbol.index as u8, self.line);
+        }
+    }
+
+    /// Starts generating a loop. `condition_start` is the offset of the condition if it precedes the body.
+    /// Must be called before entering the scope of the loop body.
+    fn enter_loop(&mut self, condition_start: Option<usize>) {
+        self.loops.push(LoopJumps {
+            scope_depth: self.scope_stack.len(),
+            block_expr_depth: self.block_expr_depth,
+            condition_start,
+            break_jumps: Vec::new(),
+            continue_jumps: Vec::new(),
+            count: 0,
+        });
+    }
+
+    /// Patches the `continue` jumps of the innermost `do`/`while` loop to the current offset (the loop condition).
+    fn patch_continue_jumps(&mut self) {
+        let continue_jumps = std::mem::take(&mut self.loops.last_mut().unwrap().continue_jumps);
+        for jump in continue_jumps {
+            self.chunk.patch_jump(jump);
+        }
+    }
+
+    /// Finishes generating the innermost loop. The `break` jumps are patched to the current offset.
+    fn exit_loop(&mut self) {
+        let loop_jumps = self.loops.pop().unwrap();
+        for jump in loop_jumps.break_jumps {
+            self.chunk.patch_jump(jump);
+        }
+    }
+
+    /// Emits the jump of a `break` or `continue` statement (`stmt`) out of the innermost loop.
+    /// The variables declared in the loop body are cleaned up first. Their scopes are not exited because the code
+    /// following the jump (in the same body) is still generated.
+    fn emit_loop_jump(&mut self, stmt: &Stmt) {
+        let loop_jumps = match self.loops.last() {
+            Some(loop_jumps) if loop_jumps.block_expr_depth == self.block_expr_depth => loop_jumps,
+            // outside of a loop or inside a block expression (reported by the resolver)
+            _ => return,
+        };
+
+        let symbols: Vec<_> = self.scope_stack[loop_jumps.scope_depth..]
+            .iter()
+            .flatten()
+            .cloned()
+            .collect();
+        let condition_start = loop_jumps.condition_start;
+        for symbol in symbols.iter().rev() {
+            match symbol.borrow().is_captured {
+                true => self.chunk.write_chunk(OpCode::CloseUpVal, self.line),
+                false => self.chunk.write_chunk(OpCode::Pop, self.line),
+            };
+        }
+
+        match (stmt, condition_start) {
+            (Stmt::ContinueStmt { .. }, Some(condition_start)) => {
+                self.emit_loop(OpCode::Loop, condition_start, self.line)
+            }
+            (Stmt::ContinueStmt { .. }, None) => {
+                let jump = self.emit_jump(OpCode::Jmp, self.line);
+                self.loops.last_mut().unwrap().continue_jumps.push(jump);
+            }
+            _ => {
+                let jump = self.emit_jump(OpCode::Jmp, self.line);
+                self.loops.last_mut().unwrap().break_jumps.push(jump);
+            }
+        }
+        self.loops.last_mut().unwrap().count += 1;
+    }
+
     /// Emits a placeholder jump.
     /// Returns the index of the start of the jump offset. This should be later patched using [`Chunk::patch_jump`].
     fn emit_jump(&mut self, instr: OpCode, line: usize) -> usize {
@@ -102,6 +275,104 @@ impl<'a> Codegen<'a> {
         self.chunk.code.len() - 2
     }
 
+    /// Adds a string constant to the constant table. Strings with the same value share the same object.
+    /// Returns the index of the added constant.
+    fn add_string_constant(&mut self, val: &str) -> u8 {
+        let obj = if let Some(obj) = self.constant_strings.get(val) {
+            // reuse same String
+            obj.clone()
+        } else {
+            let obj = Rc::new(Obj::new_string(val.to_string()));
+            self.constant_strings.insert(val.to_string(), obj.clone());
+            obj
+        };
+        self.chunk.add_constant(Value::Object(obj))
+    }
+
+    /// Returns `true` if `expr` is the identifier of the builtin variable `ident` (not shadowed by a user variable).
+    fn is_builtin(&self, expr: &Expr, ident: &str) -> bool {
+        match expr {
+            Expr::Identifier(name) if name == ident => self
+                .resolve_result
+                .lookup_identifier(expr)
+                .is_some_and(|resolved_symbol| resolved_symbol.is_builtin),
+            _ => false,
+        }
+    }
+
+    /// Emits a `ldlateglobal` or `stlateglobal` instruction for the identifier `expr`.
+    fn emit_late_bound_global(&mut self, instr: OpCode, expr: &Expr, line: usize) {
+        let ident = match expr {
+            Expr::Identifier(ident) => ident,
+            _ => unreachable!(),
+        };
+        let constant = self.add_string_constant(ident);
+        self.chunk.write_chunk(instr, line);
+        self.chunk.write_chunk(constant, line);
+    }
+
+    /// Emits the instruction that loads the variable `expr` (an identifier) resolved to `resolved_symbol`.
+    fn emit_load(&mut self, resolved_symbol: ResolvedSymbol, expr: &Expr, line: usize) {
+        let ident = match expr {
+            Expr::Identifier(ident) => ident,
+            _ => unreachable!(),
+        };
+
+        if resolved_symbol.is_late_bound {
+            self.emit_late_bound_global(OpCode::LdLateGlobal, expr, line);
+        } else if resolved_symbol.is_global {
+            self.chunk.write_chunk(OpCode::LdGlobal, line);
+            self.chunk
+                .add_debug_annotation_at_last(format!("load global variable {}", ident));
+            self.chunk.write_chunk(resolved_symbol.offset as u8, line);
+        } else if resolved_symbol.is_upvalue {
+            self.chunk.write_chunk(OpCode::LdUpVal, line);
+            self.chunk
+                .add_debug_annotation_at_last(format!("load upvalue {}", ident));
+            self.chunk.write_chunk(resolved_symbol.offset as u8, line);
+        } else {
+            let instr_offset = self.chunk.code.len();
+            self.chunk.emit_ldloc(resolved_symbol.offset as u8, line);
+            self.chunk
+                .add_debug_annotation(instr_offset, format!("load local variable {}", ident));
+        }
+    }
+
+    /// Emits the instruction that stores the value on the top of the stack into the variable `expr` (an identifier)
+    /// resolved to `resolved_symbol`. The value is left on the stack.
+    fn emit_store(&mut self, resolved_symbol: ResolvedSymbol, expr: &Expr, line: usize) {
+        if resolved_symbol.is_late_bound {
+            self.emit_late_bound_global(OpCode::StLateGlobal, expr, line);
+        } else if resolved_symbol.is_global {
+            self.chunk.write_chunk(OpCode::StGlobal, line);
+            self.chunk.write_chunk(resolved_symbol.offset as u8, line);
+        } else if resolved_symbol.is_upvalue {
+            self.chunk.write_chunk(OpCode::StUpVal, line);
+            self.chunk.write_chunk(resolved_symbol.offset as u8, line);
+        } else {
+            self.chunk.emit_stloc(resolved_symbol.offset as u8, line);
+        }
+    }
+
+    /// Generate codegen for shorthand assignments (e.g. `+=`).
+    /// The variable is loaded once and the result of `instr` is stored back. The new value is left on the stack as the
+    /// value of the expression.
+    fn gen_op_assign(&mut self, instr: OpCode, lhs: &'a Expr, rhs: &'a Expr, line: usize) {
+        let resolved_symbol = match self.resolve_result.lookup_identifier(lhs) {
+            Some(resolved_symbol) => *resolved_symbol,
+            None => {
+                // unresolved symbol (reported by the resolver), the value is the rhs
+                self.visit_expr(rhs);
+                return;
+            }
+        };
+
+        self.emit_load(resolved_symbol, lhs, line);
+        self.visit_expr(rhs);
+        self.chunk.write_chunk(instr, line);
+        self.emit_store(resolved_symbol, lhs, line);
+    }
+
     /// Emits a `loop` instruction.
     fn emit_loop(&mut self, instr: OpCode, loop_start: usize, line: usize) {
         let offset = self.chunk.code.len() - loop_start + 3;
@@ -110,124 +381,139 @@ impl<'a> Codegen<'a> {
         self.chunk.write_chunk(((offset >> 8) & 0xff) as u8, line);
         self.chunk.write_chunk((offset & 0xff) as u8, line);
     }
+
+    /// Checks that the bytecode generated for `stmt` (starting at `start`) leaves the stack balanced.
+    /// Declarations push exactly one value per declared variable. Other statements have a net stack effect of zero.
+    /// Statements where every path returns are always balanced. Statements containing a `break` or `continue` of an
+    /// enclosing loop jump out of their bytecode and are not checked.
+    fn assert_stack_balanced(&self, stmt: &Stmt, start: usize) {
+        let expected = match stmt {
+            Stmt::LetDeclaration { .. } | Stmt::FnDeclaration { .. } => 1,
+            Stmt::TupleDeclaration { idents, .. } => idents.len() as i32,
+            _ => 0,
+        };
+
+        match self.chunk.net_stack_effect(start..self.chunk.code.len()) {
+            Ok(Some(net)) => debug_assert_eq!(
+                net, expected,
+                "unbalanced stack in chunk {} at offset {} for {:?}",
+                self.chunk.name, start, stmt
+            ),
+            Ok(None) => {}
+            Err(err) => debug_assert!(
+                false,
+                "invalid stack in chunk {} at offset {}: {}",
+                self.chunk.name, start, err
+            ),
+        }
+    }
+}
+
+/// Returns the value of `expr` if it is a string literal or a concatenation of string literals (e.g. `"a" + "b"`).
+/// Concatenations with any other operand are not folded because `+` is only valid on two strings or two numbers.
+fn constant_string(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::StringLit(string) => Some(string.clone()),
+        Expr::Binary {
+            lhs,
+            op: Token::Plus,
+            rhs,
+        } => Some(constant_string(lhs)? + &constant_string(rhs)?),
+        _ => None,
+    }
 }
 
 impl<'a> Visitor<'a> for Codegen<'a> {
     fn visit_expr(&mut self, expr: &'a Expr) {
         // Do not use default walking logic.
 
-        /// Generate codegen for shorthand assignments (e.g. `+=`).
-        macro_rules! gen_op_assign {
-            ($instr: expr, $lhs: expr, $rhs: expr, $line: expr) => {{
-                let resolved_symbol = *self.resolve_result.lookup_identifier($lhs).unwrap();
-
-                // load value
-                if resolved_symbol.is_global {
-                    self.chunk.write_chunk(OpCode::LdGlobal, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                } else if resolved_symbol.is_upvalue {
-                    self.chunk.write_chunk(OpCode::LdUpVal, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                } else {
-                    self.chunk.write_chunk(OpCode::LdLoc, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                }
-
-                self.visit_expr($rhs);
-                self.chunk.write_chunk($instr, $line);
-
-                // store value
-                if resolved_symbol.is_global {
-                    self.chunk.write_chunk(OpCode::StGlobal, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                } else if resolved_symbol.is_upvalue {
-                    self.chunk.write_chunk(OpCode::StUpVal, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                } else {
-                    self.chunk.write_chunk(OpCode::StLoc, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                }
-
-                self.chunk.write_chunk(OpCode::Pop, $line); // remove rhs
-                self.chunk.write_chunk(OpCode::Pop, $line); // intentional 2nd pop
-
-                // load value, result of op assign is new value
-                if resolved_symbol.is_global {
-                    self.chunk.write_chunk(OpCode::LdGlobal, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                } else if resolved_symbol.is_upvalue {
-                    self.chunk.write_chunk(OpCode::LdUpVal, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                } else {
-                    self.chunk.write_chunk(OpCode::LdLoc, $line);
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, $line);
-                }
-            }};
-        }
-
         match expr {
             Expr::NumberLit(val) => {
-                if *val == 0.0 {
-                    self.chunk.write_chunk(OpCode::Ld0, 0);
+                if *val == 0.0 && val.is_sign_positive() {
+                    self.chunk.write_chunk(OpCode::Ld0, self.line);
                 } else if *val == 1.0 {
-                    self.chunk.write_chunk(OpCode::Ld1, 0);
+                    self.chunk.write_chunk(OpCode::Ld1, self.line);
                 } else {
-                    self.chunk.emit_ldf64(*val, 0);
+                    self.chunk.emit_ldf64(*val, self.line);
                 }
             }
             Expr::BoolLit(val) => {
                 match val {
-                    true => self.chunk.write_chunk(OpCode::LdTrue, 0),
-                    false => self.chunk.write_chunk(OpCode::LdFalse, 0),
+                    true => self.chunk.write_chunk(OpCode::LdTrue, self.line),
+                    false => self.chunk.write_chunk(OpCode::LdFalse, self.line),
                 };
             }
             Expr::StringLit(val) => {
-                let obj = if let Some(obj) = self.constant_strings.get(val) {
-                    // reuse same String
-                    obj.clone()
-                } else {
-                    let obj = Rc::new(Obj::new_string(val.clone()));
-                    self.constant_strings.insert(val.clone(), obj.clone());
-                    obj
-                };
-                let constant = self.chunk.add_constant(Value::Object(obj));
-                self.chunk.write_chunk(OpCode::Ldc, 0);
-                self.chunk.write_chunk(constant, 0);
+                let constant = self.add_string_constant(val);
+                self.chunk.write_chunk(OpCode::Ldc, self.line);
+                self.chunk.write_chunk(constant, self.line);
             }
-            Expr::Identifier(ident) => {
-                let resolved_symbol = *self.resolve_result.lookup_identifier(expr).unwrap();
-
-                if resolved_symbol.is_global {
-                    self.chunk.write_chunk(OpCode::LdGlobal, 0);
-                    self.chunk
-                        .add_debug_annotation_at_last(format!("load global variable {}", ident));
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, 0);
-                } else if resolved_symbol.is_upvalue {
-                    self.chunk.write_chunk(OpCode::LdUpVal, 0);
-                    self.chunk
-                        .add_debug_annotation_at_last(format!("load upvalue {}", ident));
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, 0);
-                } else {
-                    self.chunk.write_chunk(OpCode::LdLoc, 0);
-                    self.chunk
-                        .add_debug_annotation_at_last(format!("load local variable {}", ident));
-                    self.chunk.write_chunk(resolved_symbol.offset as u8, 0);
+            Expr::ArrayLit(elements) => {
+                for element in elements {
+                    self.visit_expr(element);
                 }
+                self.chunk.write_chunk(OpCode::MakeArray, self.line);
+                self.chunk.write_chunk(elements.len() as u8, self.line);
+            }
+            Expr::Identifier(_) => {
+                let resolved_symbol = match self.resolve_result.lookup_identifier(expr) {
+                    Some(resolved_symbol) => *resolved_symbol,
+                    None => {
+                        // unresolved symbol (reported by the resolver), treat like an error expression
+                        self.chunk.write_chunk(OpCode::LdNil, self.line);
+                        return;
+                    }
+                };
+                self.emit_load(resolved_symbol, expr, self.line);
             }
-            Expr::FnCall { callee, args } => {
-                let arity = args.len() as u8;
+            Expr::FnCall { callee, args, .. } => {
+                let mut arity = args.len() as u8;
                 for arg in args {
                     self.visit_expr(arg);
                 }
+                if args.len() == 1 && self.is_builtin(callee, "assert") {
+                    // pass the source of the condition to the builtin for the error message
+                    let constant = self.add_string_constant(&args[0].to_string());
+                    self.chunk.write_chunk(OpCode::Ldc, self.line);
+                    self.chunk.write_chunk(constant, self.line);
+                    arity += 1;
+                }
                 self.visit_expr(callee);
-                self.chunk.write_chunk(OpCode::Calli, 0);
-                self.chunk.write_chunk(arity, 0);
+                self.chunk.write_chunk(OpCode::Calli, self.line);
+                self.chunk.write_chunk(arity, self.line);
+            }
+            Expr::Binary {
+                lhs,
+                op: op @ (Token::LogicalAnd | Token::LogicalOr),
+                rhs,
+            } => {
+                // short-circuit: skip rhs if lhs determines the result
+                self.visit_expr(lhs);
+                let instr = match op {
+                    Token::LogicalAnd => OpCode::JmpIfFalse,
+                    _ => OpCode::JmpIfTrue,
+                };
+                let end_jump = self.emit_jump(instr, self.line);
+                self.chunk.write_chunk(OpCode::Pop, self.line);
+                self.visit_expr(rhs);
+                self.chunk.patch_jump(end_jump);
             }
             Expr::Binary { lhs, op, rhs } => {
+                if let Some(string) = constant_string(expr) {
+                    // concatenation of string literals is folded into a single constant
+                    let constant = self.add_string_constant(&string);
+                    self.chunk.write_chunk(OpCode::Ldc, self.line);
+                    self.chunk.write_chunk(constant, self.line);
+                    return;
+                }
                 match op {
-                    Token::Equals | Token::PlusEquals => {
+                    Token::Equals => {
                         self.visit_expr(rhs); // do not codegen lhs
                     }
+                    Token::PlusEquals
+                    | Token::MinusEquals
+                    | Token::AsteriskEquals
+                    | Token::SlashEquals => {} // operands are generated by gen_op_assign
                     _ => {
                         self.visit_expr(lhs);
                         self.visit_expr(rhs);
@@ -235,58 +521,54 @@ impl<'a> Visitor<'a> for Codegen<'a> {
                 }
                 match op {
                     Token::Plus => {
-                        self.chunk.write_chunk(OpCode::Add, 0);
+                        self.chunk.write_chunk(OpCode::Add, self.line);
                     }
                     Token::Minus => {
-                        self.chunk.write_chunk(OpCode::Sub, 0);
+                        self.chunk.write_chunk(OpCode::Sub, self.line);
                     }
                     Token::Asterisk => {
-                        self.chunk.write_chunk(OpCode::Mul, 0);
+                        self.chunk.write_chunk(OpCode::Mul, self.line);
                     }
                     Token::Slash => {
-                        self.chunk.write_chunk(OpCode::Div, 0);
+                        self.chunk.write_chunk(OpCode::Div, self.line);
+                    }
+                    Token::TildeSlash => {
+                        self.chunk.write_chunk(OpCode::FloorDiv, self.line);
                     }
                     Token::Equals => {
                         let resolved_symbol =
-                            *self.resolve_result.lookup_identifier(lhs.as_ref()).unwrap();
-
-                        if resolved_symbol.is_global {
-                            self.chunk.write_chunk(OpCode::StGlobal, 0);
-                            self.chunk.write_chunk(resolved_symbol.offset as u8, 0);
-                        } else if resolved_symbol.is_upvalue {
-                            self.chunk.write_chunk(OpCode::StUpVal, 0);
-                            self.chunk.write_chunk(resolved_symbol.offset as u8, 0);
-                        } else {
-                            self.chunk.write_chunk(OpCode::StLoc, 0);
-                            self.chunk.write_chunk(resolved_symbol.offset as u8, 0);
-                        }
+                            match self.resolve_result.lookup_identifier(lhs.as_ref()) {
+                                Some(resolved_symbol) => *resolved_symbol,
+                                None => return, // unresolved symbol (reported by the resolver)
+                            };
+                        self.emit_store(resolved_symbol, lhs, self.line);
                     }
-                    Token::PlusEquals => gen_op_assign!(OpCode::Add, lhs, rhs, 0),
-                    Token::MinusEquals => gen_op_assign!(OpCode::Sub, lhs, rhs, 0),
-                    Token::AsteriskEquals => gen_op_assign!(OpCode::Mul, lhs, rhs, 0),
-                    Token::SlashEquals => gen_op_assign!(OpCode::Div, lhs, rhs, 0),
+                    Token::PlusEquals => self.gen_op_assign(OpCode::Add, lhs, rhs, self.line),
+                    Token::MinusEquals => self.gen_op_assign(OpCode::Sub, lhs, rhs, self.line),
+                    Token::AsteriskEquals => self.gen_op_assign(OpCode::Mul, lhs, rhs, self.line),
+                    Token::SlashEquals => self.gen_op_assign(OpCode::Div, lhs, rhs, self.line),
                     Token::EqualsEquals => {
-                        self.chunk.write_chunk(OpCode::Eq, 0);
+                        self.chunk.write_chunk(OpCode::Eq, self.line);
                     }
                     Token::NotEquals => {
-                        self.chunk.write_chunk(OpCode::Eq, 0);
-                        self.chunk.write_chunk(OpCode::Not, 0);
+                        self.chunk.write_chunk(OpCode::Eq, self.line);
+                        self.chunk.write_chunk(OpCode::Not, self.line);
                     }
                     Token::LessThan => {
-                        self.chunk.write_chunk(OpCode::Less, 0);
+                        self.chunk.write_chunk(OpCode::Less, self.line);
                     }
                     Token::LessThanEquals => {
                         // a <= b equivalent to !(a > b)
-                        self.chunk.write_chunk(OpCode::Greater, 0);
-                        self.chunk.write_chunk(OpCode::Not, 0);
+                        self.chunk.write_chunk(OpCode::Greater, self.line);
+                        self.chunk.write_chunk(OpCode::Not, self.line);
                     }
                     Token::GreaterThan => {
-                        self.chunk.write_chunk(OpCode::Greater, 0);
+                        self.chunk.write_chunk(OpCode::Greater, self.line);
                     }
                     Token::GreaterThanEquals => {
                         // a >= b equivalent to !(a < b)
-                        self.chunk.write_chunk(OpCode::Less, 0);
-                        self.chunk.write_chunk(OpCode::Not, 0);
+                        self.chunk.write_chunk(OpCode::Less, self.line);
+                        self.chunk.write_chunk(OpCode::Not, self.line);
                     }
                     _ => unreachable!(),
                 };
@@ -294,60 +576,83 @@ impl<'a> Visitor<'a> for Codegen<'a> {
             Expr::Unary { op, arg } => {
                 self.visit_expr(arg);
                 match op {
-                    Token::LogicalNot => self.chunk.write_chunk(OpCode::Not, 0),
-                    Token::Minus => self.chunk.write_chunk(OpCode::Neg, 0),
+                    Token::LogicalNot => self.chunk.write_chunk(OpCode::Not, self.line),
+                    Token::Minus => self.chunk.write_chunk(OpCode::Neg, self.line),
                     _ => unreachable!(),
                 };
             }
-            Expr::Error => unreachable!(),
+            Expr::Block { body, value } => {
+                let resolved_symbol = *self.resolve_result.lookup_block(expr).unwrap();
+
+                // slot for the value of the block, `nil` if the block does not have a value
+                self.chunk.write_chunk(OpCode::LdNil, self.line);
+                self.chunk.add_debug_annotation_at_last("block value");
+
+                self.block_expr_depth += 1;
+                self.enter_scope();
+                for stmt in body {
+                    self.visit_stmt(stmt);
+                }
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                    if resolved_symbol.is_global {
+                        self.chunk.write_chunk(OpCode::StGlobal, self.line);
+                        self.chunk
+                            .write_chunk(resolved_symbol.offset as u8, self.line);
+                    } else {
+                        self.chunk
+                            .emit_stloc(resolved_symbol.offset as u8, self.line);
+                    }
+                    self.chunk.write_chunk(OpCode::Pop, self.line);
+                }
+                // locals are cleaned up below the value of the block
+                self.exit_scope();
+                self.block_expr_depth -= 1;
+            }
+            Expr::FnLit(func) => self.emit_closure(func),
+            Expr::Error => {
+                // Error recovery can leave error nodes in the AST. Push a placeholder value to keep the stack balanced.
+                self.chunk.write_chunk(OpCode::LdNil, self.line);
+                self.chunk.add_debug_annotation_at_last("error expression");
+            }
         }
     }
 
     fn visit_stmt(&mut self, stmt: &'a Stmt) {
         // Do not use default walking logic.
+        let start = self.chunk.code.len();
+        let loop_jumps_count = self.loops.last().map(|loop_jumps| loop_jumps.count);
+        // blocks use the line of the enclosing statement
+        let enclosing_line = self.line;
+        if let Some(line) = stmt.line() {
+            self.line = line;
+        }
 
         match stmt {
             Stmt::LetDeclaration {
                 ident: _,
                 initializer,
+                doc: _,
+                span: _,
+                line: _,
             } => {
                 self.visit_expr(initializer); // Push value of expression onto top of stack.
                 self.add_symbol(stmt);
             }
-            Stmt::FnDeclaration {
-                ident,
-                params,
-                body: _, // Body is codegen in a new `Codegen` instance.
+            Stmt::TupleDeclaration {
+                idents,
+                initializer,
+                ..
             } => {
-                // NOTE: we don't need to create a new scope here because the VM automatically cleans up the created local variables.
-                let ident = ident.clone();
-                let arity = params.len() as u32;
-
-                // Create a new `Codegen` instance, codegen the function, and add the chunk to the `ObjKind::Fn`.
-                let fn_chunk = {
-                    let mut cg = Codegen::new(ident.clone(), self.resolve_result);
-                    cg.codegen_function(stmt);
-                    cg.chunk
-                };
-
-                let symbol = self.resolve_result.lookup_declaration(stmt).unwrap();
-
-                let func = Rc::new(Obj {
-                    kind: ObjKind::Fn(Function {
-                        ident,
-                        arity,
-                        chunk: fn_chunk,
-                        upvalues_count: symbol.borrow().upvalues.len(),
-                    }),
-                });
-                let constant = self.chunk.add_constant(Value::Object(func));
-                self.chunk.write_chunk(OpCode::Closure, 0);
-                self.chunk.write_chunk(constant, 0);
-
-                for symbol in &symbol.borrow().upvalues {
-                    self.chunk.write_chunk(symbol.is_local as u8, 0);
-                    self.chunk.write_chunk(symbol.index as u8, 0);
-                }
+                self.visit_expr(initializer);
+                // Replace the tuple with its elements. Each element is a local variable.
+                self.chunk.write_chunk(OpCode::Unpack, self.line);
+                self.chunk.write_chunk(idents.len() as u8, self.line);
+                self.add_tuple_symbols(stmt);
+            }
+            Stmt::FnDeclaration { .. } => {
+                self.emit_closure(stmt);
+                self.add_symbol(stmt); // The closure is a local variable that is cleaned up when exiting the scope.
             }
             Stmt::Block(body) => {
                 self.enter_scope();
@@ -360,12 +665,14 @@ impl<'a> Visitor<'a> for Codegen<'a> {
                 condition,
                 if_block,
                 else_block,
+                line: _,
             } => {
                 self.visit_expr(condition);
                 self.chunk.add_debug_annotation_at_last("if condition");
 
-                let then_jump = self.emit_jump(OpCode::JmpIfFalse, 0);
-                self.chunk.write_chunk(OpCode::Pop, 0);
+                // JmpIfFalse does not pop the condition. It is popped exactly once at the start of each branch.
+                let then_jump = self.emit_jump(OpCode::JmpIfFalse, self.line);
+                self.chunk.write_chunk(OpCode::Pop, self.line);
 
                 self.enter_scope();
                 for stmt in if_block {
@@ -374,10 +681,10 @@ impl<'a> Visitor<'a> for Codegen<'a> {
                 self.exit_scope();
 
                 if let Some(else_block) = else_block {
-                    let else_jump = self.emit_jump(OpCode::Jmp, 0);
+                    let else_jump = self.emit_jump(OpCode::Jmp, self.line);
 
                     self.chunk.patch_jump(then_jump);
-                    self.chunk.write_chunk(OpCode::Pop, 0);
+                    self.chunk.write_chunk(OpCode::Pop, self.line);
 
                     self.enter_scope();
                     for stmt in else_block {
@@ -387,46 +694,91 @@ impl<'a> Visitor<'a> for Codegen<'a> {
 
                     self.chunk.patch_jump(else_jump);
                 } else {
+                    // the then branch already popped the condition
+                    let end_jump = self.emit_jump(OpCode::Jmp, self.line);
                     self.chunk.patch_jump(then_jump);
-                    self.chunk.write_chunk(OpCode::Pop, 0);
+                    self.chunk.write_chunk(OpCode::Pop, self.line);
+                    self.chunk.patch_jump(end_jump);
                 }
             }
-            Stmt::WhileStmt { condition, body } => {
+            Stmt::WhileStmt {
+                condition,
+                body,
+                line: _,
+            } => {
                 let loop_start = self.chunk.code.len();
                 self.visit_expr(condition);
 
-                let exit_jump = self.emit_jump(OpCode::JmpIfFalse, 0);
-                self.chunk.write_chunk(OpCode::Pop, 0);
+                let exit_jump = self.emit_jump(OpCode::JmpIfFalse, self.line);
+                self.chunk.write_chunk(OpCode::Pop, self.line);
 
+                self.enter_loop(Some(loop_start));
+                self.enter_scope();
                 for stmt in body {
                     self.visit_stmt(stmt);
                 }
+                self.exit_scope();
 
-                self.emit_loop(OpCode::Loop, loop_start, 0);
+                self.emit_loop(OpCode::Loop, loop_start, self.line);
 
                 self.chunk.patch_jump(exit_jump);
-                self.chunk.write_chunk(OpCode::Pop, 0);
+                self.chunk.write_chunk(OpCode::Pop, self.line);
+                // the condition is already popped when breaking out of the loop
+                self.exit_loop();
             }
-            Stmt::ExprStmt(expr) => {
+            Stmt::DoWhileStmt {
+                body,
+                condition,
+                line: _,
+            } => {
+                let loop_start = self.chunk.code.len();
+
+                self.enter_loop(None);
+                self.enter_scope();
+                for stmt in body {
+                    self.visit_stmt(stmt);
+                }
+                self.exit_scope();
+                self.patch_continue_jumps();
+
+                // jump back to the start of the body while the condition is true
+                self.visit_expr(condition);
+                let exit_jump = self.emit_jump(OpCode::JmpIfFalse, self.line);
+                self.chunk.write_chunk(OpCode::Pop, self.line);
+                self.emit_loop(OpCode::Loop, loop_start, self.line);
+
+                self.chunk.patch_jump(exit_jump);
+                self.chunk.write_chunk(OpCode::Pop, self.line);
+                self.exit_loop();
+            }
+            Stmt::ExprStmt { expr, line: _ } => {
                 self.visit_expr(expr);
-                self.chunk.write_chunk(OpCode::Pop, 0);
+                self.chunk.write_chunk(OpCode::Pop, self.line);
             }
-            Stmt::ReturnStmt(expr) => {
+            Stmt::ReturnStmt { expr, line: _ } => {
                 if let Expr::NumberLit(number) = expr {
                     if *number == 0.0 {
-                        self.chunk.write_chunk(OpCode::Ret0, 0);
+                        self.chunk.write_chunk(OpCode::Ret0, self.line);
                     } else if *number == 1.0 {
-                        self.chunk.write_chunk(OpCode::Ret1, 0);
+                        self.chunk.write_chunk(OpCode::Ret1, self.line);
                     } else {
-                        self.chunk.emit_ldf64(*number, 0);
-                        self.chunk.write_chunk(OpCode::Ret, 0);
+                        self.chunk.emit_ldf64(*number, self.line);
+                        self.chunk.write_chunk(OpCode::Ret, self.line);
                     }
                 } else {
                     self.visit_expr(expr);
-                    self.chunk.write_chunk(OpCode::Ret, 0);
+                    self.chunk.write_chunk(OpCode::Ret, self.line);
                 }
             }
-            Stmt::Error => unreachable!(),
+            Stmt::BreakStmt { .. } | Stmt::ContinueStmt { .. } => self.emit_loop_jump(stmt),
+            Stmt::EmptyStmt => {}
+            Stmt::Error => {} // Error recovery can leave error nodes in the AST.
+        }
+
+        let jumps_out = self.loops.last().map(|loop_jumps| loop_jumps.count) != loop_jumps_count;
+        if self.debug_assert_stack_balanced && cfg!(debug_assertions) && !jumps_out {
+            self.assert_stack_balanced(stmt, start);
         }
+        self.line = enclosing_line;
     }
 }
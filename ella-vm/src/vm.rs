@@ -1,483 +1,943 @@
-use ella_value::chunk::{Chunk, OpCode};
-use ella_value::object::{Closure, Function, NativeFn, Obj, ObjKind, UpValue};
-use ella_value::{BuiltinVars, Value, ValueArray};
-use num_traits::FromPrimitive;
-
-use std::cell::RefCell;
-use std::rc::Rc;
-
-const INSPECT_VM_STACK: bool = false;
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum InterpretResult {
-    Ok,
-    RuntimeError { message: String, line: usize },
-}
-
-#[derive(Clone)]
-struct CallFrame {
-    /// Instruction pointer.
-    ip: usize,
-    /// NOTE: not actually a pointer but rather an index to the start of the `CallFrame`.
-    frame_pointer: usize,
-    closure: Rc<Closure>,
-}
-
-pub struct Vm<'a> {
-    /// VM stack.
-    stack: ValueArray,
-    call_stack: Vec<CallFrame>,
-    builtin_vars: &'a BuiltinVars,
-    upvalues: Vec<Rc<RefCell<UpValue>>>,
-}
-
-impl<'a> Vm<'a> {
-    fn chunk(&self) -> &Chunk {
-        &self.call_stack.last().unwrap().closure.func.chunk
-    }
-
-    fn code(&self) -> &[u8] {
-        &self.call_stack.last().unwrap().closure.func.chunk.code
-    }
-
-    fn ip_mut(&mut self) -> &mut usize {
-        &mut self.call_stack.last_mut().unwrap().ip
-    }
-
-    fn ip(&self) -> usize {
-        self.call_stack.last().unwrap().ip
-    }
-
-    fn resolve_upvalue_into_value(&self, upvalue: &UpValue) -> Value {
-        match upvalue {
-            UpValue::Open(index) => self.stack[*index].clone(),
-            UpValue::Closed(value) => value.clone(),
-        }
-    }
-
-    fn set_upvalue(&mut self, upvalue: Rc<RefCell<UpValue>>, new_value: Value) {
-        let old_upvalue = upvalue.borrow().clone();
-        match old_upvalue {
-            UpValue::Open(index) => self.stack[index] = new_value,
-            UpValue::Closed(ref _value) => {
-                upvalue.replace(UpValue::Closed(new_value));
-            }
-        }
-    }
-
-    fn close_upvalues(&mut self, index: usize) {
-        let value = self.stack[index].clone();
-        for upvalue in &self.upvalues {
-            if upvalue.borrow().is_open_with_index(index) {
-                upvalue.replace(UpValue::Closed(value.clone()));
-            }
-        }
-    }
-
-    fn find_open_upvalue_with_index(&self, index: usize) -> Option<Rc<RefCell<UpValue>>> {
-        for upvalue in &self.upvalues {
-            if upvalue.borrow().is_open_with_index(index) {
-                return Some(upvalue.clone());
-            }
-        }
-        None
-    }
-
-    fn runtime_error(&self, message: impl ToString) -> InterpretResult {
-        InterpretResult::RuntimeError {
-            message: message.to_string(),
-            line: self.chunk().lines[self.ip() - 1], // -1 to get the last instruction
-        }
-    }
-
-    fn run(&mut self) -> InterpretResult {
-        macro_rules! read_u8 {
-            () => {{
-                let byte: u8 = self.code()[self.ip()];
-                *self.ip_mut() += 1;
-                byte
-            }};
-        }
-
-        macro_rules! read_u16 {
-            () => {{
-                let short: u16 =
-                    (self.code()[self.ip()] as u16) << 8 | self.code()[self.ip() + 1] as u16;
-                *self.ip_mut() += 2;
-                short
-            }};
-        }
-
-        macro_rules! read_f64 {
-            () => {{
-                let mut bytes: [u8; 8] = [0; 8];
-                bytes.copy_from_slice(&self.code()[self.ip()..self.ip() + 8]);
-
-                let value = f64::from_le_bytes(bytes);
-                *self.ip_mut() += 8;
-                value
-            }};
-        }
-
-        macro_rules! read_constant {
-            () => {{
-                let constant: Value =
-                    self.chunk().constants[self.code()[self.ip()] as usize].clone();
-                *self.ip_mut() += 1;
-                constant
-            }};
-        }
-
-        macro_rules! frame {
-            () => {
-                self.call_stack.last().unwrap()
-            };
-        }
-
-        /// Generate vm for binary operator.
-        macro_rules! gen_num_binary_op {
-            ($op: tt, $result: path) => {{
-                let b: Value = self.stack.pop().unwrap();
-                let a: Value = self.stack.pop().unwrap();
-
-                let a = match a {
-                    Value::Number(val) => val,
-                    _ => return self.runtime_error("Operands must be numbers."),
-                };
-
-                let b = match b {
-                    Value::Number(val) => val,
-                    _ => return self.runtime_error("Operands must be numbers."),
-                };
-
-                self.stack.push($result(a $op b));
-            }};
-
-            ($op: tt) => {
-                gen_num_binary_op!($op, Value::Number)
-            }
-        }
-
-        /// Uses the last value on the stack as the return value and cleans up the local variables created inside the function.
-        macro_rules! cleanup_function {
-            () => {{
-                let return_value = self.stack.pop().unwrap();
-                let frame = self.call_stack.pop().unwrap(); // remove a `CallFrame` from the call stack.
-
-                for i in frame.frame_pointer..self.stack.len() {
-                    self.close_upvalues(i);
-                }
-                // cleanup local variables created in function
-                self.stack.truncate(frame.frame_pointer);
-
-                self.stack.push(return_value);
-            }}
-        }
-
-        /// If inside a function, cleans up and returns `true`. Else returns `false` and does nothing.
-        macro_rules! try_implicit_ret {
-            () => {{
-                if self.call_stack.len() > 1 {
-                    // inside a function
-                    self.stack.push(Value::Number(0.0)); // FIXME: returns 0.0 by default
-                    cleanup_function!();
-                    true
-                } else {
-                    self.call_stack.pop().unwrap();
-                    false
-                }
-            }};
-        }
-
-        while self.ip() < self.code().len() || try_implicit_ret!() {
-            let opcode = read_u8!();
-            let opcode = OpCode::from_u8(opcode).expect("invalid opcode");
-            match opcode {
-                OpCode::Ldc => {
-                    let constant = read_constant!();
-                    self.stack.push(constant);
-                }
-                OpCode::Ldf64 => {
-                    let value = read_f64!();
-                    self.stack.push(Value::Number(value));
-                }
-                OpCode::Ld0 => self.stack.push(Value::Number(0.0)),
-                OpCode::Ld1 => self.stack.push(Value::Number(1.0)),
-                OpCode::LdLoc => {
-                    let local_index = read_u8!() + frame!().frame_pointer as u8;
-                    let local = self.stack[local_index as usize].clone();
-                    self.stack.push(local);
-                }
-                OpCode::StLoc => {
-                    let local_index = read_u8!() + frame!().frame_pointer as u8;
-                    let value = self.stack.last().unwrap().clone();
-                    self.stack[local_index as usize] = value;
-                }
-                OpCode::LdGlobal => {
-                    let index = read_u8!();
-                    let local = self.stack[index as usize].clone();
-                    self.stack.push(local);
-                }
-                OpCode::StGlobal => {
-                    let index = read_u8!();
-                    let value = self.stack.last().unwrap().clone();
-                    self.stack[index as usize] = value;
-                }
-                OpCode::LdUpVal => {
-                    let index = read_u8!();
-                    let upvalue =
-                        self.call_stack.last().unwrap().closure.upvalues.borrow()[index as usize].clone();
-                    let value = self.resolve_upvalue_into_value(&upvalue.borrow());
-                    self.stack.push(value);
-                }
-                OpCode::StUpVal => {
-                    let index = read_u8!();
-                    let value = self.stack.last().unwrap().clone();
-                    let upvalue =
-                        self.call_stack.last().unwrap().closure.upvalues.borrow()[index as usize].clone();
-                    self.set_upvalue(upvalue, value);
-                }
-                OpCode::CloseUpVal => {
-                    let index = self.stack.len() - 1;
-                    self.close_upvalues(index);
-                    self.stack.pop().unwrap();
-                }
-                OpCode::Neg => {
-                    let val = self.stack.pop().unwrap();
-                    match val {
-                        Value::Number(val) => self.stack.push(Value::Number(-val)),
-                        _ => return self.runtime_error("Operand must be a number."),
-                    }
-                }
-                OpCode::Not => {
-                    let val = self.stack.pop().unwrap();
-                    match val {
-                        Value::Bool(val) => self.stack.push(Value::Bool(!val)),
-                        _ => return self.runtime_error("Operand must be a boolean."),
-                    }
-                }
-                OpCode::Add => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-
-                    let a_num = a.cast_to_number();
-                    let b_num = b.cast_to_number();
-                    if a_num.is_some() && b_num.is_some() {
-                        self.stack
-                            .push(Value::Number(a_num.unwrap() + b_num.unwrap()));
-                    } else {
-                        let a_str = a.cast_to_str();
-                        let b_str = b.cast_to_str();
-                        if a_str.is_some() && b_str.is_some() {
-                            // handle string concatenation
-                            let obj = Rc::new(Obj::new_string(format!(
-                                "{}{}",
-                                a_str.unwrap(),
-                                b_str.unwrap()
-                            )));
-                            self.stack.push(Value::Object(obj));
-                        } else {
-                            return self.runtime_error(format!(
-                                "Operands must be numbers or strings. Received {} and {}",
-                                a, b
-                            ));
-                        }
-                    }
-                }
-                OpCode::Sub => gen_num_binary_op!(-),
-                OpCode::Mul => gen_num_binary_op!(*),
-                OpCode::Div => gen_num_binary_op!(/),
-                OpCode::Ret => {
-                    if self.call_stack.len() <= 1 {
-                        return self.runtime_error("Can only use return in a function.");
-                    }
-                    cleanup_function!();
-                }
-                OpCode::Ret0 => {
-                    self.stack.push(Value::Number(0.0));
-                    if self.call_stack.len() <= 1 {
-                        return self.runtime_error("Can only use return in a function.");
-                    }
-                    cleanup_function!();
-                }
-                OpCode::Ret1 => {
-                    self.stack.push(Value::Number(1.0));
-                    if self.call_stack.len() <= 1 {
-                        return self.runtime_error("Can only use return in a function.");
-                    }
-                    cleanup_function!();
-                }
-                OpCode::LdTrue => self.stack.push(Value::Bool(true)),
-                OpCode::LdFalse => self.stack.push(Value::Bool(false)),
-                OpCode::Eq => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(Value::Bool(a == b));
-                }
-                OpCode::Greater => gen_num_binary_op!(>, Value::Bool),
-                OpCode::Less => gen_num_binary_op!(<, Value::Bool),
-                OpCode::Pop => {
-                    self.stack.pop().unwrap(); // throw away result
-                }
-                OpCode::Calli => {
-                    match self.stack.pop().unwrap() {
-                        Value::Object(obj) => match &obj.kind {
-                            ObjKind::Fn(_) => {
-                                unreachable!("can not call ObjKind::Fn, wrap function in a ObjKind::Closure instead");
-                            }
-                            ObjKind::Closure(closure) => {
-                                let calli_arity = read_u8!();
-
-                                if closure.func.arity != calli_arity as u32 {
-                                    return self.runtime_error(format!(
-                                        "Expected {} argument(s), received {}.",
-                                        closure.func.arity, calli_arity
-                                    ));
-                                }
-
-                                // add new `CallFrame` to call stack
-                                self.call_stack.push(CallFrame {
-                                    ip: 0,
-                                    frame_pointer: self.stack.len() - closure.func.arity as usize,
-                                    closure: Rc::new(closure.clone()),
-                                });
-                            }
-                            ObjKind::NativeFn(NativeFn {
-                                ident: _,
-                                arity,
-                                func,
-                            }) => {
-                                let calli_arity = read_u8!();
-
-                                if *arity != calli_arity as u32 {
-                                    return self.runtime_error(format!(
-                                        "Expected {} argument(s), received {}.",
-                                        arity, calli_arity
-                                    ));
-                                }
-
-                                let stack_len = self.stack.len();
-                                let args = &mut self.stack[stack_len - *arity as usize..stack_len];
-                                debug_assert_eq!(args.len(), *arity as usize);
-
-                                let result = func(args);
-                                // remove arguments from stack
-                                for _i in 0..*arity {
-                                    self.stack.pop().unwrap();
-                                }
-                                self.stack.push(result);
-                            }
-                            _ => return self.runtime_error("Value is not a function."),
-                        },
-                        _ => return self.runtime_error("Value is not a function."),
-                    }
-                }
-                OpCode::Closure => {
-                    let func = match read_constant!() {
-                        Value::Object(obj) => match &obj.kind {
-                            ObjKind::Fn(function) => function.clone(),
-                            _ => unreachable!(),
-                        },
-                        _ => unreachable!(),
-                    };
-
-                    let upvalues_count = func.upvalues_count;
-                    let closure = Closure {
-                        func,
-                        upvalues: Rc::new(RefCell::new(Vec::with_capacity(upvalues_count))),
-                    };
-
-                    for _i in 0..upvalues_count {
-                        let is_local = read_u8!() != 0;
-                        let upvalue_index = read_u8!();
-
-                        let upvalue = if is_local {
-                            match self.find_open_upvalue_with_index(upvalue_index as usize) {
-                                Some(upvalue) => upvalue,
-                                None => {
-                                    let upvalue = Rc::new(RefCell::new(UpValue::Open(
-                                        upvalue_index as usize,
-                                    )));
-                                    self.upvalues.push(upvalue.clone());
-                                    upvalue
-                                }
-                            }
-                        } else {
-                            frame!().closure.upvalues.borrow()[upvalue_index as usize].clone()
-                        };
-
-                        closure.upvalues.borrow_mut().push(upvalue);
-                    }
-                    debug_assert_eq!(closure.upvalues.borrow().len(), upvalues_count);
-
-                    self.stack.push(Value::Object(Rc::new(Obj {
-                        kind: ObjKind::Closure(closure),
-                    })));
-                }
-                OpCode::Jmp => {
-                    let offset = read_u16!();
-                    *self.ip_mut() += offset as usize;
-                }
-                OpCode::JmpIfFalse => {
-                    let offset = read_u16!();
-                    if matches!(self.stack.last().unwrap(), Value::Bool(false)) {
-                        *self.ip_mut() += offset as usize;
-                    }
-                }
-                OpCode::Loop => {
-                    let offset = read_u16!();
-                    *self.ip_mut() -= offset as usize;
-                }
-            }
-
-            if INSPECT_VM_STACK {
-                eprintln!(
-                    "IP: {ip}, Chunk: {chunk}, VM stack: {stack:?}",
-                    ip = self.ip(),
-                    chunk = self.chunk().name,
-                    stack = &self.stack[(self.builtin_vars.values.len()).min(self.stack.len())..] // do not show builtin vars in stack
-                );
-            }
-        }
-
-        InterpretResult::Ok
-    }
-
-    pub fn new(builtin_vars: &'a BuiltinVars) -> Self {
-        Self {
-            stack: Vec::new(),
-            call_stack: Vec::new(),
-            builtin_vars,
-            upvalues: Vec::new(),
-        }
-    }
-
-    /// Executes the chunk
-    pub fn interpret(&mut self, chunk: Chunk) -> InterpretResult {
-        let func = Function {
-            arity: 0,
-            chunk,
-            ident: "top".to_string(),
-            upvalues_count: 0, // cannot have any upvalues for top-level function
-        };
-        let closure = Closure {
-            func,
-            upvalues: Rc::new(RefCell::new(Vec::new())),
-        };
-        self.call_stack.push(CallFrame {
-            ip: 0,            // start interpreting at first opcode
-            frame_pointer: 0, // global frame_pointer points to start of stack
-            closure: Rc::new(closure),
-        });
-
-        self.run()
-    }
-
-    pub fn stack(&self) -> &ValueArray {
-        &self.stack
-    }
-
-    pub fn restore_stack(&mut self, stack: ValueArray) {
-        self.stack = stack;
-    }
-}
+use ella_value::chunk::{Chunk, OpCode};
+use ella_value::object::{
+    CallContext, CallError, Closure, Function, NativeFn, NativeFnPtr, Obj, ObjKind, UpValue,
+};
+use ella_value::{BuiltinVars, Value, ValueArray};
+use num_traits::FromPrimitive;
+
+pub use ella_value::object::ErrorKind;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+const INSPECT_VM_STACK: bool = false;
+
+/// The maximum length (in bytes) of a string created by string repetition (e.g. `"a" * 3`).
+pub const MAX_STRING_LEN: usize = 1 << 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterpretResult {
+    Ok,
+    RuntimeError {
+        kind: ErrorKind,
+        message: String,
+        line: usize,
+        /// The offset in the chunk of the instruction that caused the error (the offset of its opcode).
+        ip: usize,
+        /// The name of the chunk in which the error occurred (the identifier of the function for function chunks).
+        chunk_name: String,
+    },
+}
+
+/// A callback invoked before executing every instruction with the opcode, the offset of the instruction in its chunk
+/// (`ip`) and the source line of the instruction. See [`Vm::set_trace_hook`].
+pub type TraceHook<'a> = Box<dyn FnMut(OpCode, usize, usize) + 'a>;
+
+/// A callback invoked before executing every instruction with a specific opcode. Receives the operand bytes of the
+/// instruction and the VM stack, whose values can be modified before the instruction is executed.
+/// See [`Vm::set_opcode_hook`].
+pub type OpcodeHook<'a> = Box<dyn FnMut(&[u8], &mut ValueArray) + 'a>;
+
+#[derive(Clone)]
+struct CallFrame {
+    /// Instruction pointer.
+    ip: usize,
+    /// The offset of the opcode of the instruction being executed, saved before reading its operands.
+    instruction_start: usize,
+    /// NOTE: not actually a pointer but rather an index to the start of the `CallFrame`.
+    frame_pointer: usize,
+    closure: Rc<Closure>,
+    /// Whether this frame executes a top-level chunk (see [`Vm::interpret`]) rather than a function.
+    /// A top-level frame ends when reaching the end of its chunk and cannot be returned from.
+    is_top_level: bool,
+}
+
+pub struct Vm<'a> {
+    /// VM stack.
+    stack: ValueArray,
+    call_stack: Vec<CallFrame>,
+    builtin_vars: &'a BuiltinVars,
+    upvalues: Vec<Rc<RefCell<UpValue>>>,
+    /// Global variables that are looked up by name (`ldlateglobal` and `stlateglobal`).
+    late_bound_globals: HashMap<String, Value>,
+    /// A [`HashMap`] mapping the identifiers of global variables to their stack slots.
+    /// See [`Vm::set_global_slots`].
+    global_slots: HashMap<String, usize>,
+    /// See [`Vm::set_trace_hook`].
+    trace_hook: Option<TraceHook<'a>>,
+    /// See [`Vm::set_opcode_hook`].
+    opcode_hooks: HashMap<OpCode, OpcodeHook<'a>>,
+}
+
+impl<'a> Vm<'a> {
+    fn chunk(&self) -> &Chunk {
+        &self.call_stack.last().unwrap().closure.func.chunk
+    }
+
+    fn code(&self) -> &[u8] {
+        &self.call_stack.last().unwrap().closure.func.chunk.code
+    }
+
+    fn ip_mut(&mut self) -> &mut usize {
+        &mut self.call_stack.last_mut().unwrap().ip
+    }
+
+    fn ip(&self) -> usize {
+        self.call_stack.last().unwrap().ip
+    }
+
+    fn instruction_start(&self) -> usize {
+        self.call_stack.last().unwrap().instruction_start
+    }
+
+    fn resolve_upvalue_into_value(&self, upvalue: &UpValue) -> Value {
+        match upvalue {
+            UpValue::Open(index) => self.stack[*index].clone(),
+            UpValue::Closed(value) => value.clone(),
+        }
+    }
+
+    fn set_upvalue(&mut self, upvalue: Rc<RefCell<UpValue>>, new_value: Value) {
+        let old_upvalue = upvalue.borrow().clone();
+        match old_upvalue {
+            UpValue::Open(index) => self.stack[index] = new_value,
+            UpValue::Closed(ref _value) => {
+                upvalue.replace(UpValue::Closed(new_value));
+            }
+        }
+    }
+
+    fn close_upvalues(&mut self, index: usize) {
+        let value = self.stack[index].clone();
+        for upvalue in &self.upvalues {
+            if upvalue.borrow().is_open_with_index(index) {
+                upvalue.replace(UpValue::Closed(value.clone()));
+            }
+        }
+    }
+
+    fn find_open_upvalue_with_index(&self, index: usize) -> Option<Rc<RefCell<UpValue>>> {
+        for upvalue in &self.upvalues {
+            if upvalue.borrow().is_open_with_index(index) {
+                return Some(upvalue.clone());
+            }
+        }
+        None
+    }
+
+    fn runtime_error(&self, kind: ErrorKind, message: impl ToString) -> InterpretResult {
+        InterpretResult::RuntimeError {
+            kind,
+            message: message.to_string(),
+            line: self.chunk().line_at(self.instruction_start()),
+            ip: self.instruction_start(),
+            chunk_name: self.chunk().name.clone(),
+        }
+    }
+
+    /// Calls `callee` with the `arg_count` arguments on the top of the stack.
+    /// Calling a closure pushes a new [`CallFrame`] which is then executed by [`Self::run`].
+    /// Calling a native function executes it immediately and replaces the arguments with the return value.
+    /// Returns the [`ErrorKind`] and the message of the error if the call fails.
+    fn call(&mut self, callee: Value, arg_count: u8) -> Result<(), (ErrorKind, String)> {
+        let obj = match callee {
+            Value::Object(obj) => obj,
+            _ => return Err((ErrorKind::TypeError, "Value is not a function.".to_string())),
+        };
+        match &obj.kind {
+            ObjKind::Fn(_) => {
+                unreachable!(
+                    "can not call ObjKind::Fn, wrap function in a ObjKind::Closure instead"
+                );
+            }
+            ObjKind::Closure(closure) => {
+                if closure.func.arity != arg_count as u32 {
+                    return Err((
+                        ErrorKind::ArityError,
+                        format!(
+                            "Function '{}' expected {} argument(s), received {}.",
+                            closure.func.ident, closure.func.arity, arg_count
+                        ),
+                    ));
+                }
+                if self.stack.len() < arg_count as usize {
+                    return Err((ErrorKind::InvalidBytecode, "Stack underflow.".to_string()));
+                }
+
+                // add new `CallFrame` to call stack
+                self.call_stack.push(CallFrame {
+                    ip: 0,
+                    instruction_start: 0,
+                    frame_pointer: self.stack.len() - closure.func.arity as usize,
+                    closure: Rc::new(closure.clone()),
+                    is_top_level: false,
+                });
+            }
+            ObjKind::NativeFn(NativeFn { ident, arity, func }) => {
+                if !arity.accepts(arg_count as u32) {
+                    return Err((
+                        ErrorKind::ArityError,
+                        format!(
+                            "Function '{}' expected {} argument(s), received {}.",
+                            ident, arity, arg_count
+                        ),
+                    ));
+                }
+                if self.stack.len() < arg_count as usize {
+                    return Err((ErrorKind::InvalidBytecode, "Stack underflow.".to_string()));
+                }
+
+                let args_start = self.stack.len() - arg_count as usize;
+                let result = match *func {
+                    NativeFnPtr::Infallible(func) => func(&mut self.stack[args_start..]),
+                    NativeFnPtr::Fallible(func) => func(&mut self.stack[args_start..])
+                        .map_err(|message| (ErrorKind::NativeError, message))?,
+                    NativeFnPtr::Reentrant(func) => {
+                        // copy the arguments because the stack can be modified during the call
+                        let mut args = self.stack[args_start..].to_vec();
+                        func(self, &mut args).map_err(|error| (error.kind, error.message))?
+                    }
+                };
+                // remove arguments from stack
+                self.stack.truncate(args_start);
+                self.stack.push(result);
+            }
+            _ => return Err((ErrorKind::TypeError, "Value is not a function.".to_string())),
+        }
+        Ok(())
+    }
+
+    /// Executes instructions until the call stack shrinks back to `base_depth` (after a return instruction) or the
+    /// end of the top-level chunk is reached.
+    /// `base_depth` is `0` when running a top-level chunk and the depth before the call when calling a function from
+    /// a native function (see [`CallContext::call_value`]).
+    fn run(&mut self, base_depth: usize) -> InterpretResult {
+        macro_rules! read_u8 {
+            () => {{
+                let byte: u8 = self.code()[self.ip()];
+                *self.ip_mut() += 1;
+                byte
+            }};
+        }
+
+        macro_rules! read_u16 {
+            () => {{
+                let short: u16 =
+                    (self.code()[self.ip()] as u16) << 8 | self.code()[self.ip() + 1] as u16;
+                *self.ip_mut() += 2;
+                short
+            }};
+        }
+
+        macro_rules! read_f64 {
+            () => {{
+                let mut bytes: [u8; 8] = [0; 8];
+                bytes.copy_from_slice(&self.code()[self.ip()..self.ip() + 8]);
+
+                let value = f64::from_le_bytes(bytes);
+                *self.ip_mut() += 8;
+                value
+            }};
+        }
+
+        macro_rules! read_constant {
+            () => {{
+                let constant: Value =
+                    self.chunk().constants[self.code()[self.ip()] as usize].clone();
+                *self.ip_mut() += 1;
+                constant
+            }};
+        }
+
+        macro_rules! frame {
+            () => {
+                self.call_stack.last().unwrap()
+            };
+        }
+
+        /// Pops the last value on the stack or returns a runtime error if the stack is empty.
+        macro_rules! pop {
+            () => {
+                match self.stack.pop() {
+                    Some(value) => value,
+                    None => {
+                        return self.runtime_error(ErrorKind::InvalidBytecode, "Stack underflow.")
+                    }
+                }
+            };
+        }
+
+        /// Returns a clone of the last value on the stack or returns a runtime error if the stack is empty.
+        macro_rules! peek {
+            () => {
+                match self.stack.last() {
+                    Some(value) => value.clone(),
+                    None => {
+                        return self.runtime_error(ErrorKind::InvalidBytecode, "Stack underflow.")
+                    }
+                }
+            };
+        }
+
+        /// Returns the index on the stack of the local variable at `offset` in the current frame or returns a runtime
+        /// error if the index is out of bounds.
+        macro_rules! local_index {
+            ($offset: expr) => {{
+                let index = $offset as usize + frame!().frame_pointer;
+                if index >= self.stack.len() {
+                    return self.runtime_error(
+                        ErrorKind::InvalidBytecode,
+                        format!("Local variable index {} is out of bounds.", index),
+                    );
+                }
+                index
+            }};
+        }
+
+        /// Generate vm for binary operator.
+        macro_rules! gen_num_binary_op {
+            ($op: tt, $result: path) => {{
+                let b: Value = pop!();
+                let a: Value = pop!();
+
+                let (a, b) = match (a, b) {
+                    (Value::Number(a), Value::Number(b)) => (a, b),
+                    (Value::Number(_), b) => {
+                        return self.runtime_error(
+                            ErrorKind::TypeError,
+                            format!(
+                                "Right operand must be a number. Received {} ({})",
+                                b,
+                                b.type_name()
+                            ),
+                        )
+                    }
+                    (a, Value::Number(_)) => {
+                        return self.runtime_error(
+                            ErrorKind::TypeError,
+                            format!(
+                                "Left operand must be a number. Received {} ({})",
+                                a,
+                                a.type_name()
+                            ),
+                        )
+                    }
+                    (a, b) => {
+                        return self.runtime_error(
+                            ErrorKind::TypeError,
+                            format!(
+                                "Operands must be numbers. Received {} ({}) and {} ({})",
+                                a,
+                                a.type_name(),
+                                b,
+                                b.type_name()
+                            ),
+                        )
+                    }
+                };
+
+                self.stack.push($result(a $op b));
+            }};
+
+            ($op: tt) => {
+                gen_num_binary_op!($op, Value::Number)
+            }
+        }
+
+        /// Uses the last value on the stack as the return value and cleans up the local variables created inside the function.
+        macro_rules! cleanup_function {
+            () => {{
+                let return_value = pop!();
+                let frame = self.call_stack.pop().unwrap(); // remove a `CallFrame` from the call stack.
+
+                for i in frame.frame_pointer..self.stack.len() {
+                    self.close_upvalues(i);
+                }
+                // cleanup local variables created in function
+                self.stack.truncate(frame.frame_pointer);
+
+                self.stack.push(return_value);
+
+                if self.call_stack.len() == base_depth {
+                    // returned from a function called by a native function
+                    return InterpretResult::Ok;
+                }
+            }}
+        }
+
+        // Function chunks always end with a return instruction. Only the top-level chunk can run past its last instruction.
+        while self.ip() < self.code().len() {
+            let frame = self.call_stack.last_mut().unwrap();
+            frame.instruction_start = frame.ip;
+            let byte = read_u8!();
+            let opcode = match OpCode::from_u8(byte) {
+                Some(opcode) => opcode,
+                None => {
+                    return self.runtime_error(
+                        ErrorKind::InvalidBytecode,
+                        format!(
+                            "Invalid opcode {:#04x} at offset {} in chunk {}.",
+                            byte,
+                            self.instruction_start(),
+                            self.chunk().name
+                        ),
+                    )
+                }
+            };
+            if let Some(hook) = &mut self.trace_hook {
+                let frame = self.call_stack.last().unwrap();
+                let ip = frame.instruction_start;
+                hook(opcode, ip, frame.closure.func.chunk.line_at(ip));
+            }
+            if !self.opcode_hooks.is_empty() {
+                if let Some(hook) = self.opcode_hooks.get_mut(&opcode) {
+                    let frame = self.call_stack.last().unwrap();
+                    let code = &frame.closure.func.chunk.code;
+                    let operands_end = (frame.ip + opcode.operand_len()).min(code.len());
+                    let stack_len = self.stack.len();
+                    hook(&code[frame.ip..operands_end], &mut self.stack);
+                    if self.stack.len() != stack_len {
+                        return self.runtime_error(
+                            ErrorKind::NativeError,
+                            format!(
+                                "Opcode hook for {:?} must not change the depth of the stack.",
+                                opcode
+                            ),
+                        );
+                    }
+                }
+            }
+            match opcode {
+                OpCode::Ldc => {
+                    let constant = read_constant!();
+                    self.stack.push(constant);
+                }
+                OpCode::Ldf64 => {
+                    let value = read_f64!();
+                    self.stack.push(Value::Number(value));
+                }
+                OpCode::Ld0 => self.stack.push(Value::Number(0.0)),
+                OpCode::Ld1 => self.stack.push(Value::Number(1.0)),
+                OpCode::LdLoc => {
+                    let local_index = local_index!(read_u8!());
+                    let local = self.stack[local_index].clone();
+                    self.stack.push(local);
+                }
+                OpCode::StLoc => {
+                    let local_index = local_index!(read_u8!());
+                    let value = peek!();
+                    self.stack[local_index] = value;
+                }
+                OpCode::LdLoc0 | OpCode::LdLoc1 | OpCode::LdLoc2 | OpCode::LdLoc3 => {
+                    let local_index = local_index!(opcode as u8 - OpCode::LdLoc0 as u8);
+                    let local = self.stack[local_index].clone();
+                    self.stack.push(local);
+                }
+                OpCode::StLoc0 | OpCode::StLoc1 | OpCode::StLoc2 | OpCode::StLoc3 => {
+                    let local_index = local_index!(opcode as u8 - OpCode::StLoc0 as u8);
+                    let value = peek!();
+                    self.stack[local_index] = value;
+                }
+                OpCode::LdGlobal => {
+                    let index = read_u8!();
+                    let local = self.stack[index as usize].clone();
+                    self.stack.push(local);
+                }
+                OpCode::StGlobal => {
+                    let index = read_u8!();
+                    let value = peek!();
+                    self.stack[index as usize] = value;
+                }
+                OpCode::LdLateGlobal => {
+                    let ident = read_constant!();
+                    let ident = ident.cast_to_str().unwrap();
+                    match self.late_bound_globals.get(ident) {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.stack.push(value);
+                        }
+                        None => {
+                            return self.runtime_error(
+                                ErrorKind::UndefinedGlobal,
+                                format!("Cannot resolve global {}", ident),
+                            )
+                        }
+                    }
+                }
+                OpCode::StLateGlobal => {
+                    let ident = read_constant!();
+                    let value = peek!();
+                    self.late_bound_globals
+                        .insert(ident.cast_to_str().unwrap().to_string(), value);
+                }
+                OpCode::LdUpVal => {
+                    let index = read_u8!();
+                    let upvalue =
+                        self.call_stack.last().unwrap().closure.upvalues.borrow()[index as usize].clone();
+                    let value = self.resolve_upvalue_into_value(&upvalue.borrow());
+                    self.stack.push(value);
+                }
+                OpCode::StUpVal => {
+                    let index = read_u8!();
+                    let value = peek!();
+                    let upvalue =
+                        self.call_stack.last().unwrap().closure.upvalues.borrow()[index as usize].clone();
+                    self.set_upvalue(upvalue, value);
+                }
+                OpCode::CloseUpVal => {
+                    peek!(); // check for stack underflow before computing the index
+                    let index = self.stack.len() - 1;
+                    self.close_upvalues(index);
+                    pop!();
+                }
+                OpCode::Neg => {
+                    let val = pop!();
+                    match val {
+                        Value::Number(val) => self.stack.push(Value::Number(-val)),
+                        _ => {
+                            return self
+                                .runtime_error(ErrorKind::TypeError, "Operand must be a number.")
+                        }
+                    }
+                }
+                OpCode::Not => {
+                    let val = pop!();
+                    match val.cast_to_bool() {
+                        Some(val) => self.stack.push(Value::Bool(!val)),
+                        None => {
+                            return self
+                                .runtime_error(ErrorKind::TypeError, "Operand must be a boolean.")
+                        }
+                    }
+                }
+                OpCode::Add => {
+                    let b = pop!();
+                    let a = pop!();
+
+                    if let (Some(a_num), Some(b_num)) = (a.cast_to_number(), b.cast_to_number()) {
+                        self.stack.push(Value::Number(a_num + b_num));
+                    } else if let (Some(a_str), Some(b_str)) = (a.cast_to_str(), b.cast_to_str()) {
+                        // handle string concatenation
+                        let obj = Rc::new(Obj::new_string(format!("{}{}", a_str, b_str)));
+                        self.stack.push(Value::Object(obj));
+                    } else {
+                        return self.runtime_error(
+                            ErrorKind::TypeError,
+                            format!(
+                                "Operands must be numbers or strings. Received {} ({}) and {} ({})",
+                                a,
+                                a.type_name(),
+                                b,
+                                b.type_name()
+                            ),
+                        );
+                    }
+                }
+                OpCode::Sub => gen_num_binary_op!(-),
+                OpCode::Mul => {
+                    let b = pop!();
+                    let a = pop!();
+
+                    if let (Some(a_num), Some(b_num)) = (a.cast_to_number(), b.cast_to_number()) {
+                        self.stack.push(Value::Number(a_num * b_num));
+                    } else if let Some((string, count)) = a
+                        .cast_to_str()
+                        .zip(b.cast_to_number())
+                        .or_else(|| b.cast_to_str().zip(a.cast_to_number()))
+                    {
+                        // handle string repetition
+                        if count < 0.0 || count.fract() != 0.0 {
+                            return self.runtime_error(ErrorKind::ValueError, format!(
+                                "String repetition count must be a non-negative integer. Received {}",
+                                count
+                            ));
+                        }
+                        let too_long = count * string.len() as f64 > MAX_STRING_LEN as f64;
+                        if too_long {
+                            return self.runtime_error(ErrorKind::ValueError, format!(
+                                "String repetition result must not be longer than {} bytes. Received {} repetitions of a string of {} bytes",
+                                MAX_STRING_LEN,
+                                count,
+                                string.len()
+                            ));
+                        }
+                        let obj = Rc::new(Obj::new_string(string.repeat(count as usize)));
+                        self.stack.push(Value::Object(obj));
+                    } else {
+                        return self.runtime_error(
+                            ErrorKind::TypeError,
+                            format!(
+                            "Operands must be numbers or a string and a number. Received {} ({}) and {} ({})",
+                            a,
+                            a.type_name(),
+                            b,
+                            b.type_name()
+                        ),
+                        );
+                    }
+                }
+                OpCode::Div => gen_num_binary_op!(/),
+                OpCode::FloorDiv => gen_num_binary_op!(/, floor_number),
+                OpCode::Ret | OpCode::Ret0 | OpCode::Ret1 | OpCode::RetNil => {
+                    if frame!().is_top_level {
+                        return self.runtime_error(
+                            ErrorKind::InvalidBytecode,
+                            "Can only use return in a function.",
+                        );
+                    }
+                    match opcode {
+                        OpCode::Ret0 => self.stack.push(Value::Number(0.0)),
+                        OpCode::Ret1 => self.stack.push(Value::Number(1.0)),
+                        OpCode::RetNil => self.stack.push(Value::Nil),
+                        _ => {} // the return value is already on the stack
+                    }
+                    cleanup_function!();
+                }
+                OpCode::LdTrue => self.stack.push(Value::Bool(true)),
+                OpCode::LdFalse => self.stack.push(Value::Bool(false)),
+                OpCode::LdNil => self.stack.push(Value::Nil),
+                OpCode::MakeArray => {
+                    let len = read_u8!() as usize;
+                    if self.stack.len() < len {
+                        return self.runtime_error(ErrorKind::InvalidBytecode, "Stack underflow.");
+                    }
+                    let elements = self.stack.split_off(self.stack.len() - len);
+                    self.stack.push(Value::from(elements));
+                }
+                OpCode::Unpack => {
+                    let len = read_u8!() as usize;
+                    let tuple = pop!();
+                    let elements = match tuple.cast_to_array() {
+                        Some(elements) => elements,
+                        None => {
+                            return self.runtime_error(
+                                ErrorKind::TypeError,
+                                format!("Cannot destructure {}. Expected a tuple", tuple),
+                            )
+                        }
+                    };
+                    if elements.len() != len {
+                        return self.runtime_error(
+                            ErrorKind::ValueError,
+                            format!(
+                                "Expected a tuple with {} element(s), received {}",
+                                len,
+                                elements.len()
+                            ),
+                        );
+                    }
+                    self.stack.extend_from_slice(elements);
+                }
+                OpCode::Eq => {
+                    let b = pop!();
+                    let a = pop!();
+                    self.stack.push(Value::Bool(a == b));
+                }
+                OpCode::Greater => gen_num_binary_op!(>, Value::Bool),
+                OpCode::Less => gen_num_binary_op!(<, Value::Bool),
+                OpCode::Pop => {
+                    pop!(); // throw away result
+                }
+                OpCode::Calli => {
+                    let callee = pop!();
+                    let arg_count = read_u8!();
+                    if let Err((kind, message)) = self.call(callee, arg_count) {
+                        return self.runtime_error(kind, message);
+                    }
+                }
+                OpCode::Closure => {
+                    let func = match read_constant!() {
+                        Value::Object(obj) => match &obj.kind {
+                            ObjKind::Fn(function) => function.clone(),
+                            _ => unreachable!(),
+                        },
+                        _ => unreachable!(),
+                    };
+
+                    let upvalues_count = func.upvalues_count;
+                    let closure = Closure {
+                        func,
+                        upvalues: Rc::new(RefCell::new(Vec::with_capacity(upvalues_count))),
+                    };
+
+                    for _i in 0..upvalues_count {
+                        let is_local = read_u8!() != 0;
+                        let upvalue_index = read_u8!();
+
+                        let upvalue = if is_local {
+                            // the index is relative to the frame of the enclosing function
+                            let index = frame!().frame_pointer + upvalue_index as usize;
+                            match self.find_open_upvalue_with_index(index) {
+                                Some(upvalue) => upvalue,
+                                None => {
+                                    let upvalue = Rc::new(RefCell::new(UpValue::Open(index)));
+                                    self.upvalues.push(upvalue.clone());
+                                    upvalue
+                                }
+                            }
+                        } else {
+                            frame!().closure.upvalues.borrow()[upvalue_index as usize].clone()
+                        };
+
+                        closure.upvalues.borrow_mut().push(upvalue);
+                    }
+                    debug_assert_eq!(closure.upvalues.borrow().len(), upvalues_count);
+
+                    self.stack.push(Value::Object(Rc::new(Obj {
+                        kind: ObjKind::Closure(closure),
+                    })));
+                }
+                OpCode::Jmp => {
+                    let offset = read_u16!();
+                    *self.ip_mut() += offset as usize;
+                }
+                OpCode::JmpIfFalse => {
+                    let offset = read_u16!();
+                    match peek!().cast_to_bool() {
+                        Some(false) => *self.ip_mut() += offset as usize,
+                        Some(true) => {}
+                        None => {
+                            return self.runtime_error(
+                                ErrorKind::TypeError,
+                                "Condition must be a boolean.",
+                            )
+                        }
+                    }
+                }
+                OpCode::JmpIfTrue => {
+                    let offset = read_u16!();
+                    match peek!().cast_to_bool() {
+                        Some(true) => *self.ip_mut() += offset as usize,
+                        Some(false) => {}
+                        None => {
+                            return self.runtime_error(
+                                ErrorKind::TypeError,
+                                "Condition must be a boolean.",
+                            )
+                        }
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = read_u16!();
+                    *self.ip_mut() -= offset as usize;
+                }
+            }
+
+            if INSPECT_VM_STACK {
+                eprintln!(
+                    "IP: {ip}, Chunk: {chunk}, VM stack: {stack:?}",
+                    ip = self.ip(),
+                    chunk = self.chunk().name,
+                    stack = &self.stack[(self.builtin_vars.values.len()).min(self.stack.len())..] // do not show builtin vars in stack
+                );
+            }
+        }
+
+        if !frame!().is_top_level {
+            return self.runtime_error(
+                ErrorKind::InvalidBytecode,
+                "Reached the end of a function without a return instruction.",
+            );
+        }
+        // the top-level chunk is done
+        self.call_stack.pop();
+        InterpretResult::Ok
+    }
+
+    pub fn new(builtin_vars: &'a BuiltinVars) -> Self {
+        Self {
+            stack: Vec::new(),
+            call_stack: Vec::new(),
+            builtin_vars,
+            upvalues: Vec::new(),
+            late_bound_globals: HashMap::new(),
+            global_slots: HashMap::new(),
+            trace_hook: None,
+            opcode_hooks: HashMap::new(),
+        }
+    }
+
+    /// Clears the state left by previously executed chunks so that the VM can run an unrelated program.
+    /// The stack is reset to contain only the builtin variables (as loaded by `Codegen::codegen_builtin_vars`), and the
+    /// call stack, upvalues, late-bound globals and global slots are cleared. The trace hook and the opcode hooks are
+    /// kept.
+    ///
+    /// To reuse a VM, call [`Self::reset`] before interpreting the next program. The program must be resolved with
+    /// only the builtin variables accessible (e.g. with the accessible symbols of a resolver that has only resolved
+    /// the builtin variables).
+    pub fn reset(&mut self) {
+        self.stack = self
+            .builtin_vars
+            .values
+            .iter()
+            .map(|(_ident, value)| value.clone())
+            .collect();
+        self.call_stack.clear();
+        self.upvalues.clear();
+        self.late_bound_globals.clear();
+        self.global_slots.clear();
+    }
+
+    /// Executes the chunk.
+    /// `chunk` can either be a [`Chunk`] or a `Rc<Chunk>`. Passing a `Rc<Chunk>` allows the same chunk to be executed multiple times without recompiling.
+    ///
+    /// **NOTE**: Global variables declared by the chunk are pushed onto the VM stack.
+    /// To run the same top-level chunk again from a clean state, save the stack with [`Self::stack`] and restore it with [`Self::restore_stack`].
+    pub fn interpret(&mut self, chunk: impl Into<Rc<Chunk>>) -> InterpretResult {
+        let func = Function {
+            arity: 0,
+            chunk: chunk.into(),
+            ident: "top".to_string(),
+            upvalues_count: 0, // cannot have any upvalues for top-level function
+        };
+        let closure = Closure {
+            func,
+            upvalues: Rc::new(RefCell::new(Vec::new())),
+        };
+        self.call_stack.push(CallFrame {
+            ip: 0,            // start interpreting at first opcode
+            instruction_start: 0,
+            frame_pointer: 0, // global frame_pointer points to start of stack
+            closure: Rc::new(closure),
+            is_top_level: true,
+        });
+
+        let result = self.run(0);
+        if let InterpretResult::RuntimeError { .. } = result {
+            // Discard the call frames of the aborted execution so that the VM can be reused.
+            self.call_stack.clear();
+        }
+        result
+    }
+
+    /// Sets the mapping from global variable identifiers to stack slots used by [`Self::get_global`] and [`Self::set_global`].
+    /// The mapping is obtained from the resolver after resolving the program (`Resolver::global_slots`).
+    pub fn set_global_slots(&mut self, global_slots: HashMap<String, usize>) {
+        self.global_slots = global_slots;
+    }
+
+    /// Returns the value of the global variable `ident` or `None` if it does not exist.
+    /// Declared globals (see [`Self::set_global_slots`]) take precedence over late-bound globals.
+    pub fn get_global(&self, ident: &str) -> Option<Value> {
+        match self.global_slots.get(ident) {
+            Some(slot) if *slot < self.stack.len() => Some(self.stack[*slot].clone()),
+            _ => self.late_bound_globals.get(ident).cloned(),
+        }
+    }
+
+    /// Sets the value of the global variable `ident`.
+    /// If `ident` is not a declared global (see [`Self::set_global_slots`]), it is defined as a late-bound global.
+    pub fn set_global(&mut self, ident: &str, value: Value) {
+        match self.global_slots.get(ident) {
+            Some(slot) if *slot < self.stack.len() => self.stack[*slot] = value,
+            _ => self.set_late_bound_global(ident, value),
+        }
+    }
+
+    /// Defines or updates a late-bound global variable.
+    /// Late-bound globals are referenced by code resolved with late-bound globals mode enabled.
+    pub fn set_late_bound_global(&mut self, ident: impl ToString, value: Value) {
+        self.late_bound_globals.insert(ident.to_string(), value);
+    }
+
+    /// Sets a callback that is called before executing every instruction (including the instructions of functions
+    /// called by native functions). Useful for profiling and coverage tools.
+    /// The callback receives the opcode, the offset of the instruction in its chunk and the source line.
+    /// When no trace hook is set, executing instructions has no tracing overhead.
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(OpCode, usize, usize) + 'a) {
+        self.trace_hook = Some(Box::new(hook));
+    }
+
+    /// Interprets the `chunk` (see [`Self::interpret`]) and returns the result with the set of the source lines of the
+    /// executed instructions (including the instructions of called functions). Useful for coverage reports.
+    /// Line `0` (instructions that do not belong to a statement, e.g. the implicit return at the end of a function) is
+    /// not included. The lines are collected with a trace hook. The trace hook set by [`Self::set_trace_hook`] is not called while
+    /// running the `chunk` and is restored afterwards.
+    pub fn line_coverage(
+        &mut self,
+        chunk: impl Into<Rc<Chunk>>,
+    ) -> (InterpretResult, HashSet<usize>) {
+        let lines = Rc::new(RefCell::new(HashSet::new()));
+        let previous_hook = self.trace_hook.take();
+        let executed_lines = Rc::clone(&lines);
+        self.set_trace_hook(move |_opcode, _ip, line| {
+            if line != 0 {
+                executed_lines.borrow_mut().insert(line);
+            }
+        });
+
+        let result = self.interpret(chunk);
+        self.trace_hook = previous_hook;
+        let lines = lines.take();
+        (result, lines)
+    }
+
+    /// Removes the trace hook set by [`Self::set_trace_hook`].
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    /// Sets a callback that is called before executing every instruction with the specified `opcode`, replacing the
+    /// previous callback for this opcode. The callback receives the operand bytes of the instruction (e.g. the number
+    /// of arguments for [`OpCode::Calli`]) and the VM stack. Modifying the values on the stack changes the values the
+    /// instruction operates on. Pushing or popping values raises a runtime error because the instruction and the call
+    /// frames rely on the depth of the stack.
+    /// When no opcode hook is set, executing instructions has no overhead.
+    pub fn set_opcode_hook(
+        &mut self,
+        opcode: OpCode,
+        hook: impl FnMut(&[u8], &mut ValueArray) + 'a,
+    ) {
+        self.opcode_hooks.insert(opcode, Box::new(hook));
+    }
+
+    /// Removes the callback set by [`Self::set_opcode_hook`] for the specified `opcode`.
+    pub fn remove_opcode_hook(&mut self, opcode: OpCode) {
+        self.opcode_hooks.remove(&opcode);
+    }
+
+    pub fn stack(&self) -> &ValueArray {
+        &self.stack
+    }
+
+    pub fn restore_stack(&mut self, stack: ValueArray) {
+        self.stack = stack;
+    }
+}
+
+impl<'a> CallContext for Vm<'a> {
+    fn call_value(&mut self, func: Value, args: &[Value]) -> Result<Value, CallError> {
+        let call_depth = self.call_stack.len();
+        let stack_len = self.stack.len();
+
+        self.stack.extend_from_slice(args);
+        let result = self
+            .call(func, args.len() as u8)
+            .map_err(|(kind, message)| CallError { kind, message })
+            .and_then(|()| {
+                if self.call_stack.len() > call_depth {
+                    // a closure was called, run until it returns
+                    match self.run(call_depth) {
+                        InterpretResult::Ok => {}
+                        InterpretResult::RuntimeError { kind, message, .. } => {
+                            return Err(CallError { kind, message })
+                        }
+                    }
+                }
+                Ok(self.stack.pop().unwrap())
+            });
+
+        if result.is_err() {
+            // discard the call frames and values of the aborted call
+            self.call_stack.truncate(call_depth);
+            self.stack.truncate(stack_len);
+        }
+        result
+    }
+}
+
+/// Result of [`OpCode::FloorDiv`]: rounds the `quotient` down to the nearest integer.
+fn floor_number(quotient: f64) -> Value {
+    Value::Number(quotient.floor())
+}
@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ella::try_interpret;
+use ella_vm::vm::InterpretResult;
+
+fn build_string(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build-string");
+
+    group.bench_function("concatenation", |b| {
+        b.iter(|| {
+            assert_eq!(
+                try_interpret(
+                    r#"
+                    let s = "";
+                    let i = 0;
+                    while i < 10000 {
+                        s = s + "a";
+                        i += 1;
+                    }"#
+                ),
+                InterpretResult::Ok
+            );
+        })
+    });
+    group.bench_function("string-builder", |b| {
+        b.iter(|| {
+            assert_eq!(
+                try_interpret(
+                    r#"
+                    let builder = string_builder();
+                    let i = 0;
+                    while i < 10000 {
+                        append(builder, "a");
+                        i += 1;
+                    }
+                    let s = to_string(builder);"#
+                ),
+                InterpretResult::Ok
+            );
+        })
+    });
+}
+
+criterion_group!(benches, build_string);
+criterion_main!(benches);
@@ -1,91 +1,578 @@
-//! Implementations of builtin functions and symbols.
-
-use std::rc::Rc;
-
-use ella_value::object::{Obj, ObjKind};
-use ella_value::{BuiltinVars, Value};
-
-#[allow(dead_code)] // This appears to be a bug with rustc. These functions are used in both main.rs and lib.rs
-
-/// Returns the default [`BuiltinVars`] that should be used.
-pub fn default_builtin_vars() -> BuiltinVars {
-    let mut builtin_vars = BuiltinVars::new();
-    builtin_vars.add_native_fn("print", &print, 1);
-    builtin_vars.add_native_fn("println", &println, 1);
-    builtin_vars.add_native_fn("readln", &readln, 0);
-    builtin_vars.add_native_fn("assert", &assert, 1);
-    builtin_vars.add_native_fn("assert_eq", &assert_eq, 2);
-    builtin_vars.add_native_fn("is_nan", &is_nan, 1);
-    builtin_vars.add_native_fn("parse_number", &parse_number, 1);
-    builtin_vars.add_native_fn("clock", &clock, 0);
-    builtin_vars
-}
-
-pub fn print(args: &mut [Value]) -> Value {
-    let arg = &args[0];
-    print!("{}", arg);
-
-    Value::Bool(true)
-}
-
-pub fn println(args: &mut [Value]) -> Value {
-    let arg = &args[0];
-    println!("{}", arg);
-
-    Value::Bool(true)
-}
-
-pub fn readln(_args: &mut [Value]) -> Value {
-    let mut input = String::new();
-    let stdin = std::io::stdin();
-    stdin.read_line(&mut input).expect("cannot read line");
-    Value::Object(Rc::new(Obj::new_string(input)))
-}
-
-pub fn assert(args: &mut [Value]) -> Value {
-    let arg = &args[0];
-
-    match arg {
-        Value::Bool(val) => assert!(*val),
-        _ => {}
-    }
-    Value::Bool(true)
-}
-
-pub fn assert_eq(args: &mut [Value]) -> Value {
-    let left = &args[0];
-    let right = &args[1];
-
-    assert_eq!(left, right);
-    Value::Bool(true)
-}
-
-pub fn is_nan(args: &mut [Value]) -> Value {
-    let number = &args[0];
-
-    match number {
-        Value::Number(number) if number.is_nan() => Value::Bool(true),
-        _ => Value::Bool(false),
-    }
-}
-
-pub fn parse_number(args: &mut [Value]) -> Value {
-    let string = &args[0];
-
-    match string {
-        Value::Object(obj) => match &obj.kind {
-            ObjKind::Str(string) => Value::Number(string.trim().parse().unwrap_or(f64::NAN)),
-            _ => Value::Number(f64::NAN),
-        },
-        _ => Value::Number(f64::NAN),
-    }
-}
-
-pub fn clock(_args: &mut [Value]) -> Value {
-    let now = std::time::SystemTime::now();
-    let since_the_epoch_secs = now
-        .duration_since(std::time::UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs_f64();
-    Value::Number(since_the_epoch_secs)
-}
+//! Implementations of builtin functions and symbols.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use ella_parser::lexer::parse_number_lit;
+use ella_value::object::{Arity, CallContext, CallError, Obj, ObjKind};
+use ella_value::{BuiltinVars, Value};
+
+/// Returns the default [`BuiltinVars`] that should be used.
+pub fn default_builtin_vars() -> BuiltinVars {
+    let mut builtin_vars = BuiltinVars::new();
+    builtin_vars.add_native_fn("print", &print, Arity::Variadic { min: 0 });
+    builtin_vars.add_native_fn("println", &println, Arity::Variadic { min: 0 });
+    builtin_vars.add_native_fn("readln", &readln, Arity::Range { min: 0, max: 1 });
+    builtin_vars.add_fallible_native_fn("assert", &assert, Arity::Range { min: 1, max: 2 });
+    builtin_vars.add_native_fn("assert_eq", &assert_eq, 2);
+    builtin_vars.add_fallible_native_fn("assert_ne", &assert_ne, 2);
+    builtin_vars.add_fallible_native_fn("assert_approx_eq", &assert_approx_eq, 3);
+    builtin_vars.add_native_fn("is_nan", &is_nan, 1);
+    builtin_vars.add_native_fn("is_inf", &is_inf, 1);
+    builtin_vars.add_native_fn("is_finite", &is_finite, 1);
+    builtin_vars.add_fallible_native_fn("between", &between, 3);
+    builtin_vars.add_native_fn("parse_number", &parse_number, 1);
+    builtin_vars.add_native_fn("parse_bool", &parse_bool, 1);
+    builtin_vars.add_fallible_native_fn("len", &len, 1);
+    builtin_vars.add_fallible_native_fn("contains", &contains, 2);
+    builtin_vars.add_fallible_native_fn("index_of", &index_of, 2);
+    builtin_vars.add_fallible_native_fn("slice", &slice, 3);
+    builtin_vars.add_fallible_native_fn("range", &range, Arity::Range { min: 2, max: 3 });
+    builtin_vars.add_reentrant_native_fn("map", &map, 2);
+    builtin_vars.add_reentrant_native_fn("filter", &filter, 2);
+    builtin_vars.add_native_fn("string_builder", &string_builder, 0);
+    builtin_vars.add_fallible_native_fn("append", &append, 2);
+    builtin_vars.add_native_fn("to_string", &to_string, 1);
+    builtin_vars.add_native_fn("empty_map", &empty_map, 0);
+    builtin_vars.add_fallible_native_fn("insert", &insert, 3);
+    builtin_vars.add_fallible_native_fn("get", &get, 2);
+    builtin_vars.add_fallible_native_fn("keys", &keys, 1);
+    builtin_vars.add_fallible_native_fn("values", &values, 1);
+    builtin_vars.add_native_fn("typeof", &type_of, 1);
+    builtin_vars.add_fallible_native_fn("fn_name", &fn_name, 1);
+    builtin_vars.add_fallible_native_fn("arity", &arity, 1);
+    builtin_vars.add_native_fn("clock", &clock, 0);
+    builtin_vars
+}
+
+/// Prints all the arguments separated by spaces.
+pub fn print(args: &mut [Value]) -> Value {
+    let stdout = io::stdout();
+    write_values(&mut stdout.lock(), args, false);
+
+    Value::Bool(true)
+}
+
+/// Prints all the arguments separated by spaces, followed by a newline.
+pub fn println(args: &mut [Value]) -> Value {
+    let stdout = io::stdout();
+    write_values(&mut stdout.lock(), args, true);
+
+    Value::Bool(true)
+}
+
+/// Implementation for [`print`] and [`println`] that is generic over the output stream.
+fn write_values(output: &mut impl Write, values: &[Value], newline: bool) {
+    let mut line = values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if newline {
+        line.push('\n');
+    }
+    output
+        .write_all(line.as_bytes())
+        .expect("failed to write to stdout");
+}
+
+/// Reads a line from stdin. If a prompt is passed as an argument, it is printed first.
+/// Returns `nil` if stdin is closed (EOF).
+pub fn readln(args: &mut [Value]) -> Value {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    read_line(&mut stdin.lock(), &mut stdout.lock(), args.first())
+}
+
+/// Implementation for [`readln`] that is generic over the input and output streams.
+fn read_line(input: &mut impl BufRead, output: &mut impl Write, prompt: Option<&Value>) -> Value {
+    if let Some(prompt) = prompt {
+        // The prompt is purely cosmetic. Do not fail if it cannot be written.
+        let _ = write!(output, "{}", prompt);
+        let _ = output.flush();
+    }
+
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => Value::Nil, // EOF
+        Ok(_) => Value::Object(Rc::new(Obj::new_string(line))),
+    }
+}
+
+/// Raises a runtime error if `args[0]` is `false`.
+/// The optional `args[1]` is included in the error message. When `assert` is called with a single argument, the
+/// compiler passes the source of the condition as `args[1]` (e.g. `Assertion failed: x > 0`).
+pub fn assert(args: &mut [Value]) -> Result<Value, String> {
+    match (&args[0], args.get(1)) {
+        (Value::Bool(false), Some(message)) => Err(format!("Assertion failed: {}", message)),
+        (Value::Bool(false), None) => Err("Assertion failed.".to_string()),
+        _ => Ok(Value::Bool(true)),
+    }
+}
+
+pub fn assert_eq(args: &mut [Value]) -> Value {
+    let left = &args[0];
+    let right = &args[1];
+
+    assert_eq!(left, right);
+    Value::Bool(true)
+}
+
+pub fn assert_ne(args: &mut [Value]) -> Result<Value, String> {
+    let left = &args[0];
+    let right = &args[1];
+
+    if left == right {
+        Err(format!(
+            "Assertion failed: expected values to be different, both are {}.",
+            left
+        ))
+    } else {
+        Ok(Value::Bool(true))
+    }
+}
+
+/// Asserts that two numbers are equal within a tolerance (`epsilon`).
+pub fn assert_approx_eq(args: &mut [Value]) -> Result<Value, String> {
+    match (&args[0], &args[1], &args[2]) {
+        (Value::Number(left), Value::Number(right), Value::Number(epsilon)) => {
+            if (left - right).abs() <= *epsilon {
+                Ok(Value::Bool(true))
+            } else {
+                Err(format!(
+                    "Assertion failed: expected {} and {} to be equal within {}.",
+                    left, right, epsilon
+                ))
+            }
+        }
+        (left, right, epsilon) => Err(format!(
+            "Operands must be numbers. Received {}, {} and {}",
+            left, right, epsilon
+        )),
+    }
+}
+
+pub fn is_nan(args: &mut [Value]) -> Value {
+    let number = &args[0];
+
+    match number {
+        Value::Number(number) if number.is_nan() => Value::Bool(true),
+        _ => Value::Bool(false),
+    }
+}
+
+pub fn is_inf(args: &mut [Value]) -> Value {
+    let number = &args[0];
+
+    match number {
+        Value::Number(number) if number.is_infinite() => Value::Bool(true),
+        _ => Value::Bool(false),
+    }
+}
+
+pub fn is_finite(args: &mut [Value]) -> Value {
+    let number = &args[0];
+
+    match number {
+        Value::Number(number) if number.is_finite() => Value::Bool(true),
+        _ => Value::Bool(false),
+    }
+}
+
+/// Returns `true` if `args[0]` is between `args[1]` and `args[2]` (both inclusive).
+/// Returns an error if any argument is not a number.
+pub fn between(args: &mut [Value]) -> Result<Value, String> {
+    match (&args[0], &args[1], &args[2]) {
+        (Value::Number(x), Value::Number(lo), Value::Number(hi)) => {
+            Ok(Value::Bool(lo <= x && x <= hi))
+        }
+        (x, lo, hi) => Err(format!(
+            "Operands must be numbers. Received {}, {} and {}",
+            x, lo, hi
+        )),
+    }
+}
+
+/// Parses a string into a number. Accepts the same formats as number literals with an optional sign.
+/// Returns `NaN` if the string is not a valid number.
+pub fn parse_number(args: &mut [Value]) -> Value {
+    let string = &args[0];
+
+    match string {
+        Value::Object(obj) => match &obj.kind {
+            ObjKind::Str(string) => {
+                let string = string.trim();
+                let (sign, digits) = match string.strip_prefix('-') {
+                    Some(digits) => (-1.0, digits),
+                    None => (1.0, string.strip_prefix('+').unwrap_or(string)),
+                };
+                Value::Number(parse_number_lit(digits).map_or(f64::NAN, |number| sign * number))
+            }
+            _ => Value::Number(f64::NAN),
+        },
+        _ => Value::Number(f64::NAN),
+    }
+}
+
+/// Parses a string into a bool. Accepts `true` and `false` (case-insensitive) surrounded by optional whitespace.
+/// Returns `nil` if the string is not a valid bool.
+pub fn parse_bool(args: &mut [Value]) -> Value {
+    match args[0].cast_to_str().map(str::trim) {
+        Some(string) if string.eq_ignore_ascii_case("true") => Value::Bool(true),
+        Some(string) if string.eq_ignore_ascii_case("false") => Value::Bool(false),
+        _ => Value::Nil,
+    }
+}
+
+/// Returns the number of characters of the string `args[0]`, the number of elements of the array `args[0]` or the
+/// number of entries of the map `args[0]`.
+pub fn len(args: &mut [Value]) -> Result<Value, String> {
+    let len = match &args[0] {
+        Value::Object(obj) => match &obj.kind {
+            ObjKind::Str(string) => string.chars().count(),
+            ObjKind::Array(elements) => elements.len(),
+            ObjKind::Map(entries) => entries.borrow().len(),
+            _ => return Err(format!("Cannot get the length of {}", args[0])),
+        },
+        _ => return Err(format!("Cannot get the length of {}", args[0])),
+    };
+    Ok(Value::Number(len as f64))
+}
+
+/// Returns the index of the first occurrence of `args[1]` in `args[0]` or `None` if not found.
+/// For strings, `args[1]` is a substring and the index is a character index. For arrays, `args[1]` is an element.
+/// Returns an error if the arguments cannot be searched.
+fn find(args: &[Value]) -> Result<Option<usize>, String> {
+    let haystack = &args[0];
+    let needle = &args[1];
+
+    match haystack {
+        Value::Object(obj) => match &obj.kind {
+            ObjKind::Str(haystack) => match needle.cast_to_str() {
+                Some(needle) => Ok(haystack
+                    .find(needle)
+                    .map(|byte_index| haystack[..byte_index].chars().count())),
+                None => Err(format!(
+                    "Can only search for a string in a string. Received {}",
+                    needle
+                )),
+            },
+            ObjKind::Array(elements) => Ok(elements.iter().position(|element| element == needle)),
+            _ => Err(format!("Cannot search in {}", haystack)),
+        },
+        _ => Err(format!("Cannot search in {}", haystack)),
+    }
+}
+
+/// Returns `true` if the string `args[0]` contains the substring `args[1]` or if the array `args[0]` contains the
+/// element `args[1]`.
+pub fn contains(args: &mut [Value]) -> Result<Value, String> {
+    Ok(Value::Bool(find(args)?.is_some()))
+}
+
+/// Returns the index of the first occurrence of the substring (or element) `args[1]` in the string (or array) `args[0]`
+/// or `-1` if not found.
+pub fn index_of(args: &mut [Value]) -> Result<Value, String> {
+    Ok(Value::Number(
+        find(args)?.map_or(-1.0, |index| index as f64),
+    ))
+}
+
+/// Returns the substring of the string `args[0]` from the character index `args[1]` (inclusive) to `args[2]`
+/// (exclusive). The substring shares the storage of the original string.
+/// Returns an error if the indices are not integers with `0 <= start <= end <= length`.
+pub fn slice(args: &mut [Value]) -> Result<Value, String> {
+    let string = match &args[0] {
+        Value::Object(obj) => match &obj.kind {
+            ObjKind::Str(string) => string,
+            _ => return Err(format!("Expected a string. Received {}", args[0])),
+        },
+        _ => return Err(format!("Expected a string. Received {}", args[0])),
+    };
+
+    let len = string.chars().count();
+    let (start, end) = match (args[1].cast_to_number(), args[2].cast_to_number()) {
+        (Some(start), Some(end))
+            if start.fract() == 0.0
+                && end.fract() == 0.0
+                && 0.0 <= start
+                && start <= end
+                && end <= len as f64 =>
+        {
+            (start as usize, end as usize)
+        }
+        _ => {
+            return Err(format!(
+                "Invalid slice range {} to {} for a string of length {}",
+                args[1], args[2], len
+            ))
+        }
+    };
+
+    // convert character indices into byte indices
+    let byte_index = |char_index: usize| {
+        string
+            .char_indices()
+            .nth(char_index)
+            .map_or(string.len(), |(byte_index, _)| byte_index)
+    };
+    let substring = string.slice(byte_index(start)..byte_index(end));
+    Ok(Value::Object(Rc::new(Obj::new_str(substring))))
+}
+
+/// The maximum number of elements of an array created by [`range`].
+pub const MAX_RANGE_LEN: usize = 1 << 24;
+
+/// Returns an array with the numbers from `args[0]` (inclusive) to `args[1]` (exclusive), separated by the step
+/// `args[2]` (`1` if not specified). A negative step counts down.
+/// Returns an empty array if the step does not go from the start towards the end (e.g. `range(0, 3, -1)`).
+/// Returns an error if any argument is not an integer (or is too large to be represented exactly), if the step is `0`
+/// or if the range has more than [`MAX_RANGE_LEN`] elements.
+pub fn range(args: &mut [Value]) -> Result<Value, String> {
+    /// Integers from `-2^53` (exclusive) to `2^53` (exclusive) can be represented exactly by a `f64`.
+    const MAX_EXACT_INTEGER: f64 = (1u64 << 53) as f64;
+
+    let expect_integer = |value: &Value| match value.cast_to_number() {
+        Some(number) if number.fract() == 0.0 && number.abs() < MAX_EXACT_INTEGER => Ok(number),
+        _ => Err(format!("Expected an integer. Received {}", value)),
+    };
+
+    let start = expect_integer(&args[0])?;
+    let end = expect_integer(&args[1])?;
+    let step = match args.get(2) {
+        Some(step) => expect_integer(step)?,
+        None => 1.0,
+    };
+    if step == 0.0 {
+        return Err("Range step must not be 0".to_string());
+    }
+
+    let len = ((end - start) / step).ceil().max(0.0);
+    if len > MAX_RANGE_LEN as f64 {
+        return Err(format!(
+            "Range must not have more than {} elements. Received {} elements",
+            MAX_RANGE_LEN, len
+        ));
+    }
+    let elements = (0..len as usize)
+        .map(|i| Value::Number(start + i as f64 * step))
+        .collect::<Vec<_>>();
+    Ok(Value::from(elements))
+}
+
+/// Returns the elements of the array `value` or an error if `value` is not an array.
+fn expect_array(value: &Value) -> Result<&[Value], String> {
+    value
+        .cast_to_array()
+        .ok_or_else(|| format!("Expected an array. Received {}", value))
+}
+
+/// Returns a new array with the results of calling the function `args[1]` on every element of the array `args[0]`.
+pub fn map(ctx: &mut dyn CallContext, args: &mut [Value]) -> Result<Value, CallError> {
+    let elements = expect_array(&args[0])?;
+    let func = &args[1];
+
+    let mapped = elements
+        .iter()
+        .map(|element| ctx.call_value(func.clone(), std::slice::from_ref(element)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::from(mapped))
+}
+
+/// Returns a new array with the elements of the array `args[0]` for which the predicate `args[1]` returns `true`.
+/// Returns an error if the predicate returns a value that is not a boolean.
+pub fn filter(ctx: &mut dyn CallContext, args: &mut [Value]) -> Result<Value, CallError> {
+    let elements = expect_array(&args[0])?;
+    let predicate = &args[1];
+
+    let mut filtered = Vec::new();
+    for element in elements {
+        let result = ctx.call_value(predicate.clone(), std::slice::from_ref(element))?;
+        match result.cast_to_bool() {
+            Some(true) => filtered.push(element.clone()),
+            Some(false) => {}
+            None => {
+                return Err(format!("Predicate must return a boolean. Received {}", result).into())
+            }
+        }
+    }
+    Ok(Value::from(filtered))
+}
+
+/// Returns a new empty string builder.
+pub fn string_builder(_args: &mut [Value]) -> Value {
+    Value::Object(Rc::new(Obj::new_string_builder()))
+}
+
+/// Appends `args[1]` (formatted like [`print`]) to the string builder `args[0]` in place.
+/// Returns an error if `args[0]` is not a string builder.
+pub fn append(args: &mut [Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Object(obj) => match &obj.kind {
+            ObjKind::StringBuilder(buffer) => {
+                // format before borrowing the buffer mutably because `args[1]` can contain the string builder itself
+                let appended = args[1].to_string();
+                buffer.borrow_mut().push_str(&appended);
+                Ok(Value::Nil)
+            }
+            _ => Err(format!("Expected a string builder. Received {}", args[0])),
+        },
+        _ => Err(format!("Expected a string builder. Received {}", args[0])),
+    }
+}
+
+/// Returns `args[0]` formatted like [`print`] as a new string. Useful for getting the content of a string builder.
+pub fn to_string(args: &mut [Value]) -> Value {
+    Value::Object(Rc::new(Obj::new_string(args[0].to_string())))
+}
+
+/// Returns a new empty map.
+pub fn empty_map(_args: &mut [Value]) -> Value {
+    Value::Object(Rc::new(Obj::new_map()))
+}
+
+/// Returns the entries of the map `value` or an error if `value` is not a map.
+fn expect_map(value: &Value) -> Result<&RefCell<BTreeMap<String, Value>>, String> {
+    match value {
+        Value::Object(obj) => match &obj.kind {
+            ObjKind::Map(entries) => Ok(entries),
+            _ => Err(format!("Expected a map. Received {}", value)),
+        },
+        _ => Err(format!("Expected a map. Received {}", value)),
+    }
+}
+
+/// Returns the string `value` or an error if `value` cannot be used as a map key.
+fn expect_key(value: &Value) -> Result<&str, String> {
+    value
+        .cast_to_str()
+        .ok_or_else(|| format!("Map keys must be strings. Received {}", value))
+}
+
+/// Inserts the value `args[2]` with the key `args[1]` into the map `args[0]` in place.
+/// Replaces the previous value of the key if there is one.
+pub fn insert(args: &mut [Value]) -> Result<Value, String> {
+    let entries = expect_map(&args[0])?;
+    let key = expect_key(&args[1])?.to_string();
+    entries.borrow_mut().insert(key, args[2].clone());
+    Ok(Value::Nil)
+}
+
+/// Returns the value with the key `args[1]` in the map `args[0]` or `nil` if the map does not contain the key.
+pub fn get(args: &mut [Value]) -> Result<Value, String> {
+    let entries = expect_map(&args[0])?;
+    let key = expect_key(&args[1])?;
+    Ok(entries.borrow().get(key).cloned().unwrap_or(Value::Nil))
+}
+
+/// Returns an array with the keys of the map `args[0]` in ascending order.
+pub fn keys(args: &mut [Value]) -> Result<Value, String> {
+    let entries = expect_map(&args[0])?;
+    let keys = entries
+        .borrow()
+        .keys()
+        .map(|key| Value::from(key.as_str()))
+        .collect::<Vec<_>>();
+    Ok(Value::from(keys))
+}
+
+/// Returns an array with the values of the map `args[0]` in the order of their keys (see [`keys`]).
+pub fn values(args: &mut [Value]) -> Result<Value, String> {
+    let entries = expect_map(&args[0])?;
+    let values = entries.borrow().values().cloned().collect::<Vec<_>>();
+    Ok(Value::from(values))
+}
+
+/// Returns the name of the type of `args[0]` as a string (e.g. `"number"`). See [`Value::type_name`].
+pub fn type_of(args: &mut [Value]) -> Value {
+    Value::from(args[0].type_name())
+}
+
+/// Returns the identifier and the [`Arity`] of the function `value` or an error if `value` is not a function.
+fn fn_metadata(value: &Value) -> Result<(&str, Arity), String> {
+    match value {
+        Value::Object(obj) => match &obj.kind {
+            ObjKind::Fn(func) => Ok((&func.ident, Arity::Exact(func.arity))),
+            ObjKind::Closure(closure) => {
+                Ok((&closure.func.ident, Arity::Exact(closure.func.arity)))
+            }
+            ObjKind::NativeFn(native_fn) => Ok((&native_fn.ident, native_fn.arity)),
+            _ => Err(format!("Expected a function. Received {}", value)),
+        },
+        _ => Err(format!("Expected a function. Received {}", value)),
+    }
+}
+
+/// Returns the identifier of the function `args[0]` as a string.
+pub fn fn_name(args: &mut [Value]) -> Result<Value, String> {
+    let (ident, _) = fn_metadata(&args[0])?;
+    Ok(Value::from(ident))
+}
+
+/// Returns the minimum number of arguments the function `args[0]` accepts.
+/// This is the number of parameters for functions declared with `fn`.
+pub fn arity(args: &mut [Value]) -> Result<Value, String> {
+    let (_, arity) = fn_metadata(&args[0])?;
+    let min = match arity {
+        Arity::Exact(arity) => arity,
+        Arity::Range { min, .. } | Arity::Variadic { min } => min,
+    };
+    Ok(Value::Number(min as f64))
+}
+
+pub fn clock(_args: &mut [Value]) -> Value {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch_secs = now
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs_f64();
+    Value::Number(since_the_epoch_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(string: &str) -> Value {
+        Value::Object(Rc::new(Obj::new_string(string.to_string())))
+    }
+
+    #[test]
+    fn test_write_values() {
+        let mut output = Vec::new();
+        write_values(&mut output, &[], true);
+        assert_eq!(output, b"\n");
+
+        let mut output = Vec::new();
+        write_values(&mut output, &[string("a"), string("b")], false);
+        assert_eq!(output, b"a b");
+
+        let mut output = Vec::new();
+        write_values(&mut output, &[string("a"), Value::Number(1.0)], true);
+        assert_eq!(output, b"a 1\n");
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut output = Vec::new();
+        let line = read_line(&mut "hello\n".as_bytes(), &mut output, None);
+        assert_eq!(line.cast_to_str(), Some("hello\n"));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_read_line_with_prompt() {
+        let mut output = Vec::new();
+        read_line(&mut "hello\n".as_bytes(), &mut output, Some(&string("> ")));
+        assert_eq!(output, b"> ");
+    }
+
+    #[test]
+    fn test_read_line_eof() {
+        let mut output = Vec::new();
+        let line = read_line(&mut io::empty(), &mut output, None);
+        assert_eq!(line, Value::Nil);
+    }
+}
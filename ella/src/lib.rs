@@ -1,43 +1,101 @@
-pub mod builtin_functions;
-
-/// For testing purposes only.
-pub fn interpret(source: &str) {
-    use builtin_functions::default_builtin_vars;
-
-    use ella_parser::parser::Parser;
-    use ella_passes::resolve::Resolver;
-    use ella_vm::codegen::Codegen;
-    use ella_vm::vm::{InterpretResult, Vm};
-
-    let builtin_vars = default_builtin_vars();
-
-    let dummy_source = "".into();
-    let mut resolver = Resolver::new(&dummy_source);
-    resolver.resolve_builtin_vars(&builtin_vars);
-    let mut resolve_result = resolver.resolve_result();
-    let accessible_symbols = resolver.accessible_symbols();
-
-    let mut vm = Vm::new(&builtin_vars);
-    let mut codegen = Codegen::new("<global>".to_string(), resolve_result);
-    codegen.codegen_builtin_vars(&builtin_vars);
-    vm.interpret(codegen.into_inner_chunk()); // load built in functions into memory
-
-    let source = source.into();
-    let mut parser = Parser::new(&source);
-    let ast = parser.parse_program();
-
-    let mut resolver =
-        Resolver::new_with_existing_accessible_symbols(&source, accessible_symbols.clone());
-    resolver.resolve_top_level(&ast);
-    resolve_result = resolver.resolve_result();
-
-    eprintln!("{}", source.errors);
-    assert!(source.has_no_errors());
-
-    let mut codegen = Codegen::new("<global>".to_string(), resolve_result);
-
-    codegen.codegen_function(&ast);
-
-    let chunk = codegen.into_inner_chunk();
-    assert_eq!(vm.interpret(chunk), InterpretResult::Ok);
-}
+pub mod builtin_functions;
+pub mod repl;
+
+use ella_parser::parser::Parser;
+use ella_passes::resolve::Resolver;
+use ella_source::{Source, SyntaxError};
+use ella_value::chunk::Chunk;
+use ella_value::BuiltinVars;
+use ella_vm::codegen::Codegen;
+use ella_vm::vm::InterpretResult;
+use std::io::{self, Write};
+
+/// Writes all the accumulated diagnostics of `source` to `output`. Warnings are written before errors.
+/// Returns `true` if there are no errors and the program can be executed. Returns `false` otherwise.
+pub fn report_diagnostics(source: &Source, output: &mut impl Write) -> io::Result<bool> {
+    for warning in source.errors.warnings() {
+        writeln!(output, "{}", source.display_error(&warning))?;
+    }
+    let errors = source.errors.errors();
+    for error in &errors {
+        writeln!(output, "{}", source.display_error(error))?;
+    }
+    Ok(errors.is_empty())
+}
+
+/// Parses, resolves and generates the bytecode of the top-level function of `source` without executing it.
+/// Returns the compile errors (but not the warnings) if `source` has errors.
+///
+/// `builtin_vars` are resolved as the first global variables. Before executing the chunk, the VM stack must contain the
+/// builtin variables (e.g. by executing the chunk generated by [`Codegen::codegen_builtin_vars`]).
+///
+/// # Example
+/// ```
+/// use ella::builtin_functions::default_builtin_vars;
+/// use ella::compile;
+///
+/// let builtin_vars = default_builtin_vars();
+/// assert!(compile("let x = 1;", &builtin_vars).is_ok());
+/// assert!(compile("let x = ;", &builtin_vars).is_err());
+/// ```
+pub fn compile(source: &str, builtin_vars: &BuiltinVars) -> Result<Chunk, Vec<SyntaxError>> {
+    let source = source.into();
+    let ast = Parser::new(&source).parse_program();
+
+    let mut resolver = Resolver::new(&source);
+    resolver.resolve_builtin_vars(builtin_vars);
+    resolver.resolve_top_level(&ast);
+    if !source.has_no_errors() {
+        return Err(source.errors.errors());
+    }
+
+    let mut codegen = Codegen::new("<global>".to_string(), resolver.resolve_result());
+    codegen.codegen_function(&ast);
+    Ok(codegen.into_inner_chunk())
+}
+
+/// For testing purposes only.
+/// Panics if the `source` has compile errors or if a runtime error occurs.
+pub fn interpret(source: &str) {
+    assert_eq!(try_interpret(source), InterpretResult::Ok);
+}
+
+/// For testing purposes only.
+/// Panics if the `source` has compile errors. Returns the [`InterpretResult`] otherwise.
+pub fn try_interpret(source: &str) -> InterpretResult {
+    use builtin_functions::default_builtin_vars;
+
+    use ella_vm::vm::Vm;
+
+    let builtin_vars = default_builtin_vars();
+
+    let dummy_source = "".into();
+    let mut resolver = Resolver::new(&dummy_source);
+    resolver.resolve_builtin_vars(&builtin_vars);
+    let mut resolve_result = resolver.resolve_result();
+    let accessible_symbols = resolver.accessible_symbols();
+
+    let mut vm = Vm::new(&builtin_vars);
+    let mut codegen = Codegen::new("<global>".to_string(), resolve_result);
+    codegen.codegen_builtin_vars(&builtin_vars);
+    vm.interpret(codegen.into_inner_chunk()); // load built in functions into memory
+
+    let source = source.into();
+    let mut parser = Parser::new(&source);
+    let ast = parser.parse_program();
+
+    let mut resolver =
+        Resolver::new_with_existing_accessible_symbols(&source, accessible_symbols.clone());
+    resolver.resolve_top_level(&ast);
+    resolve_result = resolver.resolve_result();
+
+    eprintln!("{}", source.errors);
+    assert!(source.has_no_errors());
+
+    let mut codegen = Codegen::new("<global>".to_string(), resolve_result);
+
+    codegen.codegen_function(&ast);
+
+    let chunk = codegen.into_inner_chunk();
+    vm.interpret(chunk)
+}
@@ -1,29 +1,24 @@
 use ella::builtin_functions::default_builtin_vars;
+use ella::repl::Repl;
+use ella::report_diagnostics;
 use ella_parser::parser::Parser;
 use ella_passes::resolve::Resolver;
+use ella_source::Source;
 use ella_vm::vm::InterpretResult;
 use ella_vm::{codegen::Codegen, vm::Vm};
 
 use std::io::{self, Write};
 
-mod builtin_functions;
-
-fn repl() {
+/// Starts the REPL. If `auto_print` is `true`, the value of a trailing expression statement is printed.
+/// Lines starting with `:` are meta-commands:
+/// * `:save path` - Saves the inputs of the session to a file.
+/// * `:load path` - Replays the inputs saved in a file.
+fn repl(auto_print: bool) {
     let mut stdout = io::stdout();
     let stdin = io::stdin();
 
     let builtin_vars = default_builtin_vars();
-
-    let dummy_source = "".into();
-    let mut resolver = Resolver::new(&dummy_source);
-    resolver.resolve_builtin_vars(&builtin_vars);
-    let mut resolve_result = resolver.resolve_result();
-    let mut accessible_symbols = resolver.accessible_symbols().clone();
-
-    let mut vm = Vm::new(&builtin_vars);
-    let mut codegen = Codegen::new("<global>".to_string(), resolve_result);
-    codegen.codegen_builtin_vars(&builtin_vars);
-    vm.interpret(codegen.into_inner_chunk()); // load built in functions into memory
+    let mut repl = Repl::new(&builtin_vars, auto_print);
 
     loop {
         print!("> ");
@@ -32,41 +27,21 @@ fn repl() {
         let mut input = String::new();
         stdin.read_line(&mut input).unwrap();
 
-        let source = input.as_str().into();
-        let mut parser = Parser::new(&source);
-        let ast = parser.parse_repl_input();
-
-        let mut resolver =
-            Resolver::new_with_existing_accessible_symbols(&source, accessible_symbols.clone());
-        resolver.resolve_top_level(&ast);
-        resolve_result = resolver.resolve_result();
-
-        eprintln!("{}", source.errors);
-        if source.has_no_errors() {
-            let mut codegen = Codegen::new("<global>".to_string(), resolve_result);
-
-            codegen.codegen_function(&ast);
-
-            let chunk = codegen.into_inner_chunk();
-
-            let initial_stack = vm.stack().clone();
-            let interpret_result = vm.interpret(chunk);
-            match &interpret_result {
-                InterpretResult::Ok => {
-                    // Success, update  resolved_symbols with new symbols.
-                    accessible_symbols = resolver.accessible_symbols().clone();
-                }
-                InterpretResult::RuntimeError { .. } => {
-                    eprintln!("{:?}", interpret_result);
-                    // Restore vm stack to previous state to recover from error.
-                    vm.restore_stack(initial_stack);
-                }
+        let result = match input.trim().split_once(' ') {
+            Some((":save", path)) => repl.save(path.trim()),
+            Some((":load", path)) => repl.load(path.trim()),
+            _ => {
+                repl.eval(&input);
+                Ok(())
             }
+        };
+        if let Err(err) = result {
+            eprintln!("Error: {}", err);
         }
     }
 }
 
-fn interpret_file_contents(source: &str) {
+fn interpret_file_contents(source: Source) {
     let builtin_vars = default_builtin_vars();
 
     let dummy_source = "".into();
@@ -80,7 +55,6 @@ fn interpret_file_contents(source: &str) {
     codegen.codegen_builtin_vars(&builtin_vars);
     vm.interpret(codegen.into_inner_chunk()); // load built in functions into memory
 
-    let source = source.into();
     let mut parser = Parser::new(&source);
     let ast = parser.parse_program();
 
@@ -89,9 +63,7 @@ fn interpret_file_contents(source: &str) {
     resolver.resolve_top_level(&ast);
     resolve_result = resolver.resolve_result();
 
-    if !source.has_no_errors() {
-        eprintln!("{}", source.errors);
-    } else {
+    if report_diagnostics(&source, &mut io::stderr()).unwrap() {
         let mut codegen = Codegen::new("<global>".to_string(), resolve_result);
 
         codegen.codegen_function(&ast);
@@ -99,7 +71,7 @@ fn interpret_file_contents(source: &str) {
         let chunk = codegen.into_inner_chunk();
         match vm.interpret(chunk) {
             InterpretResult::Ok => {}
-            InterpretResult::RuntimeError { message, line } => {
+            InterpretResult::RuntimeError { message, line, .. } => {
                 eprintln!("Runtime Error: {} at line {}", message, line);
             }
         }
@@ -107,14 +79,16 @@ fn interpret_file_contents(source: &str) {
 }
 
 fn main() {
-    if std::env::args().len() < 2 {
-        repl();
-    } else {
-        let path = std::env::args().nth(1).unwrap();
-        let contents = std::fs::read_to_string(path);
-        match contents {
-            Ok(contents) => interpret_file_contents(&contents),
-            Err(err) => eprintln!("Error: {}", err),
+    let path = std::env::args().nth(1);
+    match path.as_deref() {
+        None => repl(true),
+        Some("--no-auto-print") => repl(false),
+        Some(path) => {
+            let contents = std::fs::read_to_string(path);
+            match contents {
+                Ok(contents) => interpret_file_contents(Source::from(contents).with_filename(path)),
+                Err(err) => eprintln!("Error: {}", err),
+            }
         }
     }
 }
@@ -0,0 +1,142 @@
+//! REPL (read-eval-print loop) session state.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::report_diagnostics;
+use ella_parser::parser::Parser;
+use ella_passes::resolve::{Resolver, Symbol};
+use ella_value::BuiltinVars;
+use ella_vm::codegen::Codegen;
+use ella_vm::vm::{InterpretResult, Vm};
+
+/// A REPL session. Global variables declared by previous inputs stay accessible to the next inputs.
+pub struct Repl<'a> {
+    vm: Vm<'a>,
+    accessible_symbols: Vec<Rc<RefCell<Symbol>>>,
+    /// Inputs that were executed successfully, in order.
+    history: Vec<String>,
+    /// If `true`, the value of a trailing expression statement is printed.
+    auto_print: bool,
+}
+
+impl<'a> Repl<'a> {
+    /// Creates a new REPL session and loads the `builtin_vars` into the VM.
+    pub fn new(builtin_vars: &'a BuiltinVars, auto_print: bool) -> Self {
+        let dummy_source = "".into();
+        let mut resolver = Resolver::new(&dummy_source);
+        resolver.resolve_builtin_vars(builtin_vars);
+
+        let mut vm = Vm::new(builtin_vars);
+        let mut codegen = Codegen::new("<global>".to_string(), resolver.resolve_result());
+        codegen.codegen_builtin_vars(builtin_vars);
+        vm.interpret(codegen.into_inner_chunk()); // load built in functions into memory
+
+        Self {
+            vm,
+            accessible_symbols: resolver.accessible_symbols().clone(),
+            history: Vec::new(),
+            auto_print,
+        }
+    }
+
+    /// Parses and executes the `input`. Diagnostics and runtime errors are written to stderr.
+    /// Returns `true` if the input was executed successfully. Only successful inputs are added to the history.
+    pub fn eval(&mut self, input: &str) -> bool {
+        match self.try_eval(input) {
+            Ok(()) => true,
+            Err(message) => {
+                eprintln!("{}", message);
+                false
+            }
+        }
+    }
+
+    /// Parses and executes the `input`. Warnings are written to stderr.
+    /// Returns an `Err` with the diagnostics if the input has compile errors or with the runtime error if the execution
+    /// fails.
+    fn try_eval(&mut self, input: &str) -> Result<(), String> {
+        let source = input.into();
+        let ast = Parser::new(&source).parse_repl_input(self.auto_print);
+
+        let mut resolver = Resolver::new_with_existing_accessible_symbols(
+            &source,
+            self.accessible_symbols.clone(),
+        );
+        resolver.resolve_top_level(&ast);
+
+        let mut diagnostics = Vec::new();
+        let no_errors = report_diagnostics(&source, &mut diagnostics).unwrap();
+        let diagnostics = String::from_utf8(diagnostics).unwrap();
+        if !no_errors {
+            return Err(diagnostics.trim_end().to_string());
+        }
+        eprint!("{}", diagnostics);
+
+        let mut codegen = Codegen::new("<global>".to_string(), resolver.resolve_result());
+        codegen.codegen_function(&ast);
+        let chunk = codegen.into_inner_chunk();
+
+        let initial_stack = self.vm.stack().clone();
+        let interpret_result = self.vm.interpret(chunk);
+        match &interpret_result {
+            InterpretResult::Ok => {
+                // Success, update accessible_symbols with new symbols.
+                self.accessible_symbols = resolver.accessible_symbols().clone();
+                self.vm.set_global_slots(resolver.global_slots());
+                self.history.push(input.to_string());
+                Ok(())
+            }
+            InterpretResult::RuntimeError { .. } => {
+                // Restore vm stack to previous state to recover from error.
+                self.vm.restore_stack(initial_stack);
+                Err(format!("{:?}", interpret_result))
+            }
+        }
+    }
+
+    /// Returns the inputs that were executed successfully, in order.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Returns the VM of the session (e.g. to inspect global variables with [`Vm::get_global`]).
+    pub fn vm(&self) -> &Vm<'a> {
+        &self.vm
+    }
+
+    /// Writes the history to the file at `path`, one input per line.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut contents = String::new();
+        for input in &self.history {
+            contents.push_str(input.trim_end());
+            contents.push('\n');
+        }
+        fs::write(path, contents)
+    }
+
+    /// Replays every line of the file at `path` (written by [`Self::save`]) to reconstruct the global state.
+    /// Values of trailing expressions are not printed while replaying.
+    /// Stops at the first line that fails and returns an error with the line number and the error message.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+
+        let auto_print = self.auto_print;
+        self.auto_print = false;
+        let mut result = Ok(());
+        for (index, line) in contents.lines().enumerate() {
+            if let Err(message) = self.try_eval(line) {
+                result = Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Line {}: {}", index + 1, message),
+                ));
+                break;
+            }
+        }
+        self.auto_print = auto_print;
+        result
+    }
+}
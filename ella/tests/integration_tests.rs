@@ -1,293 +1,2279 @@
-use ella::interpret;
-
-#[test]
-#[should_panic]
-fn smoke_assert() {
-    interpret(
-        r#"
-        assert(false);"#,
-    );
-}
-
-#[test]
-#[should_panic]
-fn smoke_assert_eq() {
-    interpret(
-        r#"
-        assert_eq(1, 2);"#,
-    );
-}
-
-#[test]
-fn variables() {
-    interpret(
-        r#"
-        let x = 1;
-        assert_eq(x, 1);
-        let y = x + 1;
-        assert_eq(y, 2);
-        assert_eq(y, x + 1);
-        x = 10;
-        assert_eq(x, 10);"#,
-    );
-}
-
-#[test]
-fn comments() {
-    interpret(
-        r#"
-        let x = 1; // a comment
-        assert_eq(x, 1);"#,
-    );
-}
-
-#[test]
-fn is_nan() {
-    interpret(r#"assert(!is_nan(1));"#);
-    interpret(r#"assert(is_nan(0/0));"#);
-}
-
-#[test]
-fn parse_number() {
-    interpret(r#"assert(!is_nan(parse_number("1")));"#);
-    interpret(r#"assert(!is_nan(parse_number("1.2")));"#);
-    interpret(r#"assert(is_nan(parse_number("not a number")));"#);
-    interpret(r#"assert(is_nan(parse_number(1)));"#);
-}
-
-#[test]
-fn op_assign() {
-    interpret(
-        r#"
-        let x = 10;
-        assert_eq(x, 10);
-
-        assert_eq(x = 12, 12);// should be new value
-        assert_eq(x += 10, 22);
-        assert_eq(x -= 20, 2);
-        assert_eq(x *= 2, 4);
-        assert_eq(x /= 4, 1);"#,
-    );
-}
-
-mod functions {
-    use super::*;
-
-    #[test]
-    fn functions() {
-        interpret(
-            r#"
-            fn foo() {
-                return 1;
-            }
-            assert_eq(foo(), 1);"#,
-        );
-    }
-
-    #[test]
-    fn functions_with_params() {
-        interpret(
-            r#"
-            fn double(x) {
-                let result = x * 2;
-                return result;
-            }
-            assert_eq(double(10), 20);
-            assert_eq(double(-2), -4);"#,
-        );
-    }
-
-    #[test]
-    fn functions_implicit_return() {
-        interpret(
-            r#"
-            fn foo() { }
-            assert_eq(foo(), 0);"#,
-        );
-    }
-
-    #[test]
-    fn higher_order_function() {
-        interpret(
-            r#"
-            fn twice(f, v) {
-                return f(f(v));
-            }
-            fn double(x) {
-                return x * 2;
-            }
-            
-            assert_eq(twice(double, 10), 40);
-            assert_eq(twice(double, -2), -8);"#,
-        );
-    }
-
-    mod closures {
-        use super::*;
-
-        #[test]
-        fn close_global_variable() {
-            interpret(
-                r#"
-                fn assert_test() {
-                    assert(true); // captures assert fn from global scope
-                }
-                assert_test();"#,
-            );
-        }
-
-        #[test]
-        fn basic_closures() {
-            interpret(
-                r#"
-                fn closure() {
-                    let x = 1;
-                    fn inner() {
-                        return x + 1;
-                    }
-                    return inner();
-                }
-                assert_eq(closure(), 2);"#,
-            );
-        }
-
-        #[test]
-        fn capture_with_block_stmt() {
-            interpret(
-                r#"
-                {
-                    assert(true);
-                }"#,
-            )
-        }
-
-        #[test]
-        fn close_upvalues() {
-            interpret(
-                r#"
-                fn createAdder(x) {
-                    fn adder(y) {
-                        return x + y;
-                    }
-                    return adder;
-                }
-                let addTwo = createAdder(2);
-                assert_eq(addTwo(1), 3);"#,
-            );
-            interpret(
-                r#"
-                fn compose(f, g) {
-                    fn inner(x) {
-                        return f(g(x));
-                    }
-                    return inner;
-                }
-                fn addOne(x) { return x + 1; }
-                fn addTwo(x) { return x + 2; }
-                let addThree = compose(addOne, addTwo);
-                assert_eq(addThree(2), 5);"#,
-            );
-        }
-
-        #[test]
-        fn capture_by_ref() {
-            interpret(
-                r#"
-                let globalSet = 0;
-                let globalGet = 0;
-
-                fn main() {
-                    let a = "initial";
-
-                    fn set() { a = "updated"; }
-                    fn get() { return a; }
-
-                    globalSet = set;
-                    globalGet = get;
-                }
-
-                main();
-                assert_eq(globalGet(), "initial");
-                globalSet();
-                assert_eq(globalGet(), "updated");"#,
-            );
-        }
-
-        #[test]
-        fn nested_upvalues() {
-            interpret(
-                r#"
-                fn outer() {
-                    let x = "value";
-
-                    fn middle() {
-                        fn inner() {
-                            return x;
-                        }
-                        return inner;
-                    }
-                    return middle;
-                }
-
-                let mid = outer();
-                let in = mid();
-                assert_eq(in(), "value");"#,
-            );
-        }
-    }
-}
-
-mod control_flow {
-    use super::*;
-
-    #[test]
-    fn if_and_else() {
-        interpret(
-            r#"
-            let x = 0;
-
-            let condition = true;
-            if condition {
-                x = 1;
-            } else {
-                x = 2;
-            }
-            assert_eq(x, 1);
-
-            condition = false;
-            if condition {
-                x = 1;
-            } else {
-                x = 2;
-            }
-            assert_eq(x, 2);"#,
-        );
-    }
-
-    #[test]
-    fn fibonacci() {
-        interpret(
-            r#"
-            fn fib(x) {
-                if x <= 1 { return 1; }
-                else { return fib(x - 1) + fib(x - 2); }
-            }
-            
-            assert_eq(fib(20), 10946);"#,
-        );
-    }
-
-    #[test]
-    fn accumulate() {
-        interpret(
-            r#"
-            let x = 0;
-            let i = 0;
-            while i < 20 {
-                x = x + i;
-                i = i + 1;
-            }
-            assert_eq(x, 190);"#,
-        );
-    }
-}
+use ella::{interpret, try_interpret};
+use ella_vm::vm::{ErrorKind, InterpretResult};
+
+/// Asserts that running the `source` results in a runtime error with the specified `message`.
+fn assert_runtime_error(source: &str, message: &str) {
+    match try_interpret(source) {
+        InterpretResult::RuntimeError {
+            message: error_message,
+            ..
+        } => assert_eq!(error_message, message),
+        InterpretResult::Ok => panic!("expected runtime error: {}", message),
+    }
+}
+
+/// Asserts that running the `source` results in a runtime error of the specified `kind`.
+fn assert_runtime_error_kind(source: &str, kind: ErrorKind) {
+    match try_interpret(source) {
+        InterpretResult::RuntimeError {
+            kind: error_kind, ..
+        } => assert_eq!(error_kind, kind),
+        InterpretResult::Ok => panic!("expected runtime error: {:?}", kind),
+    }
+}
+
+#[test]
+fn runtime_error_kinds() {
+    assert_runtime_error_kind("1 + true;", ErrorKind::TypeError);
+    assert_runtime_error_kind("-true;", ErrorKind::TypeError);
+    assert_runtime_error_kind("let x = 1; x();", ErrorKind::TypeError);
+    assert_runtime_error_kind(r#""ab" * -1;"#, ErrorKind::ValueError);
+    assert_runtime_error_kind("fn f(a) {} let g = f; g();", ErrorKind::ArityError);
+    assert_runtime_error_kind("assert_ne(1, 1);", ErrorKind::NativeError);
+    // errors raised in callbacks keep their kind
+    assert_runtime_error_kind(
+        "fn f(x) { return -x; } map([true], f);",
+        ErrorKind::TypeError,
+    );
+    assert_runtime_error_kind(
+        "fn f(x) { return x; } filter([1], f);",
+        ErrorKind::NativeError,
+    );
+}
+
+#[test]
+fn arity_error_message() {
+    assert_runtime_error(
+        "fn double(x) { return x * 2; } let f = double; f(1, 2);",
+        "Function 'double' expected 1 argument(s), received 2.",
+    );
+    assert_runtime_error(
+        "let f = assert_eq; f(1);",
+        "Function 'assert_eq' expected 2 argument(s), received 1.",
+    );
+}
+
+#[test]
+#[should_panic]
+fn smoke_assert() {
+    interpret(
+        r#"
+        assert(false);"#,
+    );
+}
+
+#[test]
+fn assert_message() {
+    interpret("let x = 1; assert(x > 0);");
+    assert_runtime_error("let x = -1; assert(x > 0);", "Assertion failed: x > 0");
+    assert_runtime_error(
+        r#"fn f(x) { return x; } assert(!f(true) || "a" == "b" + "c");"#,
+        r#"Assertion failed: !f(true) || ("a" == ("b" + "c"))"#,
+    );
+    assert_runtime_error(
+        r#"assert(1 == 2, "custom message");"#,
+        "Assertion failed: custom message",
+    );
+    // a user defined function named assert does not receive the source of the condition
+    interpret("fn f() { fn assert(x) { return x; } assert(false); } f();");
+}
+
+#[test]
+#[should_panic]
+fn smoke_assert_eq() {
+    interpret(
+        r#"
+        assert_eq(1, 2);"#,
+    );
+}
+
+#[test]
+fn smoke_assert_ne() {
+    interpret(r#"assert_ne(1, 2);"#);
+    interpret(r#"assert_ne("a", 1);"#);
+    assert_runtime_error(
+        r#"assert_ne(1, 1);"#,
+        "Assertion failed: expected values to be different, both are 1.",
+    );
+}
+
+#[test]
+fn smoke_assert_approx_eq() {
+    interpret(r#"assert_approx_eq(0.1 + 0.2, 0.3, 0.0001);"#);
+    interpret(r#"assert_approx_eq(1, 1, 0);"#);
+    assert_runtime_error(
+        r#"assert_approx_eq(1, 1.5, 0.1);"#,
+        "Assertion failed: expected 1 and 1.5 to be equal within 0.1.",
+    );
+    assert_runtime_error(
+        r#"assert_approx_eq("1", 1, 0.1);"#,
+        "Operands must be numbers. Received 1, 1 and 0.1",
+    );
+}
+
+#[test]
+fn smoke_contains_index_of() {
+    interpret(
+        r#"
+        assert(contains("hello", "ell"));
+        assert(contains("hello", ""));
+        assert_eq(contains("hello", "world"), false);
+        assert_eq(index_of("hello", "l"), 2);
+        assert_eq(index_of("hello", "z"), -1);
+        assert_eq(index_of("héllo", "l"), 2);
+        assert(contains([1, "a", true], "a"));
+        assert_eq(contains([1, 2], 3), false);
+        assert_eq(index_of([1, 2, 3], 3), 2);
+        assert_eq(index_of([], 1), -1);"#,
+    );
+    assert_runtime_error(r#"contains(1, "a");"#, "Cannot search in 1");
+    assert_runtime_error(
+        r#"index_of("a", 1);"#,
+        "Can only search for a string in a string. Received 1",
+    );
+}
+
+#[test]
+fn variables() {
+    interpret(
+        r#"
+        let x = 1;
+        assert_eq(x, 1);
+        let y = x + 1;
+        assert_eq(y, 2);
+        assert_eq(y, x + 1);
+        x = 10;
+        assert_eq(x, 10);"#,
+    );
+}
+
+#[test]
+fn comments() {
+    interpret(
+        r#"
+        let x = 1; // a comment
+        assert_eq(x, 1);"#,
+    );
+}
+
+#[test]
+fn empty_program() {
+    interpret("");
+    interpret("// only a comment");
+}
+
+#[test]
+fn empty_stmt() {
+    interpret(";;;");
+    interpret(
+        r#"
+        let x = 1;;
+        ;
+        assert_eq(x, 1);"#,
+    );
+}
+
+#[test]
+fn warnings_do_not_prevent_execution() {
+    interpret(
+        r#"
+        let x = 5;
+        fn never_called() { x(); } // warning: x is not a function
+        let y = 1;
+        assert_eq(y, 1);"#,
+    );
+}
+
+#[test]
+fn report_diagnostics() {
+    use ella::report_diagnostics;
+    use ella_source::{Source, SyntaxError};
+
+    let source: Source = "".into();
+    source
+        .errors
+        .add_error(SyntaxError::warning("a warning", 0..0));
+    let mut output = Vec::new();
+    assert!(report_diagnostics(&source, &mut output).unwrap());
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "WARNING: a warning at position 0\n"
+    );
+
+    source.errors.add_error(SyntaxError::new("an error", 1..2));
+    let mut output = Vec::new();
+    assert!(!report_diagnostics(&source, &mut output).unwrap());
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "WARNING: a warning at position 0\nERROR: an error at position 1\n"
+    );
+}
+
+#[test]
+fn variadic_print() {
+    interpret(
+        r#"
+        print();
+        print("a", "b");
+        println();
+        println("a", 1, true);"#,
+    );
+}
+
+#[test]
+fn is_nan() {
+    interpret(r#"assert(!is_nan(1));"#);
+    interpret(r#"assert(is_nan(0/0));"#);
+}
+
+#[test]
+fn is_inf() {
+    interpret(r#"assert(is_inf(1/0));"#);
+    interpret(r#"assert(is_inf(0 - 1/0));"#);
+    interpret(r#"assert(!is_inf(1));"#);
+    interpret(r#"assert(!is_inf(0/0));"#);
+    interpret(r#"assert(!is_inf("a"));"#);
+}
+
+#[test]
+fn is_finite() {
+    interpret(r#"assert(is_finite(1));"#);
+    interpret(r#"assert(!is_finite(1/0));"#);
+    interpret(r#"assert(!is_finite(0/0));"#);
+    interpret(r#"assert(!is_finite("a"));"#);
+}
+
+#[test]
+fn between() {
+    interpret(r#"assert(between(5, 1, 10));"#);
+    interpret(r#"assert(!between(0, 1, 10));"#);
+    interpret(r#"assert(between(1, 1, 10) && between(10, 1, 10));"#);
+    interpret(r#"assert(!between(0/0, 1, 10));"#);
+    assert_runtime_error(
+        r#"between("5", 1, 10);"#,
+        "Operands must be numbers. Received 5, 1 and 10",
+    );
+}
+
+#[test]
+fn parse_number() {
+    interpret(r#"assert(!is_nan(parse_number("1")));"#);
+    interpret(r#"assert(!is_nan(parse_number("1.2")));"#);
+    interpret(r#"assert(is_nan(parse_number("not a number")));"#);
+    interpret(r#"assert(is_nan(parse_number(1)));"#);
+    interpret(r#"assert_eq(parse_number("-1.5"), -1.5);"#);
+    interpret(r#"assert(!is_nan(parse_number("1e3")));"#);
+    interpret(r#"assert_eq(parse_number("1e3"), 1000);"#);
+    interpret(r#"assert_eq(parse_number("0xff"), 255);"#);
+    interpret(r#"assert(is_nan(parse_number("inf")));"#);
+}
+
+#[test]
+fn parse_bool() {
+    interpret(r#"assert(parse_bool("true"));"#);
+    interpret(r#"assert(!parse_bool("false"));"#);
+    interpret(r#"assert(parse_bool("  TRUE\n"));"#);
+    interpret(r#"assert_eq(parse_bool("False"), false);"#);
+    interpret(r#"assert_eq(typeof(parse_bool("maybe")), "nil");"#);
+    interpret(r#"assert_eq(typeof(parse_bool("")), "nil");"#);
+    interpret(r#"assert_eq(typeof(parse_bool(1)), "nil");"#);
+}
+
+#[test]
+fn number_literals() {
+    interpret(r#"assert_eq(0xff, 255);"#);
+    interpret(r#"assert_eq(1e3, 1000);"#);
+    interpret(r#"assert_eq(2.5E-1, 0.25);"#);
+}
+
+#[test]
+fn equality() {
+    interpret(
+        r#"
+        assert(1 == 1);
+        assert(1 != 2);
+        assert("a" == "a");
+        assert("a" != "b");
+        assert(true != 1);"#,
+    );
+}
+
+#[test]
+fn function_equality() {
+    interpret(
+        r#"
+        let f = print;
+        assert(f == print);
+        assert(f != println);
+
+        fn foo() {}
+        fn bar() {}
+        let g = foo;
+        assert(g == foo);
+        assert(foo == foo);
+        assert(foo != bar);"#,
+    );
+}
+
+#[test]
+fn unary_precedence() {
+    interpret(
+        r#"
+        assert_eq(-2 * 3 + 7, 1);
+        let x = 2;
+        assert_eq(-x + 3, 1);
+        assert(!false == true);"#,
+    );
+}
+
+#[test]
+fn string_repetition() {
+    interpret(
+        r#"
+        assert_eq("ab" * 3, "ababab");
+        assert_eq(3 * "ab", "ababab");
+        assert_eq("ab" * 0, "");"#,
+    );
+    assert_runtime_error(
+        r#""ab" * 1.5;"#,
+        "String repetition count must be a non-negative integer. Received 1.5",
+    );
+    assert_runtime_error(
+        r#""ab" * -1;"#,
+        "String repetition count must be a non-negative integer. Received -1",
+    );
+    assert_runtime_error(
+        r#""a" * 1e18;"#,
+        "String repetition result must not be longer than 1073741824 bytes. Received 1000000000000000000 repetitions of a string of 1 bytes",
+    );
+    interpret(r#"assert_eq("" * 1e18, "");"#);
+}
+
+#[test]
+fn floor_division() {
+    interpret(
+        r#"
+        assert_eq(7 ~/ 2, 3);
+        assert_eq(-7 ~/ 2, -4);
+        assert_eq(7.5 ~/ 0.5, 15);
+        assert_eq(1 + 7 ~/ 2 * 2, 7); // same precedence as /
+        let x = 9;
+        assert_eq(x ~/ 4, 2); // a comment after a floor division
+        assert_eq((x + 1) ~/ 4, 2);
+        // a comment
+        assert_eq(x, 9); // x // 4
+        let elements = [
+            x, // first
+            x ~/ 2 // second
+        ];
+        assert_eq(elements, [9, 4]);
+        assert_eq(
+            x ~/ 2, // four
+            4 // the floor of 4.5
+        );
+        "#,
+    );
+    assert_runtime_error(
+        "1 ~/ true;",
+        "Right operand must be a number. Received true (bool)",
+    );
+}
+
+#[test]
+fn trailing_comments() {
+    interpret(
+        r#"
+        let x = true;
+        let result = 0;
+        if x // note
+        {
+            result = 1;
+        }
+        assert_eq(result, 1);
+
+        let i = 0;
+        let n = 3;
+        while i < n // loop
+        {
+            i += 1;
+        }
+        assert_eq(i, n);
+
+        let elements = [1, 2 // two
+        ];
+        assert_eq(elements, [1, 2]);
+        assert_eq([1, 2] // after ]
+            , elements);
+        assert_eq((n) // after )
+            , 3);
+        "#,
+    );
+}
+
+#[test]
+fn arithmetic_type_errors() {
+    assert_runtime_error(r#""x" - 1;"#, "Left operand must be a number. Received x (string)");
+    assert_runtime_error("1 / true;", "Right operand must be a number. Received true (bool)");
+    assert_runtime_error(
+        r#"[1] < "a";"#,
+        "Operands must be numbers. Received [1] (array) and a (string)",
+    );
+}
+
+#[test]
+fn op_assign() {
+    interpret(
+        r#"
+        let x = 10;
+        assert_eq(x, 10);
+
+        assert_eq(x = 12, 12);// should be new value
+        assert_eq(x += 10, 22);
+        assert_eq(x -= 20, 2);
+        assert_eq(x *= 2, 4);
+        assert_eq(x /= 4, 1);"#,
+    );
+}
+
+#[test]
+fn string_op_assign() {
+    interpret(
+        r#"
+        let s = "";
+        let i = 0;
+        while i < 3 {
+            s += "ab";
+            i += 1;
+        }
+        assert_eq(s, "ababab");
+        assert_eq(s += "!", "ababab!");
+        assert_eq(s, "ababab!");
+
+        let repeated = "xy";
+        repeated *= 2;
+        assert_eq(repeated, "xyxy");
+
+        fn append(suffix) {
+            s += suffix;
+        }
+        append("?");
+        assert_eq(s, "ababab!?");"#,
+    );
+    assert_runtime_error(
+        r#"let s = "a"; s -= "b";"#,
+        "Operands must be numbers. Received a (string) and b (string)",
+    );
+}
+
+#[test]
+fn op_assign_evaluates_rhs_once() {
+    interpret(
+        r#"
+        let calls = 0;
+        fn one() {
+            calls += 1;
+            return 1;
+        }
+        let x = 5;
+        x -= one();
+        x += one();
+        assert_eq(x, 5);
+        assert_eq(calls, 2);
+        assert_eq(x *= { let a = 2; a }, 10);
+        assert_eq(x, 10);"#,
+    );
+}
+
+#[test]
+fn logical_operators() {
+    interpret(
+        r#"
+        assert(true || false);
+        assert(false || true);
+        assert_eq(false || false, false);
+        assert(true && true);
+        assert_eq(true && false, false);
+        assert_eq(false && true, false);
+        assert(true || false && false); // && binds tighter than ||
+        let x = 2;
+        assert(1 < x && x < 3);"#,
+    );
+}
+
+#[test]
+fn logical_operators_short_circuit() {
+    interpret(
+        r#"
+        let calls = 0;
+        fn call(result) {
+            calls += 1;
+            return result;
+        }
+        assert(true || call(false));
+        assert_eq(false && call(true), false);
+        assert_eq(calls, 0);
+        assert(false || call(true));
+        assert(true && call(true));
+        assert_eq(calls, 2);"#,
+    );
+    assert_runtime_error("1 || true;", "Condition must be a boolean.");
+}
+
+#[test]
+fn arrays() {
+    interpret(
+        r#"
+        let empty = [];
+        let array = [1, "two", [3]];
+        assert_eq(array, [1, "two", [3]]);
+        assert_ne(array, [1, "two", 3]);
+        assert_eq(empty, []);
+        let x = 2;
+        assert_eq([x * 2, { let y = x; y }], [4, 2]);"#,
+    );
+}
+
+#[test]
+fn range() {
+    interpret(
+        r#"
+        assert_eq(range(0, 3), [0, 1, 2]);
+        assert_eq(range(0, 10, 2), [0, 2, 4, 6, 8]);
+        assert_eq(range(3, 0, -1), [3, 2, 1]);
+        assert_eq(range(-2, 1), [-2, -1, 0]);
+        assert_eq(range(3, 3), []);
+        assert_eq(range(3, 0), []);
+        assert_eq(range(0, 3, -1), []);
+        assert_eq(range(0, 5, 2), [0, 2, 4]);
+        assert_eq(range(5, 0, -2), [5, 3, 1]);
+        assert_eq(range(-9007199254740991, -9007199254740989), [-9007199254740991, -9007199254740990]);"#,
+    );
+    assert_runtime_error("range(0, 3, 0);", "Range step must not be 0");
+    assert_runtime_error("range(0, 1.5);", "Expected an integer. Received 1.5");
+    assert_runtime_error("range(0, 3, true);", "Expected an integer. Received true");
+    assert_runtime_error(
+        "range(0, 1e12);",
+        "Range must not have more than 16777216 elements. Received 1000000000000 elements",
+    );
+    assert_runtime_error(
+        "range(9007199254740992, 9007199254740993);",
+        "Expected an integer. Received 9007199254740992",
+    );
+}
+
+#[test]
+fn map_filter() {
+    interpret(
+        r#"
+        fn double(x) { return x * 2; }
+        fn is_even(x) {
+            while x > 1 {
+                x -= 2;
+            }
+            return x == 0;
+        }
+        assert_eq(map([1, 2, 3], double), [2, 4, 6]);
+        assert_eq(filter([1, 2, 3, 4], is_even), [2, 4]);
+        assert_eq(map([], double), []);
+        assert_eq(filter([], is_even), []);
+        assert_eq(map(filter([1, 2, 3, 4], is_even), double), [4, 8]);"#,
+    );
+    assert_runtime_error(
+        "fn id(x) { return x; } filter([1, 2], id);",
+        "Predicate must return a boolean. Received 1",
+    );
+    assert_runtime_error("map(1, print);", "Expected an array. Received 1");
+}
+
+#[test]
+fn strings() {
+    interpret(
+        r#"
+        let s = "hello" + " " + "world";
+        assert_eq(s, "hello world");
+        assert_ne(s, "hello");
+        assert_eq(slice(s, 0, 5), "hello");
+        assert_eq(slice(s, 6, 11), "world");
+        assert_eq(slice(s, 3, 3), "");
+        assert_eq(slice(slice(s, 6, 11), 1, 3), "or");
+        assert_eq(slice(s, 0, 5) + "!", "hello!");
+        assert_eq(slice("héllo", 1, 3), "él");
+        assert_eq(slice(s, 6, 11) * 2, "worldworld");
+        assert_eq(index_of(slice(s, 6, 11), "l"), 3);
+        assert_eq("\u{41}", "A");
+        assert_eq("\x41\u{1F600}", "A😀");
+        assert_eq(slice("\"\\\n", 0, 3), "\"\\\n");"#,
+    );
+    assert_runtime_error(
+        r#"slice("abc", 2, 4);"#,
+        "Invalid slice range 2 to 4 for a string of length 3",
+    );
+    assert_runtime_error(
+        r#"slice("abc", 2, 1);"#,
+        "Invalid slice range 2 to 1 for a string of length 3",
+    );
+    assert_runtime_error("slice(1, 0, 0);", "Expected a string. Received 1");
+}
+
+#[test]
+fn string_builder() {
+    interpret(
+        r#"
+        let builder = string_builder();
+        let naive = "";
+        let i = 0;
+        while i < 100 {
+            append(builder, "ab");
+            naive = naive + "ab";
+            i += 1;
+        }
+        assert_eq(to_string(builder), naive);
+
+        let builder = string_builder();
+        assert_eq(to_string(builder), "");
+        append(builder, "x = ");
+        append(builder, 1.5);
+        append(builder, [true]);
+        assert_eq(to_string(builder), "x = 1.5[true]");
+        assert_eq(to_string(2), "2");
+
+        // appending a string builder to itself
+        let builder = string_builder();
+        append(builder, "ab");
+        append(builder, builder);
+        assert_eq(to_string(builder), "abab");
+        append(builder, [builder]);
+        assert_eq(to_string(builder), "abab[abab]");"#,
+    );
+    assert_runtime_error(
+        r#"append("a", "b");"#,
+        "Expected a string builder. Received a",
+    );
+}
+
+#[test]
+fn maps() {
+    interpret(
+        r#"
+        let m = empty_map();
+        assert_eq(len(keys(m)), 0);
+        insert(m, "b", 2);
+        insert(m, "a", 1);
+        insert(m, "c", [3]);
+        insert(m, "a", 4); // replaces the previous value
+        assert_eq(len(keys(m)), 3);
+        assert_eq(len(m), 3);
+        assert_eq(keys(m), ["a", "b", "c"]);
+        assert_eq(values(m), [4, 2, [3]]);
+        assert_eq(get(m, "a"), 4);
+        assert_eq(typeof(get(m, "d")), "nil");
+        assert_eq(to_string(m), "{a: 4, b: 2, c: [3]}");
+        assert_eq(len("héllo"), 5);
+        assert_eq(len([1, 2]), 2);"#,
+    );
+    assert_runtime_error("keys([1]);", "Expected a map. Received [1]");
+    assert_runtime_error(r#"values("a");"#, "Expected a map. Received a");
+    assert_runtime_error(
+        "insert(empty_map(), 1, 2);",
+        "Map keys must be strings. Received 1",
+    );
+    assert_runtime_error("len(1);", "Cannot get the length of 1");
+}
+
+#[test]
+fn fn_name_and_arity() {
+    interpret(
+        r#"
+        fn add(a, b) { return a + b; }
+        assert_eq(fn_name(add), "add");
+        assert_eq(arity(add), 2);
+        fn make_adder(n) {
+            fn adder(x) { return x + n; }
+            return adder;
+        }
+        let add_one = make_adder(1);
+        assert_eq(fn_name(add_one), "adder");
+        assert_eq(arity(add_one), 1);
+        assert_eq(fn_name(println), "println");
+        assert_eq(arity(is_nan), 1);
+        // minimum number of arguments for variadic functions
+        assert_eq(arity(print), 0);
+        assert_eq(arity(readln), 0);"#,
+    );
+    assert_runtime_error("arity(1);", "Expected a function. Received 1");
+    assert_runtime_error(r#"fn_name("f");"#, "Expected a function. Received f");
+}
+
+#[test]
+fn type_of() {
+    interpret(
+        r#"
+        fn f() {}
+        assert_eq(typeof(1), "number");
+        assert_eq(typeof(true), "bool");
+        assert_eq(typeof(f()), "nil");
+        assert_eq(typeof("a"), "string");
+        assert_eq(typeof([1, 2]), "array");
+        assert_eq(typeof(string_builder()), "string builder");
+        assert_eq(typeof(empty_map()), "map");
+        // functions declared with `fn` are always wrapped in a closure at runtime
+        assert_eq(typeof(f), "closure");
+        assert_eq(typeof(println), "native function");"#,
+    );
+    assert_runtime_error(
+        r#""a" + 1;"#,
+        "Operands must be numbers or strings. Received a (string) and 1 (number)",
+    );
+}
+
+#[test]
+fn repl_multi_line_fn() {
+    use ella::builtin_functions::default_builtin_vars;
+    use ella::repl::Repl;
+
+    let builtin_vars = default_builtin_vars();
+    let mut repl = Repl::new(&builtin_vars, false);
+    assert!(repl.eval("fn add(a, b) {\n    assert(a > 0)\n    let sum = a + b;\n    sum\n}\n"));
+    assert!(repl.eval("assert_eq(add(1, 2), 3)\n"));
+}
+
+#[test]
+fn repl_save_load() {
+    use ella::builtin_functions::default_builtin_vars;
+    use ella::repl::Repl;
+    use ella_value::Value;
+
+    let path = std::env::temp_dir().join("ella_repl_save_load.ella");
+    let builtin_vars = default_builtin_vars();
+
+    let mut repl = Repl::new(&builtin_vars, false);
+    assert!(repl.eval("let x = 1;\n"));
+    assert!(!repl.eval("let y = undefined;\n"));
+    assert!(repl.eval("fn double(n) { return n * 2; }\n"));
+    assert!(repl.eval("x = double(x + 1);\n"));
+    assert_eq!(repl.history().len(), 3);
+    repl.save(&path).unwrap();
+
+    let mut repl = Repl::new(&builtin_vars, false);
+    repl.load(&path).unwrap();
+    assert_eq!(repl.vm().get_global("x"), Some(Value::Number(4.0)));
+    assert_eq!(repl.vm().get_global("y"), None);
+    assert!(repl.eval("assert_eq(double(x), 8);"));
+
+    // loading stops at the first failing line
+    std::fs::write(&path, "let a = 1;\nlet b = undefined;\nlet c = 2;\n").unwrap();
+    let mut repl = Repl::new(&builtin_vars, false);
+    let err = repl.load(&path).unwrap_err();
+    assert!(err.to_string().starts_with("Line 2: "));
+    assert!(err.to_string().contains("Cannot resolve symbol undefined"));
+    assert_eq!(repl.vm().get_global("a"), Some(Value::Number(1.0)));
+    assert_eq!(repl.vm().get_global("c"), None);
+
+    std::fs::write(&path, "let a = 1;\nassert(false);\nlet c = 2;\n").unwrap();
+    let mut repl = Repl::new(&builtin_vars, false);
+    let err = repl.load(&path).unwrap_err();
+    assert!(err.to_string().starts_with("Line 2: "));
+    assert!(err.to_string().contains("Assertion failed: false"));
+    assert_eq!(repl.vm().get_global("c"), None);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn block_expressions() {
+    interpret(
+        r#"
+        let x = { let a = 1; a + 1 };
+        assert_eq(x, 2);
+        let y = 5;
+        assert_eq(1 + { let a = y; a * 2 } + { 3 }, 14);
+        assert_eq(y, 5);
+
+        fn f(x) {
+            let before = 1;
+            let result = x + { let a = x; let b = before; a * b } * { let c = 2; c };
+            return result + before;
+        }
+        assert_eq(f(3), 10);
+
+        // captured locals are closed below the value of the block
+        let get = {
+            let captured = 10;
+            fn get() { return captured; }
+            get
+        };
+        assert_eq(get(), 10);"#,
+    );
+}
+
+#[test]
+fn block_expressions_without_value() {
+    interpret(
+        r#"
+        fn nil() {}
+        let counter = 0;
+        let x = { let a = 1; counter = a + 1; };
+        assert_eq(x, nil());
+        assert_eq(counter, 2);
+        assert_eq({}, nil());"#,
+    );
+}
+
+mod functions {
+    use super::*;
+
+    #[test]
+    fn functions() {
+        interpret(
+            r#"
+            fn foo() {
+                return 1;
+            }
+            assert_eq(foo(), 1);"#,
+        );
+    }
+
+    #[test]
+    fn functions_with_params() {
+        interpret(
+            r#"
+            fn double(x) {
+                let result = x * 2;
+                return result;
+            }
+            assert_eq(double(10), 20);
+            assert_eq(double(-2), -4);"#,
+        );
+    }
+
+    #[test]
+    fn functions_implicit_return() {
+        interpret(
+            r#"
+            fn foo() { }
+            assert_ne(foo(), 0);
+            assert_eq(foo(), foo());
+
+            fn bar(x) {
+                if x { return 1; }
+            }
+            assert_eq(bar(true), 1);
+            assert_eq(bar(false), bar(false));"#,
+        );
+    }
+
+    #[test]
+    fn functions_trailing_expression() {
+        interpret(
+            r#"
+            fn double(x) { x * 2 }
+            assert_eq(double(21), 42);
+
+            fn sign(x) {
+                let zero = 0;
+                if x < zero { return -1; }
+                x > zero
+            }
+            assert_eq(sign(-5), -1);
+            assert_eq(sign(5), true);
+            assert_eq(sign(0), false);
+
+            assert_eq(fn(a, b) { a + b }(1, 2), 3);
+            assert_eq(map([1, 2], fn(x) { x + 1 }), [2, 3]);"#,
+        );
+    }
+
+    #[test]
+    fn multiple_return_values() {
+        interpret(
+            r#"
+            fn div_mod(a, b) {
+                let rem = a;
+                let quot = 0;
+                while rem >= b {
+                    rem -= b;
+                    quot += 1;
+                }
+                return (quot, rem);
+            }
+            let (q, r) = div_mod(7, 2);
+            assert_eq(q, 3);
+            assert_eq(r, 1);
+            assert_eq(div_mod(9, 3), (3, 0));
+
+            fn swap(pair) {
+                let (a, b) = pair;
+                return (b, a);
+            }
+            let (x, y) = swap((1, "one"));
+            assert_eq(x, "one");
+            assert_eq(y, 1);
+            assert_eq((1 + 2) * 3, 9);"#,
+        );
+        assert_runtime_error(
+            "let (a, b) = (1, 2, 3);",
+            "Expected a tuple with 2 element(s), received 3",
+        );
+        assert_runtime_error("let (a, b) = 1;", "Cannot destructure 1. Expected a tuple");
+    }
+
+    #[test]
+    fn higher_order_function() {
+        interpret(
+            r#"
+            fn twice(f, v) {
+                return f(f(v));
+            }
+            fn double(x) {
+                return x * 2;
+            }
+            
+            assert_eq(twice(double, 10), 40);
+            assert_eq(twice(double, -2), -8);"#,
+        );
+    }
+
+    #[test]
+    fn nested_returns() {
+        interpret(
+            r#"
+            fn classify(x) {
+                if x < 0 { return "negative"; }
+                while true {
+                    if x == 0 { return "zero"; }
+                    return "positive";
+                }
+            }
+            fn outer(x) {
+                fn inner(y) { return classify(y) + "!"; }
+                return inner(x);
+            }
+            assert_eq(outer(-1), "negative!");
+            assert_eq(outer(0), "zero!");
+            assert_eq(outer(1), "positive!");
+            assert_eq(map([0, 2], classify), ["zero", "positive"]);"#,
+        );
+    }
+
+    #[test]
+    fn locals_in_deep_stack() {
+        // the frame pointers of the innermost calls are greater than 255
+        interpret(
+            r#"
+            fn sum(n) {
+                let a = 0;
+                let b = 0;
+                let c = 0;
+                let local = n;
+                if n == 0 { return 0; }
+                local = local + a + b + c;
+                return local + sum(n - 1);
+            }
+            assert_eq(sum(100), 5050);"#,
+        );
+    }
+
+    #[test]
+    fn function_expressions() {
+        interpret(
+            r#"
+            let x = fn() { return 42; }();
+            assert_eq(x, 42);
+            assert_eq(fn(a, b) { return a + b; }(1, 2), 3);
+
+            let offset = 10;
+            let add_offset = fn(x) { return x + offset; };
+            assert_eq(add_offset(5), 15);
+            assert_eq(map([1, 2], fn(x) { return x * 2; }), [2, 4]);"#,
+        );
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        interpret(
+            r#"
+            fn isEven(n) {
+                if n == 0 { return true; }
+                return isOdd(n - 1);
+            }
+            fn isOdd(n) {
+                if n == 0 { return false; }
+                return isEven(n - 1);
+            }
+            assert_eq(isEven(10), true);
+            assert_eq(isOdd(7), true);
+            assert_eq(isEven(3), false);"#,
+        );
+    }
+
+    mod closures {
+        use super::*;
+
+        #[test]
+        fn close_global_variable() {
+            interpret(
+                r#"
+                fn assert_test() {
+                    assert(true); // captures assert fn from global scope
+                }
+                assert_test();"#,
+            );
+        }
+
+        #[test]
+        fn basic_closures() {
+            interpret(
+                r#"
+                fn closure() {
+                    let x = 1;
+                    fn inner() {
+                        return x + 1;
+                    }
+                    return inner();
+                }
+                assert_eq(closure(), 2);"#,
+            );
+        }
+
+        #[test]
+        fn capture_with_block_stmt() {
+            interpret(
+                r#"
+                {
+                    assert(true);
+                }"#,
+            )
+        }
+
+        #[test]
+        fn close_upvalues() {
+            interpret(
+                r#"
+                fn createAdder(x) {
+                    fn adder(y) {
+                        return x + y;
+                    }
+                    return adder;
+                }
+                let addTwo = createAdder(2);
+                assert_eq(addTwo(1), 3);"#,
+            );
+            interpret(
+                r#"
+                fn compose(f, g) {
+                    fn inner(x) {
+                        return f(g(x));
+                    }
+                    return inner;
+                }
+                fn addOne(x) { return x + 1; }
+                fn addTwo(x) { return x + 2; }
+                let addThree = compose(addOne, addTwo);
+                assert_eq(addThree(2), 5);"#,
+            );
+        }
+
+        #[test]
+        fn capture_block_scoped_global() {
+            interpret(
+                r#"
+                let g = 0;
+                {
+                    let x = 1;
+                    fn get() {
+                        return x;
+                    }
+                    g = get;
+                }
+                let y = 2;
+                assert_eq(g(), 1);"#,
+            );
+        }
+
+        #[test]
+        fn close_captured_fn_declared_in_block() {
+            interpret(
+                r#"
+                fn outer() {
+                    let f = 0;
+                    {
+                        fn rec(n) {
+                            if n == 0 {
+                                return 0;
+                            }
+                            return n + rec(n - 1);
+                        }
+                        f = rec;
+                    }
+                    let other = 100;
+                    return f(3);
+                }
+                assert_eq(outer(), 6);"#,
+            );
+        }
+
+        #[test]
+        fn close_upvalues_at_different_stack_depths() {
+            interpret(
+                r#"
+                fn make_counter() {
+                    let count = 0;
+                    fn counter() {
+                        count += 1;
+                        return count;
+                    }
+                    return counter;
+                }
+                fn run() {
+                    let a = 1;
+                    let b = 2;
+                    let counter = make_counter();
+                    counter();
+                    assert_eq(counter(), 2);
+                }
+                run();"#,
+            );
+        }
+
+        #[test]
+        fn call_result_of_call() {
+            interpret(
+                r#"
+                fn getAdder(x) {
+                    fn adder(y) { return x + y; }
+                    return adder;
+                }
+                assert_eq(getAdder(2)(3), 5);
+                fn getGetAdder() { return getAdder; }
+                assert_eq(getGetAdder()(1)(2), 3);
+                assert_eq({ getAdder(10) }(1), 11);"#,
+            );
+        }
+
+        #[test]
+        fn capture_by_ref() {
+            interpret(
+                r#"
+                let globalSet = 0;
+                let globalGet = 0;
+
+                fn main() {
+                    let a = "initial";
+
+                    fn set() { a = "updated"; }
+                    fn get() { return a; }
+
+                    globalSet = set;
+                    globalGet = get;
+                }
+
+                main();
+                assert_eq(globalGet(), "initial");
+                globalSet();
+                assert_eq(globalGet(), "updated");"#,
+            );
+        }
+
+        #[test]
+        fn nested_upvalues() {
+            interpret(
+                r#"
+                fn outer() {
+                    let x = "value";
+
+                    fn middle() {
+                        fn inner() {
+                            return x;
+                        }
+                        return inner;
+                    }
+                    return middle;
+                }
+
+                let mid = outer();
+                let in = mid();
+                assert_eq(in(), "value");"#,
+            );
+        }
+    }
+}
+
+mod control_flow {
+    use super::*;
+
+    #[test]
+    fn do_while() {
+        interpret(
+            r#"
+            let count = 0;
+            do {
+                count += 1;
+            } while count < 0;
+            assert_eq(count, 1);
+
+            let i = 0;
+            let sum = 0;
+            do {
+                let next = i + 1;
+                sum += next;
+                i = next;
+            } while i < 4;
+            assert_eq(sum, 10);
+
+            fn first_iteration_returns() {
+                do {
+                    return "body";
+                } while false;
+            }
+            assert_eq(first_iteration_returns(), "body");"#,
+        );
+    }
+
+    #[test]
+    fn break_and_continue() {
+        interpret(
+            r#"
+            let i = 0;
+            let sum = 0;
+            while true {
+                i += 1;
+                let next = i;
+                if next > 5 {
+                    break;
+                }
+                if next == 2 {
+                    continue;
+                }
+                sum += next;
+            }
+            assert_eq(i, 6);
+            assert_eq(sum, 13);
+
+            // continue checks the condition of a do-while loop
+            let count = 0;
+            do {
+                count += 1;
+                continue;
+            } while count < 3;
+            assert_eq(count, 3);
+
+            // break and continue only exit the innermost loop
+            let pairs = 0;
+            let a = 0;
+            while a < 3 {
+                a += 1;
+                let b = 0;
+                while true {
+                    b += 1;
+                    if b > a {
+                        break;
+                    }
+                    pairs += 1;
+                }
+            }
+            assert_eq(pairs, 6);
+
+            // captured variables are closed when breaking out of a loop
+            fn last_getter() {
+                let getter = 0;
+                let n = 0;
+                while true {
+                    n += 1;
+                    let captured = n * 10;
+                    fn get() { return captured; }
+                    getter = get;
+                    if n == 2 {
+                        break;
+                    }
+                }
+                return getter;
+            }
+            assert_eq(last_getter()(), 20);"#,
+        );
+    }
+
+    #[test]
+    fn if_and_else() {
+        interpret(
+            r#"
+            let x = 0;
+
+            let condition = true;
+            if condition {
+                x = 1;
+            } else {
+                x = 2;
+            }
+            assert_eq(x, 1);
+
+            condition = false;
+            if condition {
+                x = 1;
+            } else {
+                x = 2;
+            }
+            assert_eq(x, 2);"#,
+        );
+    }
+
+    #[test]
+    fn else_if() {
+        interpret(
+            r#"
+            fn sign(x) {
+                if x < 0 {
+                    return -1;
+                } else if x == 0 {
+                    return 0;
+                } else {
+                    return 1;
+                }
+            }
+            assert_eq(sign(-5), -1);
+            assert_eq(sign(0), 0);
+            assert_eq(sign(5), 1);
+
+            let y = 0;
+            if false { y = 1; } else if false { y = 2; }
+            assert_eq(y, 0);"#,
+        );
+    }
+
+    #[test]
+    fn non_bool_condition() {
+        assert_runtime_error("if 3 {}", "Condition must be a boolean.");
+        assert_runtime_error(r#"if "str" {} else {}"#, "Condition must be a boolean.");
+        assert_runtime_error("while 1 {}", "Condition must be a boolean.");
+        assert_runtime_error("let x = 0; if x = 5 {}", "Condition must be a boolean.");
+    }
+
+    #[test]
+    fn fn_declaration_in_loop_body() {
+        interpret(
+            r#"
+            fn f() {
+                let i = 0;
+                let sum = 0;
+                while i < 3 {
+                    {
+                        fn g() {}
+                    }
+                    let z = i * 10;
+                    sum += z;
+                    i += 1;
+                }
+                return sum;
+            }
+            assert_eq(f(), 30);"#,
+        );
+    }
+
+    #[test]
+    fn while_body_locals() {
+        interpret(
+            r#"
+            fn f() {
+                let i = 0;
+                while i < 3 {
+                    let x = i * 10;
+                    i += 1;
+                }
+                let y = 5;
+                return y;
+            }
+            assert_eq(f(), 5);"#,
+        );
+    }
+
+    #[test]
+    fn fibonacci() {
+        interpret(
+            r#"
+            fn fib(x) {
+                if x <= 1 { return 1; }
+                else { return fib(x - 1) + fib(x - 2); }
+            }
+            
+            assert_eq(fib(20), 10946);"#,
+        );
+    }
+
+    #[test]
+    fn accumulate() {
+        interpret(
+            r#"
+            let x = 0;
+            let i = 0;
+            while i < 20 {
+                x = x + i;
+                i = i + 1;
+            }
+            assert_eq(x, 190);"#,
+        );
+    }
+}
+
+mod vm {
+    use super::*;
+    use ella::builtin_functions::default_builtin_vars;
+    use ella_parser::parser::Parser;
+    use ella_passes::resolve::{Resolver, Symbol};
+    use ella_source::Source;
+    use ella_value::chunk::Chunk;
+    use ella_value::{BuiltinVars, Value};
+    use ella_vm::codegen::Codegen;
+    use ella_vm::vm::{ErrorKind, InterpretResult, Vm};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    /// Creates a VM with the `builtin_vars` loaded. Returns the VM and the symbols of the builtin variables, used to
+    /// resolve the programs run in the VM (see [`run`]).
+    fn builtin_vm(builtin_vars: &BuiltinVars) -> (Vm<'_>, Vec<Rc<RefCell<Symbol>>>) {
+        let dummy_source = "".into();
+        let mut resolver = Resolver::new(&dummy_source);
+        resolver.resolve_builtin_vars(builtin_vars);
+
+        let mut vm = Vm::new(builtin_vars);
+        let mut codegen = Codegen::new("<global>".to_string(), resolver.resolve_result());
+        codegen.codegen_builtin_vars(builtin_vars);
+        vm.interpret(codegen.into_inner_chunk());
+        (vm, resolver.accessible_symbols().clone())
+    }
+
+    /// Parses, resolves and compiles `source` with access to the global `symbols`. Unresolved identifiers are
+    /// late-bound globals. Returns the chunk and the global slots of the program.
+    fn compile(symbols: &[Rc<RefCell<Symbol>>], source: &str) -> (Chunk, HashMap<String, usize>) {
+        let source = source.into();
+        let ast = Parser::new(&source).parse_program();
+        let mut resolver =
+            Resolver::new_with_existing_accessible_symbols(&source, symbols.to_vec());
+        resolver.set_late_bound_globals(true);
+        resolver.resolve_top_level(&ast);
+        assert!(source.has_no_errors());
+
+        let mut codegen = Codegen::new("<global>".to_string(), resolver.resolve_result());
+        codegen.codegen_function(&ast);
+        (codegen.into_inner_chunk(), resolver.global_slots())
+    }
+
+    /// Parses, resolves and compiles `source` without any builtin variables. Codegen asserts that every statement
+    /// leaves the stack balanced. Errors are reported to `source`.
+    fn compile_standalone(source: &Source) -> Chunk {
+        let ast = Parser::new(source).parse_program();
+        let mut resolver = Resolver::new(source);
+        resolver.resolve_top_level(&ast);
+
+        let mut codegen = Codegen::new("<global>".to_string(), resolver.resolve_result());
+        codegen.set_debug_assert_stack_balanced(true);
+        codegen.codegen_function(&ast);
+        codegen.into_inner_chunk()
+    }
+
+    /// Compiles `source` (see [`compile`]) and runs it in the `vm`. The global slots of the `vm` are updated to the
+    /// globals of the program.
+    fn run(vm: &mut Vm, symbols: &[Rc<RefCell<Symbol>>], source: &str) -> InterpretResult {
+        let (chunk, global_slots) = compile(symbols, source);
+        let result = vm.interpret(chunk);
+        vm.set_global_slots(global_slots);
+        result
+    }
+
+    #[test]
+    fn logical_or_disassembly() {
+        let source = "let a = true; let b = a || false;".into();
+        let disassembled = format!("{}", compile_standalone(&source));
+        assert!(source.has_no_errors());
+        assert!(disassembled.contains("jmp_if_true"));
+        assert!(!disassembled.contains("not"));
+    }
+
+    #[test]
+    fn stack_balanced() {
+        let source = r#"
+            let a = 1;
+            fn counter() {
+                let count = 0;
+                fn inc() {
+                    count += 1;
+                    return count;
+                }
+                return inc;
+            }
+            let c = counter();
+            let arr = [a, c(), {
+                let b = 2;
+                b * 3
+            }];
+            let i = 0;
+            while i < 3 && true {
+                let x = i;
+                let (p, q) = (x, i);
+                if x == 1 {
+                    a = a + x;
+                } else if x == 2 || false {
+                    a = -a;
+                } else {
+                    fn unused() {}
+                }
+                i += 1;
+            }
+        "#
+        .into();
+        compile_standalone(&source); // panics if any statement is unbalanced
+        assert!(source.has_no_errors());
+    }
+
+    #[test]
+    fn codegen_error_nodes() {
+        let source = "let x = ; fn f() { return 1 + ; } let y = [x, f(), z]; z = 1; z += 1;".into();
+        let disassembled = format!("{}", compile_standalone(&source));
+        assert!(!source.has_no_errors());
+        assert!(disassembled.contains("error expression"));
+    }
+
+    #[test]
+    fn scope_cleanup_order() {
+        let source = r#"
+            fn f() {
+                {
+                    let captured = 1;
+                    let local = 2;
+                    fn get() { return captured; }
+                }
+            }"#
+        .into();
+        let disassembled = format!("{:#}", compile_standalone(&source));
+        assert!(source.has_no_errors());
+        // `local` is on top of the stack so it must be cleaned up before `captured`.
+        let pop = disassembled.find("pop // cleanup local variable").unwrap();
+        let close = disassembled.find("closeupval").unwrap();
+        assert!(pop < close);
+    }
+
+    #[test]
+    fn rerun_chunk() {
+        let builtin_vars = default_builtin_vars();
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+
+        let source = r#"
+            let x = 0;
+            let i = 0;
+            while i < 20 {
+                x = x + i;
+                i = i + 1;
+            }
+            assert_eq(x, 190);"#;
+        let chunk = Rc::new(compile(&symbols, source).0);
+
+        let initial_stack = vm.stack().clone();
+        let globals_start = initial_stack.len();
+        assert_eq!(vm.interpret(Rc::clone(&chunk)), InterpretResult::Ok);
+        let first_globals = vm.stack()[globals_start..].to_vec();
+        assert_eq!(first_globals, vec![Value::Number(190.0), Value::Number(20.0)]);
+
+        vm.restore_stack(initial_stack);
+        assert_eq!(vm.interpret(Rc::clone(&chunk)), InterpretResult::Ok);
+        assert_eq!(vm.stack()[globals_start..], first_globals[..]);
+    }
+
+    #[test]
+    fn reset() {
+        let builtin_vars = default_builtin_vars();
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+        let builtins_stack = vm.stack().clone();
+
+        let first = r#"
+            let counter = 10;
+            let get = {
+                let captured = counter;
+                fn get() { return captured; }
+                get
+            };
+            late = get();"#;
+        assert_eq!(run(&mut vm, &symbols, first), InterpretResult::Ok);
+        assert_eq!(vm.get_global("late"), Some(Value::Number(10.0)));
+        // a runtime error leaves values on the stack
+        assert!(matches!(
+            run(&mut vm, &symbols, "let x = 1; x();"),
+            InterpretResult::RuntimeError { .. }
+        ));
+
+        vm.reset();
+        assert_eq!(vm.stack(), &builtins_stack);
+        assert_eq!(vm.get_global("counter"), None);
+
+        let second = r#"
+            let result = 1;
+            assert_eq(result, 1);
+            fn late_is_defined() { return late; }"#;
+        assert_eq!(run(&mut vm, &symbols, second), InterpretResult::Ok);
+        assert_eq!(vm.get_global("result"), Some(Value::Number(1.0)));
+        assert_eq!(vm.stack().len(), builtins_stack.len() + 2);
+        assert_eq!(
+            run(&mut vm, &symbols, "late;"),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::UndefinedGlobal,
+                message: "Cannot resolve global late".to_string(),
+                line: 1,
+                ip: 0,
+                chunk_name: "<global>".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn late_bound_globals() {
+        let builtin_vars = default_builtin_vars();
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+
+        let source = r#"
+            fn get_x() { return x; }
+            assert_eq(get_x(), 5);
+            x += 1;
+            y = x;"#;
+        let chunk = Rc::new(compile(&symbols, source).0);
+
+        let initial_stack = vm.stack().clone();
+        assert_eq!(
+            vm.interpret(Rc::clone(&chunk)),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::UndefinedGlobal,
+                message: "Cannot resolve global x".to_string(),
+                line: 2,
+                ip: 0,
+                chunk_name: "get_x".to_string()
+            }
+        );
+
+        vm.restore_stack(initial_stack);
+        vm.set_late_bound_global("x", Value::Number(5.0));
+        assert_eq!(vm.interpret(Rc::clone(&chunk)), InterpretResult::Ok);
+    }
+
+    #[test]
+    fn builtin_constant() {
+        let mut builtin_vars = BuiltinVars::new();
+        builtin_vars.add_constant("PI", Value::Number(std::f64::consts::PI));
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+
+        assert_eq!(
+            run(&mut vm, &symbols, "let tau = PI * 2;"),
+            InterpretResult::Ok
+        );
+        assert_eq!(
+            vm.stack(),
+            &vec![
+                Value::Number(std::f64::consts::PI),
+                Value::Number(std::f64::consts::TAU)
+            ]
+        );
+    }
+
+    #[test]
+    fn get_and_set_global() {
+        let builtin_vars = default_builtin_vars();
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+
+        vm.set_global("input", Value::Number(21.0));
+        assert_eq!(
+            run(&mut vm, &symbols, "let output = input * 2;"),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.get_global("output"), Some(Value::Number(42.0)));
+        assert_eq!(vm.get_global("input"), Some(Value::Number(21.0)));
+        assert_eq!(vm.get_global("missing"), None);
+
+        vm.set_global("output", Value::Bool(true));
+        assert_eq!(vm.get_global("output"), Some(Value::Bool(true)));
+    }
+
+    #[test]
+    fn string_op_assign_refcount() {
+        let builtin_vars = default_builtin_vars();
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+
+        let source = r#"let s = ""; let i = 0; while i < 3 { s += "ab"; i += 1; }"#;
+        assert_eq!(run(&mut vm, &symbols, source), InterpretResult::Ok);
+
+        let s = vm.get_global("s").unwrap();
+        assert_eq!(s.to_string(), "ababab");
+        match &s {
+            // referenced by the VM stack and by `s`
+            Value::Object(obj) => assert_eq!(Rc::strong_count(obj), 2),
+            _ => panic!("expected a string"),
+        }
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        use ella_value::chunk::{Chunk, OpCode};
+        use ella_value::BuiltinVars;
+
+        let builtin_vars = BuiltinVars::new();
+        let mut vm = Vm::new(&builtin_vars);
+
+        let mut chunk = Chunk::new("bad".to_string());
+        chunk.write_chunk(OpCode::Ld0, 1);
+        chunk.write_chunk(0xff, 2);
+        assert_eq!(
+            vm.interpret(chunk),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::InvalidBytecode,
+                message: "Invalid opcode 0xff at offset 1 in chunk bad.".to_string(),
+                line: 2,
+                ip: 1,
+                chunk_name: "bad".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn top_level_return() {
+        use ella_value::chunk::{Chunk, OpCode};
+        use ella_value::BuiltinVars;
+
+        let builtin_vars = BuiltinVars::new();
+        let mut vm = Vm::new(&builtin_vars);
+
+        let mut chunk = Chunk::new("top".to_string());
+        chunk.write_chunk(OpCode::Ld1, 1);
+        chunk.write_chunk(OpCode::Ret, 1);
+        assert_eq!(
+            vm.interpret(chunk),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::InvalidBytecode,
+                message: "Can only use return in a function.".to_string(),
+                line: 1,
+                ip: 1,
+                chunk_name: "top".to_string()
+            }
+        );
+
+        // reaching the end of the top-level chunk completes the execution
+        let mut chunk = Chunk::new("top".to_string());
+        chunk.write_chunk(OpCode::Ld1, 1);
+        assert_eq!(vm.interpret(chunk), InterpretResult::Ok);
+        assert_eq!(vm.stack().last(), Some(&Value::Number(1.0)));
+    }
+
+    #[test]
+    fn runtime_error_location() {
+        // the error is raised by the `add` instruction at offset 2 of the chunk of `f` (`ldloc0`, `ldtrue`, `add`)
+        match try_interpret("fn f(x) { return x + true; }\nf(1);") {
+            InterpretResult::RuntimeError { ip, chunk_name, .. } => {
+                assert_eq!(ip, 2);
+                assert_eq!(chunk_name, "f");
+            }
+            InterpretResult::Ok => panic!("expected runtime error"),
+        }
+    }
+
+    #[test]
+    fn local_index_out_of_bounds() {
+        use ella_value::chunk::{Chunk, OpCode};
+        use ella_value::BuiltinVars;
+
+        let builtin_vars = BuiltinVars::new();
+        let mut vm = Vm::new(&builtin_vars);
+
+        let mut chunk = Chunk::new("top".to_string());
+        chunk.write_chunk(OpCode::Ld1, 1);
+        chunk.write_chunk(OpCode::LdLoc, 1);
+        chunk.write_chunk(5, 1);
+        assert_eq!(
+            vm.interpret(chunk),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::InvalidBytecode,
+                message: "Local variable index 5 is out of bounds.".to_string(),
+                line: 1,
+                ip: 1,
+                chunk_name: "top".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn compile_without_vm() {
+        let builtin_vars = default_builtin_vars();
+        let chunk = ella::compile("let x = 1; x = 2;", &builtin_vars).unwrap();
+        let disassembled = format!("{}", chunk);
+        let instrs: Vec<_> = disassembled
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().nth(2))
+            .collect();
+        assert!(instrs.contains(&"ld1"));
+        assert!(instrs.contains(&"stglobal"));
+
+        let errors = ella::compile("let x = y;", &builtin_vars).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message(), "Cannot resolve symbol y");
+    }
+
+    #[test]
+    fn if_else_disassembly() {
+        let source = "let c = true; if c {} else {}".into();
+        let disassembled = format!("{}", compile_standalone(&source));
+        assert!(source.has_no_errors());
+        let instrs: Vec<_> = disassembled
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().nth(2))
+            .collect();
+        // the condition is popped once at the start of each branch
+        let pos = instrs
+            .iter()
+            .position(|&instr| instr == "jmp_if_false")
+            .unwrap();
+        assert_eq!(instrs[pos + 1], "pop");
+        assert_eq!(instrs[pos + 2], "jmp");
+        assert_eq!(instrs[pos + 3], "pop");
+        assert_eq!(instrs.iter().filter(|&&instr| instr == "pop").count(), 2);
+    }
+
+    #[test]
+    fn nested_if_else_stack_depth() {
+        use ella_value::BuiltinVars;
+
+        // every combination of taken and not taken branches in three nested levels
+        for (a, b, c, expected) in &[
+            (true, true, true, 1),
+            (true, true, false, 2),
+            (true, false, true, 3),
+            (true, false, false, 4),
+            (false, true, true, 5),
+            (false, true, false, 6),
+            (false, false, true, 7),
+            (false, false, false, 8),
+        ] {
+            let source = format!(
+                r#"
+                let a = {};
+                let b = {};
+                let c = {};
+                let x = 0;
+                if a {{
+                    let y = 0;
+                    if b {{
+                        if c {{ x = 1; }} else {{ x = 2; }}
+                    }} else {{
+                        if c {{ x = 3; }} else {{ let z = 4; x = z; }}
+                    }}
+                }} else if b {{
+                    if c {{ x = 5; }} else {{ x = 6; }}
+                }} else if c {{
+                    x = 7;
+                }} else {{
+                    x = 8;
+                }}
+                let result = x;"#,
+                a, b, c
+            )
+            .into();
+            let chunk = compile_standalone(&source);
+            assert!(source.has_no_errors());
+
+            let builtin_vars = BuiltinVars::new();
+            let mut vm = Vm::new(&builtin_vars);
+            assert_eq!(vm.interpret(chunk), InterpretResult::Ok);
+            // a, b, c, x and result
+            assert_eq!(vm.stack().len(), 5);
+            assert_eq!(vm.stack()[4], Value::Number(*expected as f64));
+        }
+    }
+
+    #[test]
+    fn if_without_else_stack_depth() {
+        use ella_value::BuiltinVars;
+
+        for (a, expected) in &[(true, 1), (false, 0)] {
+            let source = format!(
+                "let a = {}; let x = 0; if a {{ x = 1; }} let result = x;",
+                a
+            )
+            .into();
+            let chunk = compile_standalone(&source);
+            assert!(source.has_no_errors());
+
+            let builtin_vars = BuiltinVars::new();
+            let mut vm = Vm::new(&builtin_vars);
+            assert_eq!(vm.interpret(chunk), InterpretResult::Ok);
+            // a, x and result
+            assert_eq!(vm.stack().len(), 3);
+            assert_eq!(vm.stack()[2], Value::Number(*expected as f64));
+        }
+    }
+
+    #[test]
+    fn break_and_continue_stack_depth() {
+        use ella_value::BuiltinVars;
+
+        let source = r#"
+            let i = 0;
+            while i < 10 {
+                let a = i;
+                i += 1;
+                {
+                    let b = a;
+                    if b == 1 { continue; }
+                    if b == 3 { break; }
+                }
+            }
+            let j = 0;
+            do {
+                let c = j;
+                j += 1;
+                if c == 0 { continue; }
+                break;
+            } while true;
+            let result = i + j;"#
+            .into();
+        let chunk = compile_standalone(&source);
+        assert!(source.has_no_errors());
+
+        let builtin_vars = BuiltinVars::new();
+        let mut vm = Vm::new(&builtin_vars);
+        assert_eq!(vm.interpret(chunk), InterpretResult::Ok);
+        // i, j and result
+        assert_eq!(vm.stack().len(), 3);
+        assert_eq!(vm.stack()[2], Value::Number(6.0));
+    }
+
+    #[test]
+    fn append_chunk() {
+        use ella_value::chunk::{Chunk, OpCode};
+        use ella_value::BuiltinVars;
+
+        let mut first = Chunk::new("first".to_string());
+        let constant = first.add_constant(Value::Number(10.0));
+        first.write_chunk(OpCode::Ldc, 0);
+        first.write_chunk(constant, 0);
+
+        let mut second = Chunk::new("second".to_string());
+        second.write_chunk(OpCode::LdFalse, 1);
+        // jump over pop and ldc 99
+        let jump = second.write_chunk(OpCode::JmpIfFalse, 1);
+        second.write_chunk(0, 1);
+        second.write_chunk(0, 1);
+        second.write_chunk(OpCode::Pop, 1);
+        let constant = second.add_constant(Value::Number(99.0));
+        second.write_chunk(OpCode::Ldc, 1);
+        second.write_chunk(constant, 1);
+        second.patch_jump(jump + 1);
+        second.write_chunk(OpCode::Pop, 2);
+        let constant = second.add_constant(Value::Number(20.0));
+        second.write_chunk(OpCode::Ldc, 2);
+        second.write_chunk(constant, 2);
+
+        first.append(&second);
+
+        let builtin_vars = BuiltinVars::new();
+        let mut vm = Vm::new(&builtin_vars);
+        assert_eq!(vm.interpret(first), InterpretResult::Ok);
+        assert_eq!(vm.stack(), &vec![Value::Number(10.0), Value::Number(20.0)]);
+    }
+
+    #[test]
+    fn implicit_return_nil() {
+        use ella_value::BuiltinVars;
+
+        let source = "fn foo() {} let x = foo();".into();
+        let chunk = Rc::new(compile_standalone(&source));
+        assert!(source.has_no_errors());
+
+        let builtin_vars = BuiltinVars::new();
+        let mut vm = Vm::new(&builtin_vars);
+        assert_eq!(vm.interpret(Rc::clone(&chunk)), InterpretResult::Ok);
+        assert_eq!(vm.stack().len(), 2);
+        assert_eq!(vm.stack()[1], Value::Nil);
+
+        // top-level chunk terminated correctly and the vm can be reused
+        vm.restore_stack(Vec::new());
+        assert_eq!(vm.interpret(chunk), InterpretResult::Ok);
+        assert_eq!(vm.stack()[1], Value::Nil);
+    }
+
+    #[test]
+    fn reentrant_native_fn() {
+        use ella_value::object::{CallContext, CallError};
+
+        /// Calls `args[0]` with `args[1]`.
+        fn apply(ctx: &mut dyn CallContext, args: &mut [Value]) -> Result<Value, CallError> {
+            ctx.call_value(args[0].clone(), &args[1..])
+        }
+
+        let mut builtin_vars = default_builtin_vars();
+        builtin_vars.add_reentrant_native_fn("apply", &apply, 2);
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+
+        let initial_stack = vm.stack().clone();
+        assert_eq!(
+            run(
+                &mut vm,
+                &symbols,
+                r#"
+                fn double(x) { return x * 2; }
+                assert_eq(apply(double, 21), 42);
+                fn make_adder(n) {
+                    fn adder(x) { return x + n; }
+                    return adder;
+                }
+                assert_eq(apply(make_adder(2), 3), 5);
+                assert_eq(apply(is_nan, 1), false);
+                // nested re-entrant calls
+                fn apply_double_plus_one(x) { return apply(double, x) + 1; }
+                assert_eq(apply(apply_double_plus_one, 2) * 2, 10);"#
+            ),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.stack().len(), initial_stack.len() + 3);
+
+        vm.restore_stack(initial_stack.clone());
+        assert_eq!(
+            run(&mut vm, &symbols, "fn not(x) { return !x; } apply(not, 1);"),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::TypeError,
+                message: "Operand must be a boolean.".to_string(),
+                line: 1,
+                ip: 7,
+                chunk_name: "<global>".to_string()
+            }
+        );
+        vm.restore_stack(initial_stack);
+        assert_eq!(
+            run(
+                &mut vm,
+                &symbols,
+                "fn not(x) { return !x; } assert(apply(not, false));"
+            ),
+            InterpretResult::Ok
+        );
+    }
+
+    #[test]
+    fn stack_underflow() {
+        use ella_value::chunk::{Chunk, OpCode};
+        use ella_value::BuiltinVars;
+
+        let builtin_vars = BuiltinVars::new();
+        let mut vm = Vm::new(&builtin_vars);
+
+        let mut chunk = Chunk::new("bad".to_string());
+        chunk.write_chunk(OpCode::Pop, 1);
+        assert_eq!(
+            vm.interpret(chunk),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::InvalidBytecode,
+                message: "Stack underflow.".to_string(),
+                line: 1,
+                ip: 0,
+                chunk_name: "bad".to_string()
+            }
+        );
+
+        let mut chunk = Chunk::new("bad".to_string());
+        chunk.write_chunk(OpCode::Ld1, 1);
+        chunk.write_chunk(OpCode::Add, 2);
+        assert_eq!(
+            vm.interpret(chunk),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::InvalidBytecode,
+                message: "Stack underflow.".to_string(),
+                line: 2,
+                ip: 1,
+                chunk_name: "bad".to_string()
+            }
+        );
+
+        let mut chunk = Chunk::new("bad".to_string());
+        chunk.write_chunk(OpCode::CloseUpVal, 1);
+        assert_eq!(
+            vm.interpret(chunk),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::InvalidBytecode,
+                message: "Stack underflow.".to_string(),
+                line: 1,
+                ip: 0,
+                chunk_name: "bad".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn trace_hook() {
+        use ella_value::chunk::{Chunk, OpCode};
+        use ella_value::BuiltinVars;
+
+        let builtin_vars = BuiltinVars::new();
+        let mut trace = Vec::new();
+        let mut vm = Vm::new(&builtin_vars);
+        vm.set_trace_hook(|opcode, ip, line| trace.push((opcode, ip, line)));
+
+        let mut chunk = Chunk::new("trace".to_string());
+        chunk.write_chunk(OpCode::Ld1, 1);
+        chunk.write_chunk(OpCode::Ldc, 1);
+        let constant = chunk.add_constant(Value::Number(2.0));
+        chunk.write_chunk(constant, 1);
+        chunk.write_chunk(OpCode::Add, 2);
+        chunk.write_chunk(OpCode::Pop, 3);
+        assert_eq!(vm.interpret(chunk), InterpretResult::Ok);
+        drop(vm);
+
+        assert_eq!(trace.len(), 4);
+        assert_eq!(
+            trace,
+            vec![
+                (OpCode::Ld1, 0, 1),
+                (OpCode::Ldc, 1, 1),
+                (OpCode::Add, 3, 2),
+                (OpCode::Pop, 4, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_coverage() {
+        let builtin_vars = default_builtin_vars();
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+
+        let source = r#"let x = 1;
+if (x > 0) {
+    x = 2;
+} else {
+    x = 3;
+}
+fn get_x() {
+    return x;
+}
+assert_eq(get_x(), 2);"#;
+        let (result, lines) = vm.line_coverage(compile(&symbols, source).0);
+        assert_eq!(result, InterpretResult::Ok);
+        let mut lines: Vec<_> = lines.into_iter().collect();
+        lines.sort_unstable();
+        // the else branch (line 5) is never executed
+        assert_eq!(lines, vec![1, 2, 3, 7, 8, 10]);
+    }
+
+    #[test]
+    fn opcode_hook() {
+        use ella_value::chunk::OpCode;
+
+        let builtin_vars = default_builtin_vars();
+        let source = "fn add(a, b) { a + b } fn one() { 1 } add(one(), 2);";
+
+        let mut arities = Vec::new();
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+        vm.set_opcode_hook(OpCode::Calli, |operands, _stack| arities.push(operands[0]));
+        assert_eq!(run(&mut vm, &symbols, source), InterpretResult::Ok);
+        drop(vm);
+        assert_eq!(arities, vec![0, 2]);
+
+        // the values on the stack can be modified
+        let (mut vm, symbols) = builtin_vm(&builtin_vars);
+        vm.set_opcode_hook(OpCode::Add, |_operands, stack| {
+            *stack.last_mut().unwrap() = Value::Number(40.0)
+        });
+        assert_eq!(
+            run(&mut vm, &symbols, "let x = 1 + 2;"),
+            InterpretResult::Ok
+        );
+        assert_eq!(vm.get_global("x"), Some(Value::Number(41.0)));
+
+        // but the depth of the stack cannot be changed
+        vm.set_opcode_hook(OpCode::Add, |_operands, stack| {
+            stack.pop();
+        });
+        assert_eq!(
+            run(&mut vm, &symbols, "1 + 2;"),
+            InterpretResult::RuntimeError {
+                kind: ErrorKind::NativeError,
+                message: "Opcode hook for Add must not change the depth of the stack.".to_string(),
+                line: 1,
+                ip: 10,
+                chunk_name: "<global>".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn string_concat_folding() {
+        use ella_value::chunk::OpCode;
+
+        let compile = |source: &str| {
+            let source = source.into();
+            let chunk = compile_standalone(&source);
+            assert!(source.has_no_errors());
+            chunk
+        };
+
+        let chunk = compile(r#""a" + "b";"#);
+        assert_eq!(chunk.code, vec![OpCode::Ldc as u8, 0, OpCode::Pop as u8]);
+        assert_eq!(chunk.constants, vec![Value::from("ab")]);
+
+        let chunk = compile(r#""a" + "b" + "c";"#);
+        assert_eq!(chunk.constants, vec![Value::from("abc")]);
+
+        // mixed operands are not folded
+        let chunk = compile(r#""a" + 1;"#);
+        assert!(chunk.code.contains(&(OpCode::Add as u8)));
+    }
+
+    #[test]
+    fn op_assign_disassembly() {
+        let source = "fn f(x) { x += 2; }".into();
+        let disassembled = format!("{:#}", compile_standalone(&source));
+        assert!(source.has_no_errors());
+        let instrs: Vec<_> = disassembled
+            .lines()
+            .skip_while(|line| !line.contains("== f (line 1) =="))
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().nth(2))
+            .collect();
+        // the variable is loaded and stored exactly once
+        assert_eq!(instrs, vec!["ldloc0", "ldf64", "add", "stloc0", "pop", "ret_nil"]);
+    }
+
+    #[test]
+    fn nested_function_declaration_line() {
+        let source = r#"
+            fn outer() {
+                let x = 1;
+
+                fn inner() {
+                    return x;
+                }
+                return inner;
+            }"#
+        .into();
+        let chunk = compile_standalone(&source);
+        assert!(source.has_no_errors());
+        assert_eq!(chunk.declaration_line, None);
+        let disassembled = format!("{:#}", chunk);
+        assert!(disassembled.starts_with("== <global> ==\n"));
+        assert!(disassembled.contains("== outer (line 2) =="));
+        assert!(disassembled.contains("== inner (line 5) =="));
+    }
+
+    #[test]
+    fn specialized_local_opcodes() {
+        let source = r#"
+            fn locals(a, b) {
+                let c = a + b;
+                let d = c;
+                let e = d;
+                a = e;
+                return a + e;
+            }"#;
+
+        let ast_source = source.into();
+        let disassembled = format!("{:#}", compile_standalone(&ast_source));
+        assert!(ast_source.has_no_errors());
+        assert!(disassembled.contains("ldloc0"));
+        assert!(disassembled.contains("ldloc3"));
+        assert!(disassembled.contains("stloc0"));
+        assert!(disassembled.contains("ldloc ")); // e has offset 4
+
+        interpret(&format!("{}\nassert_eq(locals(1, 2), 6);", source));
+    }
+}